@@ -149,4 +149,176 @@ INSERT INTO t_sys_config (`key`, value, default_value) VALUES('WEB_HOOK_TOKEN',
 alter table t_memo add column `source` TEXT default  'web';
 
 -- changeset jerry:6
-INSERT INTO t_sys_config (`key`, value, default_value) VALUES('PUSH_OFFICIAL_SQUARE', '', 'false');
\ No newline at end of file
+INSERT INTO t_sys_config (`key`, value, default_value) VALUES('PUSH_OFFICIAL_SQUARE', '', 'false');
+
+-- changeset jerry:7
+alter table t_comment add column like_count int default 0;
+CREATE TABLE `t_comment_relation`
+(
+    `id`         INTEGER PRIMARY KEY        NOT NULL,
+    `comment_id` int       NOT NULL,
+    `user_id`    int       NOT NULL,
+    `fav_type`   TEXT      NOT NULL DEFAULT 'LIKE',
+    `created`    timestamp NULL     default(datetime(CURRENT_TIMESTAMP,'localtime'))
+);
+CREATE UNIQUE INDEX `t_comment_relation_IDX` ON `t_comment_relation` (`comment_id`,`user_id`,`fav_type`);
+
+-- changeset jerry:8
+CREATE TABLE `t_webhook_outbox`
+(
+    `id`         INTEGER PRIMARY KEY        NOT NULL,
+    `memo_id`    int       NOT NULL,
+    `status`     TEXT      NOT NULL DEFAULT 'PENDING',
+    `attempts`   int       NOT NULL DEFAULT 0,
+    `last_error` TEXT           DEFAULT NULL,
+    `created`    timestamp NULL     default(datetime(CURRENT_TIMESTAMP,'localtime')),
+    `updated`    timestamp NULL
+);
+CREATE INDEX `t_webhook_outbox_status_IDX` ON `t_webhook_outbox` (`status`);
+
+-- changeset jerry:9
+INSERT INTO t_sys_config (`key`, value, default_value) VALUES('THUMBNAIL_URL_TEMPLATE', '', '');
+
+-- changeset jerry:10
+INSERT INTO t_sys_config (`key`, value, default_value) VALUES('WEB_HOOK_CONTENT_FORMAT', '', 'raw');
+
+-- changeset jerry:11
+UPDATE t_tag SET memo_count = (SELECT sum(coalesce(d.memo_count, 0)) FROM t_tag d WHERE d.user_id IS t_tag.user_id AND d.name = t_tag.name) WHERE id IN (SELECT min(id) FROM t_tag GROUP BY user_id, name HAVING count(1) > 1);
+DELETE FROM t_tag WHERE id NOT IN (SELECT min(id) FROM t_tag GROUP BY user_id, name);
+CREATE UNIQUE INDEX IF NOT EXISTS `t_tag_tag_IDX` ON `t_tag` (`user_id`,`name`);
+
+-- changeset jerry:12
+alter table t_user add column last_checked_comments timestamp NULL DEFAULT NULL;
+
+-- changeset jerry:13
+INSERT INTO t_sys_config (`key`, value, default_value) VALUES('READ_ONLY_MODE', '', 'false');
+
+-- changeset jerry:14
+INSERT INTO t_sys_config (`key`, value, default_value) VALUES('NOTIFY_ON_PENDING_COMMENT', '', 'false');
+
+-- changeset jerry:15
+INSERT INTO t_sys_config (`key`, value, default_value) VALUES('MAX_BLANK_LINES', '', '2');
+
+-- changeset jerry:16
+INSERT INTO t_sys_config (`key`, value, default_value) VALUES('MEMO_MAX_RESOURCES', '', '0');
+
+-- changeset jerry:17
+INSERT INTO t_sys_config (`key`, value, default_value) VALUES('REGISTER_MODE', '', '');
+INSERT INTO t_sys_config (`key`, value, default_value) VALUES('INVITE_CODE', '', '');
+CREATE TABLE `t_invite_code`
+(
+    `id`         INTEGER PRIMARY KEY        NOT NULL,
+    `code`       TEXT      NOT NULL,
+    `created_by` int       NOT NULL,
+    `used_by`    int            DEFAULT NULL,
+    `used_at`    timestamp NULL DEFAULT NULL,
+    `created`    timestamp NULL default(datetime(CURRENT_TIMESTAMP,'localtime'))
+);
+CREATE UNIQUE INDEX `t_invite_code_code_IDX` ON `t_invite_code` (`code`);
+
+-- changeset jerry:18
+INSERT INTO t_sys_config (`key`, value, default_value) VALUES('MEMO_MAX_PER_USER', '', '0');
+CREATE INDEX IF NOT EXISTS `t_memo_user_status_IDX` ON `t_memo` (`user_id`,`status`);
+
+-- changeset jerry:19
+INSERT INTO t_sys_config (`key`, value, default_value) VALUES('VIEW_COUNT_MODE', '', 'inline');
+INSERT INTO t_sys_config (`key`, value, default_value) VALUES('VIEW_EVENT_CURSOR', '', '0');
+CREATE TABLE `t_memo_view`
+(
+    `id`          INTEGER PRIMARY KEY        NOT NULL,
+    `memo_id`     int       NOT NULL,
+    `viewer_hash` TEXT      NOT NULL,
+    `created`     timestamp NULL     default(datetime(CURRENT_TIMESTAMP,'localtime'))
+);
+CREATE INDEX `t_memo_view_memo_IDX` ON `t_memo_view` (`memo_id`);
+
+-- changeset jerry:20
+alter table t_memo add column `title` TEXT DEFAULT NULL;
+INSERT INTO t_sys_config (`key`, value, default_value) VALUES('MEMO_FIRST_LINE_MODE', '', 'tags');
+
+-- changeset jerry:21
+alter table t_resource add column `alt` TEXT DEFAULT NULL;
+
+-- changeset jerry:22
+INSERT INTO t_sys_config (`key`, value, default_value) VALUES('MAX_SESSIONS_PER_USER', '', '0');
+CREATE TABLE `t_session`
+(
+    `id`        INTEGER PRIMARY KEY        NOT NULL,
+    `jti`       TEXT      NOT NULL,
+    `user_id`   int       NOT NULL,
+    `device`    TEXT      NOT NULL DEFAULT 'WEB',
+    `revoked`   int       NOT NULL DEFAULT 0,
+    `created`   timestamp NULL     default(datetime(CURRENT_TIMESTAMP,'localtime')),
+    `last_seen` timestamp NULL
+);
+CREATE UNIQUE INDEX `t_session_jti_IDX` ON `t_session` (`jti`);
+CREATE INDEX `t_session_user_IDX` ON `t_session` (`user_id`,`revoked`);
+
+-- changeset jerry:23
+INSERT INTO t_sys_config (`key`, value, default_value) VALUES('RESERVED_USERNAMES', '', 'admin,administrator,root,system,support,api,mblog,official,moderator');
+
+-- changeset jerry:24
+INSERT INTO t_sys_config (`key`, value, default_value) VALUES('RSS_DESCRIPTION_FORMAT', '', 'raw');
+
+-- changeset jerry:25
+INSERT INTO t_sys_config (`key`, value, default_value) VALUES('MAX_PAGE_OFFSET', '', '10000');
+
+-- changeset jerry:26
+INSERT INTO t_sys_config (`key`, value, default_value) VALUES('RSS_ENABLED', '', 'true');
+
+-- changeset jerry:27
+DELETE FROM t_user_memo_relation WHERE id NOT IN (SELECT min(id) FROM t_user_memo_relation GROUP BY memo_id, user_id, fav_type);
+CREATE UNIQUE INDEX `t_user_memo_relation_IDX` ON `t_user_memo_relation` (`memo_id`,`user_id`,`fav_type`);
+UPDATE t_memo SET like_count = (SELECT count(1) FROM t_user_memo_relation r WHERE r.memo_id = t_memo.id);
+
+-- changeset jerry:28
+alter table t_memo add column `content_format` TEXT DEFAULT NULL;
+
+-- changeset jerry:29
+INSERT INTO t_sys_config (`key`, value, default_value) VALUES('ANONYMOUS_LIKE', '', 'false');
+
+-- changeset jerry:30
+INSERT INTO t_sys_config (`key`, value, default_value) VALUES('ALLOW_STORAGE_OVERRIDE', '', 'false');
+
+-- changeset jerry:31
+INSERT INTO t_sys_config (`key`, value, default_value) VALUES('DAILY_DIGEST', '', 'false');
+INSERT INTO t_sys_config (`key`, value, default_value) VALUES('DAILY_DIGEST_HOUR', '', '8');
+INSERT INTO t_sys_config (`key`, value, default_value) VALUES('DAILY_DIGEST_LAST_DATE', '', '');
+
+-- changeset jerry:32
+alter table t_memo add column `mentioned_user_id` TEXT DEFAULT NULL;
+
+-- changeset jerry:33
+alter table t_memo add column `source_title` TEXT DEFAULT NULL;
+alter table t_memo add column `source_image` TEXT DEFAULT NULL;
+INSERT INTO t_sys_config (`key`, value, default_value) VALUES('UNFURL_SOURCE', '', 'false');
+INSERT INTO t_sys_config (`key`, value, default_value) VALUES('UNFURL_ALLOWED_HOSTS', '', '');
+INSERT INTO t_sys_config (`key`, value, default_value) VALUES('UNFURL_DENIED_HOSTS', '', '');
+
+-- changeset jerry:34
+INSERT INTO t_sys_config (`key`, value, default_value) VALUES('AUTO_APPROVE_KNOWN_EMAIL', '', 'false');
+
+-- changeset jerry:35
+INSERT INTO t_sys_config (`key`, value, default_value) VALUES('PROTECT_SCOPE', '', 'logged_in');
+
+-- changeset jerry:36
+INSERT INTO t_sys_config (`key`, value, default_value) VALUES('DEFAULT_ENABLE_COMMENT', '', 'false');
+
+-- changeset jerry:37
+INSERT INTO t_sys_config (`key`, value, default_value) VALUES('MEMO_MIN_LENGTH', '', '0');
+INSERT INTO t_sys_config (`key`, value, default_value) VALUES('MEMO_MAX_CONTENT_LENGTH', '', '0');
+
+-- changeset jerry:38
+INSERT INTO t_sys_config (`key`, value, default_value) VALUES('STRIP_TAGS_FROM_CONTENT', '', 'true');
+
+-- changeset jerry:39
+INSERT INTO t_sys_config (`key`, value, default_value) VALUES('MAX_UPLOAD_SIZE', '', '0');
+
+-- changeset jerry:40
+INSERT INTO t_sys_config (`key`, value, default_value) VALUES('ALLOWED_UPLOAD_SUFFIXES', '', '');
+
+-- changeset jerry:41
+INSERT INTO t_sys_config (`key`, value, default_value) VALUES('DEDUP_UPLOADS', '', 'true');
+
+-- changeset jerry:42
+alter table t_dev_token add column last_used timestamp NULL DEFAULT NULL;
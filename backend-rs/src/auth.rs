@@ -1,11 +1,19 @@
+use std::sync::atomic::{AtomicI32, Ordering};
+
 use actix_web::{dev::Payload, web, FromRequest, HttpRequest};
+use chrono::{Duration, Utc};
 use futures_util::future::LocalBoxFuture;
-use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
-use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
+use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use log::{error, warn};
+use sea_orm::sea_query::Expr;
+use sea_orm::{ColumnTrait, Condition, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder, QuerySelect};
+use serde::Serialize;
 
 use crate::config::AppConfig;
 use crate::entity::{dev_token, user};
 use crate::error::AppError;
+use crate::rate_limit;
+use crate::session;
 
 #[derive(Clone, Debug)]
 pub struct AuthUser {
@@ -13,8 +21,17 @@ pub struct AuthUser {
     pub role: Option<String>,
     #[allow(dead_code)]
     pub device: String,
+    /// Set for WEB logins that carry a `jti`; older tokens without one have no session row.
+    pub session_id: Option<i32>,
+    /// The `t_dev_token` row behind an `API` device token.
+    pub dev_token_id: Option<i32>,
 }
 
+static PRIMARY_ADMIN_ID: AtomicI32 = AtomicI32::new(0);
+
+/// `t_dev_token.last_used` is only rewritten when older than this, so API calls stay reads.
+const DEV_TOKEN_LAST_USED_RESOLUTION_SECS: i64 = 60;
+
 #[derive(Clone, Debug)]
 pub struct OptionalAuthUser(pub Option<AuthUser>);
 
@@ -36,10 +53,12 @@ impl FromRequest for AuthUser {
             }
         };
         let token = extract_token(req, &config);
+        let req = req.clone();
 
         Box::pin(async move {
-            let token = token.ok_or_else(|| AppError::need_login())?;
+            let token = token.ok_or_else(AppError::need_login)?;
             let auth = authenticate_token(&db, &config, &token).await?;
+            rate_limit::charge(&req, &config, &auth)?;
             Ok(auth)
         })
     }
@@ -63,10 +82,14 @@ impl FromRequest for OptionalAuthUser {
             }
         };
         let token = extract_token(req, &config);
+        let req = req.clone();
 
         Box::pin(async move {
             if let Some(token) = token {
                 let auth = authenticate_token(&db, &config, &token).await.ok();
+                if let Some(auth) = auth.as_ref() {
+                    rate_limit::charge(&req, &config, auth)?;
+                }
                 return Ok(OptionalAuthUser(auth));
             }
             Ok(OptionalAuthUser(None))
@@ -74,6 +97,53 @@ impl FromRequest for OptionalAuthUser {
     }
 }
 
+/// Resolves the site owner used for anonymous views: the ADMIN with the lowest id, so the
+/// choice stays stable when several admins exist. Users are never deleted or demoted, so the id
+/// is cached after the first lookup and later calls don't touch the database.
+pub async fn primary_admin_id(db: &DatabaseConnection) -> Result<i32, AppError> {
+    let cached = PRIMARY_ADMIN_ID.load(Ordering::Relaxed);
+    if cached > 0 {
+        return Ok(cached);
+    }
+    let id = user::Entity::find()
+        .select_only()
+        .column(user::Column::Id)
+        .filter(user::Column::Role.eq("ADMIN"))
+        .order_by_asc(user::Column::Id)
+        .into_tuple::<i32>()
+        .one(db)
+        .await
+        .map_err(|_| AppError::system_exception())?
+        .ok_or_else(|| AppError::fail("管理员不存在"))?;
+    PRIMARY_ADMIN_ID.store(id, Ordering::Relaxed);
+    Ok(id)
+}
+
+/// Forgets the cached [`primary_admin_id`] so the next call queries again.
+#[cfg(test)]
+pub(crate) fn reset_primary_admin_cache() {
+    PRIMARY_ADMIN_ID.store(0, Ordering::Relaxed);
+}
+
+/// The [`primary_admin_id`] user, or None on a site without an admin yet.
+pub async fn find_primary_admin(db: &DatabaseConnection) -> Result<Option<user::Model>, AppError> {
+    let id = match primary_admin_id(db).await {
+        Ok(id) => id,
+        Err(e) if e.code() == AppError::system_exception().code() => return Err(e),
+        Err(_) => return Ok(None),
+    };
+    user::Entity::find_by_id(id)
+        .one(db)
+        .await
+        .map_err(|_| AppError::system_exception())
+}
+
+pub async fn primary_admin(db: &DatabaseConnection) -> Result<user::Model, AppError> {
+    find_primary_admin(db)
+        .await?
+        .ok_or_else(|| AppError::fail("管理员不存在"))
+}
+
 fn extract_token(req: &HttpRequest, config: &AppConfig) -> Option<String> {
     let header = config.token_header.as_str();
     req.headers()
@@ -97,26 +167,120 @@ async fn authenticate_token(
         .and_then(|u| u.role);
 
     let device = extract_device(&decoded).unwrap_or_else(|| "WEB".to_string());
+    let mut dev_token_id = None;
     if device == "API" {
-        let exists = dev_token::Entity::find()
+        // 只查 id,即使 last_used 列缺失也不影响鉴权
+        let id = dev_token::Entity::find()
+            .select_only()
+            .column(dev_token::Column::Id)
             .filter(dev_token::Column::Token.eq(token))
             .filter(dev_token::Column::UserId.eq(user_id))
+            .into_tuple::<i32>()
             .one(db)
             .await
             .map_err(|_| AppError::system_exception())?
-            .is_some();
-        if !exists {
-            return Err(AppError::api_token_invalid());
+            .ok_or_else(AppError::api_token_invalid)?;
+        touch_dev_token(db, id).await;
+        dev_token_id = Some(id);
+    }
+
+    let mut session_id = None;
+    if let Some(jti) = decoded.get("jti").and_then(|v| v.as_str()) {
+        session_id = session::validate(db, user_id, jti).await?;
+        if session_id.is_none() {
+            return Err(AppError::need_login());
         }
     }
 
-    Ok(AuthUser { user_id, role, device })
+    Ok(AuthUser { user_id, role, device, session_id, dev_token_id })
+}
+
+/// Records when a dev token was last used, at most once per [`DEV_TOKEN_LAST_USED_RESOLUTION_SECS`]
+/// (the update matches no row otherwise). Failures are only logged so a missing column never
+/// blocks API access.
+async fn touch_dev_token(db: &DatabaseConnection, id: i32) {
+    let now = Utc::now();
+    let stale = now - Duration::seconds(DEV_TOKEN_LAST_USED_RESOLUTION_SECS);
+    let result = dev_token::Entity::update_many()
+        .col_expr(dev_token::Column::LastUsed, Expr::value(now))
+        .filter(dev_token::Column::Id.eq(id))
+        .filter(
+            Condition::any()
+                .add(dev_token::Column::LastUsed.is_null())
+                .add(dev_token::Column::LastUsed.lt(stale)),
+        )
+        .exec(db)
+        .await;
+    if let Err(e) = result {
+        warn!("update last_used of dev token {} failed: {}", id, e);
+    }
+}
+
+/// Signs `claims` with the configured algorithm: the shared secret for HS256, the PEM
+/// private key for RS256.
+pub fn encode_jwt<T: Serialize>(config: &AppConfig, claims: &T) -> Result<String, AppError> {
+    let key = match config.jwt_algorithm {
+        Algorithm::RS256 => {
+            let pem = config.jwt_private_key.as_deref().unwrap_or_default();
+            EncodingKey::from_rsa_pem(pem.as_bytes()).map_err(|e| {
+                error!("invalid JWT private key: {}", e);
+                AppError::system_exception()
+            })?
+        }
+        _ => EncodingKey::from_secret(config.jwt_secret.as_bytes()),
+    };
+    encode(&Header::new(config.jwt_algorithm), claims, &key).map_err(|_| AppError::system_exception())
+}
+
+/// `exp` (unix seconds) for a login or dev token signed now, `TOKEN_TTL_DAYS` ahead.
+pub fn token_exp(config: &AppConfig) -> usize {
+    (Utc::now() + Duration::days(config.token_ttl_days)).timestamp() as usize
+}
+
+#[derive(Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ShareClaims {
+    purpose: String,
+    memo_id: i32,
+    exp: usize,
+}
+
+const SHARE_PURPOSE: &str = "share";
+
+/// Signs a token that grants read access to a single memo until `exp` (unix seconds).
+pub fn encode_share_token(config: &AppConfig, memo_id: i32, exp: usize) -> Result<String, AppError> {
+    let claims = ShareClaims {
+        purpose: SHARE_PURPOSE.to_string(),
+        memo_id,
+        exp,
+    };
+    encode_jwt(config, &claims)
+}
+
+/// Returns the memo id a share token grants access to, or None if it is invalid or expired.
+pub fn verify_share_token(config: &AppConfig, token: &str) -> Option<i32> {
+    let mut validation = Validation::new(config.jwt_algorithm);
+    validation.set_required_spec_claims(&["exp"]);
+    let key = match config.jwt_algorithm {
+        Algorithm::RS256 => DecodingKey::from_rsa_pem(config.jwt_public_key.as_deref()?.as_bytes()).ok()?,
+        _ => DecodingKey::from_secret(config.jwt_secret.as_bytes()),
+    };
+    let claims = decode::<ShareClaims>(token, &key, &validation).ok()?.claims;
+    (claims.purpose == SHARE_PURPOSE).then_some(claims.memo_id)
 }
 
 fn decode_jwt(config: &AppConfig, token: &str) -> Result<serde_json::Value, AppError> {
-    let key = DecodingKey::from_secret(config.jwt_secret.as_bytes());
-    let mut validation = Validation::new(Algorithm::HS256);
-    validation.validate_exp = false;
+    let key = match config.jwt_algorithm {
+        Algorithm::RS256 => {
+            let pem = config.jwt_public_key.as_deref().unwrap_or_default();
+            DecodingKey::from_rsa_pem(pem.as_bytes()).map_err(|e| {
+                error!("invalid JWT public key: {}", e);
+                AppError::system_exception()
+            })?
+        }
+        _ => DecodingKey::from_secret(config.jwt_secret.as_bytes()),
+    };
+    let validation = Validation::new(config.jwt_algorithm);
     decode::<serde_json::Value>(token, &key, &validation)
         .map(|data| data.claims)
         .map_err(|_| AppError::need_login())
@@ -128,10 +292,10 @@ fn extract_user_id(claims: &serde_json::Value) -> Option<i32> {
             if let Some(id) = value.as_i64() {
                 return Some(id as i32);
             }
-            if let Some(s) = value.as_str() {
-                if let Ok(id) = s.parse::<i32>() {
-                    return Some(id);
-                }
+            if let Some(s) = value.as_str()
+                && let Ok(id) = s.parse::<i32>()
+            {
+                return Some(id);
             }
         }
     }
@@ -140,11 +304,34 @@ fn extract_user_id(claims: &serde_json::Value) -> Option<i32> {
 
 fn extract_device(claims: &serde_json::Value) -> Option<String> {
     for key in ["device", "loginType", "login_type", "deviceType"] {
-        if let Some(value) = claims.get(key) {
-            if let Some(s) = value.as_str() {
-                return Some(s.to_string());
-            }
+        if let Some(value) = claims.get(key)
+            && let Some(s) = value.as_str()
+        {
+            return Some(s.to_string());
         }
     }
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{TestEnv, ADMIN_ID};
+
+    // 缓存是全局的,另一个测试在中途重置它会让 drop table 之后的断言失败,所以放在同一个测试里
+    #[actix_web::test]
+    async fn primary_admin_id_picks_lowest_admin_and_caches_it() {
+        let env = TestEnv::new().await;
+        env.exec(
+            "insert into t_user (id, username, password_hash, role) values (50, 'admin2', 'x', 'ADMIN')",
+            vec![],
+        )
+        .await;
+        reset_primary_admin_cache();
+        assert_eq!(primary_admin_id(&env.db).await.unwrap(), ADMIN_ID);
+
+        env.exec("drop table t_user", vec![]).await;
+        assert_eq!(primary_admin_id(&env.db).await.unwrap(), ADMIN_ID);
+        assert_eq!(find_primary_admin(&env.db).await.unwrap_err().code(), 99);
+    }
+}
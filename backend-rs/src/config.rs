@@ -1,4 +1,8 @@
-use std::env;
+use std::{env, fs};
+
+use jsonwebtoken::Algorithm;
+
+use crate::response::ResponseFormat;
 
 #[derive(Clone)]
 pub struct AppConfig {
@@ -6,10 +10,45 @@ pub struct AppConfig {
     pub sqlite_path: String,
     pub database_url: Option<String>,
     pub jwt_secret: String,
+    pub jwt_algorithm: Algorithm,
+    /// PEM keys, only loaded for RS256.
+    pub jwt_private_key: Option<String>,
+    pub jwt_public_key: Option<String>,
     pub token_header: String,
-    #[allow(dead_code)]
+    /// Lifetime of newly signed login and dev tokens; tokens already issued keep their own `exp`.
+    pub token_ttl_days: i64,
+    /// `bcrypt` (default) or `argon2id`, used for new password hashes.
+    pub password_hash_algo: String,
+    /// Front end origin (`MBLOG_FRONT_DOMAIN`), always allowed by CORS next to `CORS_DOMAIN_LIST`.
     pub safe_domain: String,
     pub upload_storage_path: String,
+    pub webhook_poll_seconds: u64,
+    pub webhook_max_attempts: i32,
+    /// Maintenance job interval and per-table retention in days, 0 keeps rows forever.
+    pub prune_interval_seconds: u64,
+    pub session_retention_days: i64,
+    pub view_event_retention_days: i64,
+    pub webhook_outbox_retention_days: i64,
+    /// Unattached uploads (`memo_id = 0`) older than this are deleted with their files.
+    pub orphan_resource_ttl_hours: i64,
+    pub max_concurrent_uploads: usize,
+    /// Per-file upload cap in bytes, 0 disables.
+    pub max_upload_file_bytes: u64,
+    /// Per-user upload limits counted from t_resource, 0 disables; admins are exempt.
+    pub upload_files_per_minute: u64,
+    pub upload_bytes_per_hour: i64,
+    /// Requests per minute per dev token (`API`) and per user for web logins, 0 disables.
+    pub api_rate_limit_per_minute: u64,
+    pub web_rate_limit_per_minute: u64,
+    pub official_square_url: String,
+    pub official_square_allowed_hosts: Vec<String>,
+    pub disable_official_square: bool,
+    /// Trust `X-Forwarded-Proto`/`X-Forwarded-Host` when building URLs without `DOMAIN`.
+    pub trusted_proxy: bool,
+    pub response_format: ResponseFormat,
+    /// Name the failing field in JSON deserialize errors (`JSON_ERROR_DETAIL`, default on).
+    pub json_error_detail: bool,
+    pub embed: bool,
 }
 
 impl AppConfig {
@@ -26,22 +65,152 @@ impl AppConfig {
             .or_else(|_| env::var("JWT_SECRET"))
             .unwrap_or_else(|_| "6c6AJaXnTRXWpr9aUUqP".to_string());
 
+        let jwt_algorithm = match env::var("JWT_ALGORITHM").unwrap_or_default().trim() {
+            "" | "HS256" => Algorithm::HS256,
+            "RS256" => Algorithm::RS256,
+            other => panic!("unsupported JWT_ALGORITHM: {}", other),
+        };
+        let (jwt_private_key, jwt_public_key) = if jwt_algorithm == Algorithm::RS256 {
+            let read_pem = |key: &str| {
+                let path = env::var(key).unwrap_or_else(|_| panic!("{} is required for RS256", key));
+                fs::read_to_string(&path).unwrap_or_else(|e| panic!("failed to read {}: {}", path, e))
+            };
+            (
+                Some(read_pem("JWT_PRIVATE_KEY_PATH")),
+                Some(read_pem("JWT_PUBLIC_KEY_PATH")),
+            )
+        } else {
+            (None, None)
+        };
+
         let token_header = env::var("SA_TOKEN_HEADER")
             .or_else(|_| env::var("TOKEN_HEADER"))
             .unwrap_or_else(|_| "token".to_string());
 
+        let token_ttl_days = env::var("TOKEN_TTL_DAYS")
+            .ok()
+            .and_then(|v| v.parse::<i64>().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(36500);
+
+        let password_hash_algo = match env::var("PASSWORD_HASH_ALGO").unwrap_or_default().trim() {
+            "" | "bcrypt" => "bcrypt".to_string(),
+            "argon2id" => "argon2id".to_string(),
+            other => panic!("unsupported PASSWORD_HASH_ALGO: {}", other),
+        };
+
         let safe_domain = env::var("MBLOG_FRONT_DOMAIN").unwrap_or_default();
         let upload_storage_path = env::var("UPLOAD_STORAGE_PATH")
             .unwrap_or_else(|_| "/opt/mblog/upload".to_string());
+        let webhook_poll_seconds = env::var("WEB_HOOK_POLL_SECONDS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(10);
+        let webhook_max_attempts = env::var("WEB_HOOK_MAX_ATTEMPTS")
+            .ok()
+            .and_then(|v| v.parse::<i32>().ok())
+            .unwrap_or(5);
+        let prune_interval_seconds = env::var("PRUNE_INTERVAL_SECONDS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(3600);
+        let session_retention_days = env::var("SESSION_RETENTION_DAYS")
+            .ok()
+            .and_then(|v| v.parse::<i64>().ok())
+            .unwrap_or(30);
+        let view_event_retention_days = env::var("VIEW_EVENT_RETENTION_DAYS")
+            .ok()
+            .and_then(|v| v.parse::<i64>().ok())
+            .unwrap_or(7);
+        let webhook_outbox_retention_days = env::var("WEB_HOOK_OUTBOX_RETENTION_DAYS")
+            .ok()
+            .and_then(|v| v.parse::<i64>().ok())
+            .unwrap_or(30);
+        let orphan_resource_ttl_hours = env::var("ORPHAN_RESOURCE_TTL_HOURS")
+            .ok()
+            .and_then(|v| v.parse::<i64>().ok())
+            .unwrap_or(24);
+        let max_concurrent_uploads = env::var("MAX_CONCURRENT_UPLOADS")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .unwrap_or(0);
+        let max_upload_file_bytes = env::var("MAX_UPLOAD_FILE_BYTES")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(0);
+        let upload_files_per_minute = env::var("UPLOAD_FILES_PER_MINUTE")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(0);
+        let upload_bytes_per_hour = env::var("UPLOAD_BYTES_PER_HOUR")
+            .ok()
+            .and_then(|v| v.parse::<i64>().ok())
+            .unwrap_or(0);
+        let api_rate_limit_per_minute = env::var("API_RATE_LIMIT_PER_MINUTE")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(120);
+        let web_rate_limit_per_minute = env::var("WEB_RATE_LIMIT_PER_MINUTE")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(0);
+        let official_square_url = env::var("OFFICIAL_SQUARE_URL")
+            .unwrap_or_else(|_| "https://square.mblog.club".to_string());
+        let official_square_allowed_hosts = env::var("OFFICIAL_SQUARE_ALLOWED_HOSTS")
+            .unwrap_or_else(|_| "square.mblog.club".to_string())
+            .split(',')
+            .map(|s| s.trim().to_ascii_lowercase())
+            .filter(|s| !s.is_empty())
+            .collect();
+        let disable_official_square = env::var("DISABLE_OFFICIAL_SQUARE")
+            .map(|v| matches!(v.trim().to_ascii_lowercase().as_str(), "1" | "true" | "yes"))
+            .unwrap_or(false);
+        let trusted_proxy = env::var("TRUSTED_PROXY")
+            .map(|v| matches!(v.trim().to_ascii_lowercase().as_str(), "1" | "true" | "yes"))
+            .unwrap_or(false);
+        let response_format = match env::var("RESPONSE_FORMAT").unwrap_or_default().trim() {
+            "" | "legacy" => ResponseFormat::Legacy,
+            "standard" => ResponseFormat::Standard,
+            other => panic!("unsupported RESPONSE_FORMAT: {}", other),
+        };
+        let json_error_detail = env::var("JSON_ERROR_DETAIL")
+            .map(|v| !matches!(v.trim().to_ascii_lowercase().as_str(), "0" | "false" | "no"))
+            .unwrap_or(true);
+        let embed = env::var("MBLOG_EMBED").map(|v| !v.is_empty()).unwrap_or(false);
 
         Self {
             server_port,
             sqlite_path,
             database_url,
             jwt_secret,
+            jwt_algorithm,
+            jwt_private_key,
+            jwt_public_key,
             token_header,
+            token_ttl_days,
+            password_hash_algo,
             safe_domain,
             upload_storage_path,
+            webhook_poll_seconds,
+            webhook_max_attempts,
+            prune_interval_seconds,
+            session_retention_days,
+            view_event_retention_days,
+            webhook_outbox_retention_days,
+            orphan_resource_ttl_hours,
+            max_concurrent_uploads,
+            max_upload_file_bytes,
+            upload_files_per_minute,
+            upload_bytes_per_hour,
+            api_rate_limit_per_minute,
+            web_rate_limit_per_minute,
+            official_square_url,
+            official_square_allowed_hosts,
+            disable_official_square,
+            trusted_proxy,
+            response_format,
+            json_error_detail,
+            embed,
         }
     }
 
@@ -1,4 +1,5 @@
-use sea_orm::{ConnectionTrait, Database, DatabaseConnection, Statement};
+use log::{info, warn};
+use sea_orm::{ConnectionTrait, Database, DatabaseConnection, DbErr, Statement, TransactionTrait};
 use std::fs::{self, OpenOptions};
 use std::path::Path;
 
@@ -11,9 +12,32 @@ pub async fn connect_db(config: &AppConfig) -> DatabaseConnection {
         .await
         .unwrap_or_else(|e| panic!("db connect failed: {}", e));
     init_sqlite_schema(&db).await;
+    warn_on_local_time_offset(&db).await;
     db
 }
 
+/// Rows the application writes carry UTC timestamps, but columns left to their SQLite default
+/// (`datetime(CURRENT_TIMESTAMP,'localtime')`) get the server's local time and are later read
+/// back as UTC. Warn when the two differ so the server can be run with `TZ=UTC`.
+async fn warn_on_local_time_offset(db: &DatabaseConnection) {
+    let offset = db
+        .query_one(Statement::from_string(
+            db.get_database_backend(),
+            "SELECT CAST(strftime('%s','now','localtime') AS INTEGER) - CAST(strftime('%s','now') AS INTEGER) AS offset",
+        ))
+        .await
+        .ok()
+        .flatten()
+        .and_then(|row| row.try_get::<i64>("", "offset").ok())
+        .unwrap_or(0);
+    if offset != 0 {
+        warn!(
+            "local time is {}s off UTC: column defaults will store local time while the app reads UTC, run with TZ=UTC",
+            offset
+        );
+    }
+}
+
 fn ensure_sqlite_path(config: &AppConfig) {
     let raw = config.database_url();
     let path = raw
@@ -26,29 +50,99 @@ fn ensure_sqlite_path(config: &AppConfig) {
     }
     let _ = OpenOptions::new()
         .create(true)
+        .truncate(false)
         .write(true)
         .open(path);
 }
 
 async fn init_sqlite_schema(db: &DatabaseConnection) {
     let backend = db.get_database_backend();
-    let exists_stmt = Statement::from_string(
-        backend,
-        "SELECT name FROM sqlite_master WHERE type='table' AND name='t_sys_config' LIMIT 1",
-    );
-    let exists = db.query_one(exists_stmt).await.ok().flatten().is_some();
-    if exists {
-        return;
+    let fresh = !table_exists(db, "t_sys_config").await;
+    if !table_exists(db, "t_db_changelog").await {
+        let _ = db
+            .execute(Statement::from_string(
+                backend,
+                "CREATE TABLE `t_db_changelog` (`id` TEXT NOT NULL, `applied` timestamp NULL, PRIMARY KEY (`id`))",
+            ))
+            .await;
+        if !fresh {
+            // databases created before changesets were tracked already contain these
+            for id in BASELINE_CHANGESETS {
+                mark_changeset_applied(db, id).await;
+            }
+        }
     }
 
+    let applied: Vec<String> = db
+        .query_all(Statement::from_string(backend, "SELECT id FROM t_db_changelog"))
+        .await
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|row| row.try_get::<String>("", "id").ok())
+        .collect();
+
     let sql = include_str!("../changelog-sqlite.sql");
-    for stmt in split_sql(sql) {
-        let _ = db
-            .execute(Statement::from_string(backend, stmt))
-            .await;
+    for (id, body) in split_changesets(sql) {
+        if applied.contains(&id) {
+            continue;
+        }
+        if let Err(e) = apply_changeset(db, &id, &body).await {
+            panic!("changeset {} failed, database left unchanged: {}", id, e);
+        }
+        info!("changeset {} applied", id);
     }
 }
 
+/// Runs every statement of a changeset and records it in one transaction, so a failing
+/// statement leaves neither partial changes nor an applied mark behind.
+async fn apply_changeset(db: &DatabaseConnection, id: &str, body: &str) -> Result<(), DbErr> {
+    let backend = db.get_database_backend();
+    let txn = db.begin().await?;
+    for stmt in split_sql(body) {
+        txn.execute(Statement::from_string(backend, stmt)).await?;
+    }
+    txn.execute(Statement::from_sql_and_values(
+        backend,
+        "INSERT INTO t_db_changelog (id, applied) VALUES (?, CURRENT_TIMESTAMP)",
+        vec![id.into()],
+    ))
+    .await?;
+    txn.commit().await
+}
+
+const BASELINE_CHANGESETS: [&str; 6] = ["jerry:1", "jerry:2", "jerry:3", "jerry:4", "jerry:5", "jerry:6"];
+
+async fn table_exists(db: &DatabaseConnection, name: &str) -> bool {
+    let stmt = Statement::from_sql_and_values(
+        db.get_database_backend(),
+        "SELECT name FROM sqlite_master WHERE type='table' AND name=? LIMIT 1",
+        vec![name.into()],
+    );
+    db.query_one(stmt).await.ok().flatten().is_some()
+}
+
+async fn mark_changeset_applied(db: &DatabaseConnection, id: &str) {
+    let stmt = Statement::from_sql_and_values(
+        db.get_database_backend(),
+        "INSERT INTO t_db_changelog (id, applied) VALUES (?, CURRENT_TIMESTAMP)",
+        vec![id.into()],
+    );
+    let _ = db.execute(stmt).await;
+}
+
+fn split_changesets(input: &str) -> Vec<(String, String)> {
+    let mut sets: Vec<(String, String)> = Vec::new();
+    for line in input.lines() {
+        if let Some(id) = line.trim().strip_prefix("-- changeset ") {
+            sets.push((id.trim().to_string(), String::new()));
+        } else if let Some((_, body)) = sets.last_mut() {
+            body.push_str(line);
+            body.push('\n');
+        }
+    }
+    sets
+}
+
 fn split_sql(input: &str) -> Vec<String> {
     let mut buf = String::new();
     for line in input.lines() {
@@ -65,3 +159,33 @@ fn split_sql(input: &str) -> Vec<String> {
         .map(|s| s.to_string())
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Utc};
+    use sea_orm::{ActiveModelTrait, EntityTrait, Set};
+
+    use crate::entity::memo;
+    use crate::test_util::{TestEnv, ADMIN_ID};
+
+    #[actix_web::test]
+    async fn utc_timestamps_round_trip() {
+        let env = TestEnv::new().await;
+        let created = DateTime::parse_from_rfc3339("2024-03-10T23:30:15.250Z").unwrap().with_timezone(&Utc);
+        let updated = DateTime::parse_from_rfc3339("2024-03-11T01:02:03Z").unwrap().with_timezone(&Utc);
+        let saved = memo::ActiveModel {
+            user_id: Set(ADMIN_ID),
+            content: Set(Some("tz".to_string())),
+            created: Set(Some(created)),
+            updated: Set(Some(updated)),
+            ..Default::default()
+        }
+        .insert(&env.db)
+        .await
+        .unwrap();
+
+        let loaded = memo::Entity::find_by_id(saved.id).one(&env.db).await.unwrap().unwrap();
+        assert_eq!(loaded.created, Some(created));
+        assert_eq!(loaded.updated, Some(updated));
+    }
+}
@@ -0,0 +1,134 @@
+use std::time::Duration;
+
+use chrono::{NaiveDate, Timelike, Utc};
+use log::{error, info};
+use sea_orm::{
+    ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder, Statement,
+};
+use serde::Serialize;
+
+use crate::entity::memo;
+use crate::error::AppError;
+use crate::sys_config as sys_config_store;
+use crate::webhook;
+
+const CHECK_INTERVAL_SECS: u64 = 60;
+/// UTC date (`YYYY-MM-DD`) of the last digest that was delivered.
+const LAST_DATE_KEY: &str = "DAILY_DIGEST_LAST_DATE";
+const TITLE_MAX_CHARS: usize = 50;
+
+/// Once per day, at or after `DAILY_DIGEST_HOUR` (UTC), posts yesterday's public memos to
+/// `WEB_HOOK_URL` when `DAILY_DIGEST` is on.
+pub fn spawn_worker(db: DatabaseConnection) {
+    actix_web::rt::spawn(async move {
+        let mut interval = actix_web::rt::time::interval(Duration::from_secs(CHECK_INTERVAL_SECS));
+        loop {
+            interval.tick().await;
+            if let Err(e) = run_if_due(&db).await {
+                error!("daily digest failed: {}", e);
+            }
+        }
+    });
+}
+
+async fn run_if_due(db: &DatabaseConnection) -> Result<(), AppError> {
+    let enabled = sys_config_store::get_boolean(db, "DAILY_DIGEST")
+        .await
+        .map_err(|_| AppError::system_exception())?;
+    let url = sys_config_store::get_string(db, "WEB_HOOK_URL")
+        .await
+        .map_err(|_| AppError::system_exception())?
+        .unwrap_or_default();
+    if !enabled || url.is_empty() {
+        return Ok(());
+    }
+
+    let now = Utc::now();
+    let hour = sys_config_store::get_i64(db, "DAILY_DIGEST_HOUR")
+        .await
+        .map_err(|_| AppError::system_exception())?
+        .unwrap_or(8)
+        .clamp(0, 23) as u32;
+    let today = now.date_naive();
+    let last = sys_config_store::get_string(db, LAST_DATE_KEY)
+        .await
+        .map_err(|_| AppError::system_exception())?
+        .unwrap_or_default();
+    if now.hour() < hour || last == today.to_string() {
+        return Ok(());
+    }
+
+    let Some(day) = today.pred_opt() else {
+        return Ok(());
+    };
+    send_digest(db, &url, day).await?;
+    // 发送成功后才记录日期,重启不会重复发送同一天的摘要
+    let stmt = Statement::from_sql_and_values(
+        db.get_database_backend(),
+        "update t_sys_config set value = ? where `key` = ?",
+        vec![today.to_string().into(), LAST_DATE_KEY.into()],
+    );
+    db.execute(stmt).await.map_err(|_| AppError::system_exception())?;
+    info!("daily digest for {} delivered", day);
+    Ok(())
+}
+
+async fn send_digest(db: &DatabaseConnection, url: &str, day: NaiveDate) -> Result<(), AppError> {
+    let start = day.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc();
+    let end = start + chrono::Duration::days(1);
+    let memos = memo::Entity::find()
+        .filter(memo::Column::Status.eq("NORMAL"))
+        .filter(memo::Column::Visibility.eq("PUBLIC"))
+        .filter(memo::Column::Created.gte(start))
+        .filter(memo::Column::Created.lt(end))
+        .order_by_asc(memo::Column::Created)
+        .all(db)
+        .await
+        .map_err(|_| AppError::system_exception())?;
+
+    let token = sys_config_store::get_string(db, "WEB_HOOK_TOKEN")
+        .await
+        .map_err(|_| AppError::system_exception())?
+        .unwrap_or_default();
+    let domain = sys_config_store::get_string(db, "DOMAIN")
+        .await
+        .map_err(|_| AppError::system_exception())?
+        .unwrap_or_default();
+
+    #[derive(Serialize)]
+    #[serde(rename_all = "camelCase")]
+    struct DigestItem {
+        id: i32,
+        title: String,
+        link: String,
+    }
+
+    #[derive(Serialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Payload {
+        event: &'static str,
+        date: String,
+        count: usize,
+        memos: Vec<DigestItem>,
+    }
+
+    let items = memos
+        .into_iter()
+        .map(|m| DigestItem {
+            id: m.id,
+            title: m
+                .title
+                .filter(|t| !t.is_empty())
+                .unwrap_or_else(|| m.content.unwrap_or_default().chars().take(TITLE_MAX_CHARS).collect()),
+            link: format!("{}/memo/{}", domain, m.id),
+        })
+        .collect::<Vec<_>>();
+
+    let payload = Payload {
+        event: "DAILY_DIGEST",
+        date: day.to_string(),
+        count: items.len(),
+        memos: items,
+    };
+    webhook::post_webhook(url, &token, &payload).await
+}
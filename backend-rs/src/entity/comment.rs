@@ -16,6 +16,7 @@ pub struct Model {
     pub email: Option<String>,
     pub link: Option<String>,
     pub approved: Option<i32>,
+    pub like_count: Option<i32>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -0,0 +1,17 @@
+use sea_orm::entity::prelude::*;
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+#[sea_orm(table_name = "t_comment_relation")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    pub comment_id: i32,
+    pub user_id: i32,
+    pub fav_type: String,
+    pub created: Option<DateTimeUtc>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
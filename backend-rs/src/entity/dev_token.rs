@@ -8,6 +8,7 @@ pub struct Model {
     pub name: String,
     pub token: String,
     pub user_id: i32,
+    pub last_used: Option<DateTimeUtc>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -18,6 +18,14 @@ pub struct Model {
     pub enable_comment: Option<i32>,
     pub view_count: Option<i32>,
     pub source: Option<String>,
+    pub title: Option<String>,
+    /// Page title and `og:image` of `source`, filled in the background when `UNFURL_SOURCE` is on.
+    pub source_title: Option<String>,
+    pub source_image: Option<String>,
+    /// `markdown` (also when null), `plaintext` or `html`.
+    pub content_format: Option<String>,
+    /// Users `@`-mentioned in the content, `#id,` per user like `t_comment.mentioned_user_id`.
+    pub mentioned_user_id: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -6,3 +6,7 @@ pub mod user_memo_relation;
 pub mod comment;
 pub mod resource;
 pub mod tag;
+pub mod comment_relation;
+pub mod webhook_outbox;
+pub mod invite_code;
+pub mod session;
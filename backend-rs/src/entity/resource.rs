@@ -3,7 +3,7 @@ use sea_orm::entity::prelude::*;
 #[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
 #[sea_orm(table_name = "t_resource")]
 pub struct Model {
-    #[sea_orm(primary_key)]
+    #[sea_orm(primary_key, auto_increment = false)]
     pub public_id: String,
     pub memo_id: i32,
     pub user_id: i32,
@@ -17,6 +17,7 @@ pub struct Model {
     pub created: Option<DateTimeUtc>,
     pub updated: Option<DateTimeUtc>,
     pub suffix: Option<String>,
+    pub alt: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
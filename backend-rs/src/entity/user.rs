@@ -17,6 +17,7 @@ pub struct Model {
     pub last_clicked_mentioned: Option<DateTimeUtc>,
     pub default_visibility: Option<String>,
     pub default_enable_comment: Option<String>,
+    pub last_checked_comments: Option<DateTimeUtc>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -1,7 +1,7 @@
 use actix_web::{http::StatusCode, ResponseError};
 use thiserror::Error;
 
-use crate::response::response_from_error;
+use crate::response::{error_status, response_from_error};
 
 #[derive(Debug, Error)]
 pub enum AppError {
@@ -26,11 +26,14 @@ impl AppError {
         Self::Biz { code: 3, msg: "api token已失效".to_string() }
     }
 
-    #[allow(dead_code)]
     pub fn file_size_limit(msg: impl Into<String>) -> Self {
         Self::Biz { code: 4, msg: msg.into() }
     }
 
+    pub fn too_many_requests(msg: impl Into<String>) -> Self {
+        Self::Biz { code: 5, msg: msg.into() }
+    }
+
     pub fn system_exception() -> Self {
         Self::Biz { code: 99, msg: "system_exception".to_string() }
     }
@@ -50,7 +53,7 @@ impl AppError {
 
 impl ResponseError for AppError {
     fn status_code(&self) -> StatusCode {
-        StatusCode::OK
+        error_status(self)
     }
 
     fn error_response(&self) -> actix_web::HttpResponse {
@@ -0,0 +1,122 @@
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use serde::Deserialize;
+
+use crate::error::AppError;
+
+/// A memo read from another app's export, before it is mapped onto `t_memo`.
+pub struct ImportedMemo {
+    pub content: String,
+    pub created: Option<DateTime<Utc>>,
+    pub visibility: Option<String>,
+    /// Tags carried outside the content (e.g. a flomo `tags` column), without the `#`.
+    pub tags: Vec<String>,
+}
+
+/// Parses one export format into memos. Add a new implementation and register it in
+/// [`importer_for`] to support another app.
+pub trait MemoImporter {
+    fn parse(&self, data: &[u8]) -> Result<Vec<ImportedMemo>, AppError>;
+}
+
+pub fn importer_for(format: &str) -> Option<Box<dyn MemoImporter>> {
+    match format {
+        "memos" => Some(Box::new(MemosImporter)),
+        "flomo" => Some(Box::new(FlomoImporter)),
+        _ => None,
+    }
+}
+
+/// JSON export of the Memos app: either a bare array or `{ "memos": [...] }`, with
+/// `createdTs` in unix seconds (older versions) or `createTime` as RFC 3339.
+struct MemosImporter;
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum MemosExport {
+    List(Vec<MemosItem>),
+    Wrapped { memos: Vec<MemosItem> },
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MemosItem {
+    content: Option<String>,
+    created_ts: Option<i64>,
+    create_time: Option<String>,
+    visibility: Option<String>,
+}
+
+impl MemoImporter for MemosImporter {
+    fn parse(&self, data: &[u8]) -> Result<Vec<ImportedMemo>, AppError> {
+        let export: MemosExport =
+            serde_json::from_slice(data).map_err(|_| AppError::fail("无法解析Memos导出文件"))?;
+        let items = match export {
+            MemosExport::List(items) => items,
+            MemosExport::Wrapped { memos } => memos,
+        };
+        Ok(items
+            .into_iter()
+            .map(|item| ImportedMemo {
+                content: item.content.unwrap_or_default(),
+                created: item
+                    .created_ts
+                    .and_then(|ts| Utc.timestamp_opt(ts, 0).single())
+                    .or_else(|| {
+                        item.create_time
+                            .as_deref()
+                            .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+                            .map(|t| t.with_timezone(&Utc))
+                    }),
+                visibility: item.visibility.map(|v| match v.as_str() {
+                    "PROTECTED" => "PROTECT".to_string(),
+                    _ => v,
+                }),
+                tags: Vec::new(),
+            })
+            .collect())
+    }
+}
+
+/// CSV export of flomo with a header row; reads the `content`, `created_at` and optional
+/// `tags` (comma or space separated) columns. Everything imports as private.
+struct FlomoImporter;
+
+impl MemoImporter for FlomoImporter {
+    fn parse(&self, data: &[u8]) -> Result<Vec<ImportedMemo>, AppError> {
+        let mut reader = csv::Reader::from_reader(data);
+        let headers = reader
+            .headers()
+            .map_err(|_| AppError::fail("无法解析flomo导出文件"))?
+            .clone();
+        let column = |name: &str| headers.iter().position(|h| h.trim().eq_ignore_ascii_case(name));
+        let content_idx = column("content").ok_or_else(|| AppError::fail("flomo导出文件缺少content列"))?;
+        let created_idx = column("created_at");
+        let tags_idx = column("tags");
+
+        let mut list = Vec::new();
+        for record in reader.records() {
+            let record = record.map_err(|_| AppError::fail("无法解析flomo导出文件"))?;
+            let created = created_idx
+                .and_then(|i| record.get(i))
+                .and_then(|t| NaiveDateTime::parse_from_str(t.trim(), "%Y-%m-%d %H:%M:%S").ok())
+                .map(|t| Utc.from_utc_datetime(&t));
+            let tags = tags_idx
+                .and_then(|i| record.get(i))
+                .map(|t| {
+                    t.split(|c: char| c == ',' || c.is_whitespace())
+                        .map(|s| s.trim_start_matches('#'))
+                        .filter(|s| !s.is_empty())
+                        .map(str::to_string)
+                        .collect()
+                })
+                .unwrap_or_default();
+            list.push(ImportedMemo {
+                content: record.get(content_idx).unwrap_or_default().to_string(),
+                created,
+                visibility: Some("PRIVATE".to_string()),
+                tags,
+            });
+        }
+        Ok(list)
+    }
+}
@@ -1,11 +1,25 @@
 mod auth;
 mod config;
 mod db;
+mod digest;
 mod entity;
 mod error;
+mod importer;
+mod maintenance;
+mod origin;
+mod password;
+mod rate_limit;
+mod render;
+mod request;
 mod response;
 mod routes;
+mod session;
 mod sys_config;
+#[cfg(test)]
+mod test_util;
+mod unfurl;
+mod view_counter;
+mod webhook;
 
 use actix_web::{middleware, web, App, HttpServer};
 use config::AppConfig;
@@ -20,15 +34,25 @@ async fn main() -> std::io::Result<()> {
     dotenvy::dotenv().ok();
     env_logger::init();
     let config = AppConfig::from_env();
+    response::init_format(config.response_format);
+    request::init_json_error_detail(config.json_error_detail);
     let db = connect_db(&config).await;
     sys_config_routes::init_defaults(&db).await;
+    webhook::spawn_worker(db.clone(), config.clone());
+    view_counter::spawn_worker(db.clone());
+    digest::spawn_worker(db.clone());
+    maintenance::spawn_worker(db.clone(), config.clone());
+    let cors_origins = web::Data::new(routes::cors::CorsOrigins::load(&db, &config).await);
     let server_port = config.server_port;
 
     let server = HttpServer::new(move || {
         App::new()
             .app_data(web::Data::new(config.clone()))
             .app_data(web::Data::new(db.clone()))
+            .app_data(cors_origins.clone())
             .app_data(web::JsonConfig::default().error_handler(json_error_handler))
+            .wrap(actix_web::middleware::from_fn(routes::read_only::read_only_guard))
+            .wrap(actix_web::middleware::from_fn(rate_limit::headers))
             .wrap(middleware::Logger::default())
             .wrap(actix_web::middleware::from_fn(routes::cors::cors_handler))
             .service(web::scope("/api")
@@ -0,0 +1,119 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use log::{error, info};
+use sea_orm::{ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait, QueryFilter, QuerySelect, Statement};
+
+use crate::config::AppConfig;
+use crate::entity::resource;
+use crate::routes::resource::delete_stored_file;
+use crate::sys_config as sys_config_store;
+
+/// Highest `t_memo_view.id` already folded into `t_memo.view_count`, see `view_counter`.
+const VIEW_CURSOR_KEY: &str = "VIEW_EVENT_CURSOR";
+/// Orphaned uploads reaped per run, so one run never holds up the worker for long.
+const ORPHAN_BATCH: u64 = 500;
+
+/// Periodically deletes rows that only matter for a while: revoked sessions, aggregated view
+/// events, delivered webhook outbox entries and uploads never attached to a memo, each after
+/// its retention from `AppConfig`.
+pub fn spawn_worker(db: DatabaseConnection, config: AppConfig) {
+    actix_web::rt::spawn(async move {
+        let mut interval =
+            actix_web::rt::time::interval(Duration::from_secs(config.prune_interval_seconds.max(60)));
+        loop {
+            interval.tick().await;
+            prune_all(&db, &config).await;
+        }
+    });
+}
+
+async fn prune_all(db: &DatabaseConnection, config: &AppConfig) {
+    if config.session_retention_days > 0 {
+        let cutoff = Utc::now() - chrono::Duration::days(config.session_retention_days);
+        prune(
+            db,
+            "t_session",
+            "delete from t_session where revoked = 1 and coalesce(last_seen, created) < ?",
+            vec![cutoff.into()],
+        )
+        .await;
+    }
+
+    if config.view_event_retention_days > 0 {
+        // 只删已经计入 view_count 的事件,没聚合的保留给 view_counter
+        match sys_config_store::get_i64(db, VIEW_CURSOR_KEY).await {
+            Ok(cursor) => {
+                let cutoff = Utc::now() - chrono::Duration::days(config.view_event_retention_days);
+                prune(
+                    db,
+                    "t_memo_view",
+                    "delete from t_memo_view where id <= ? and created < ?",
+                    vec![cursor.unwrap_or(0).into(), cutoff.into()],
+                )
+                .await;
+            }
+            Err(e) => error!("prune t_memo_view failed: {}", e),
+        }
+    }
+
+    if config.webhook_outbox_retention_days > 0 {
+        let cutoff = Utc::now() - chrono::Duration::days(config.webhook_outbox_retention_days);
+        prune(
+            db,
+            "t_webhook_outbox",
+            "delete from t_webhook_outbox where status = 'DELIVERED' and updated < ?",
+            vec![cutoff.into()],
+        )
+        .await;
+    }
+
+    if config.orphan_resource_ttl_hours > 0 {
+        reap_orphan_resources(db, config.orphan_resource_ttl_hours).await;
+    }
+}
+
+/// Unlike the tables above, orphaned uploads also own a file or object, so they are deleted
+/// row by row through the same path as `/api/resource/remove`.
+async fn reap_orphan_resources(db: &DatabaseConnection, ttl_hours: i64) {
+    let cutoff = Utc::now() - chrono::Duration::hours(ttl_hours);
+    let orphans = resource::Entity::find()
+        .filter(resource::Column::MemoId.eq(0))
+        .filter(resource::Column::Created.lt(cutoff))
+        .limit(ORPHAN_BATCH)
+        .all(db)
+        .await;
+    let orphans = match orphans {
+        Ok(orphans) => orphans,
+        Err(e) => {
+            error!("find orphaned resources failed: {}", e);
+            return;
+        }
+    };
+    let mut reaped = 0;
+    for r in orphans {
+        // memo_id 条件防止删掉刚被保存的memo挂上的附件
+        let deleted = resource::Entity::delete_many()
+            .filter(resource::Column::PublicId.eq(r.public_id.clone()))
+            .filter(resource::Column::MemoId.eq(0))
+            .exec(db)
+            .await;
+        match deleted {
+            Ok(result) if result.rows_affected == 1 => {
+                delete_stored_file(db, &r).await;
+                reaped += 1;
+            }
+            Ok(_) => {}
+            Err(e) => error!("delete orphaned resource {} failed: {}", r.public_id, e),
+        }
+    }
+    info!("reaped {} orphaned resources", reaped);
+}
+
+async fn prune(db: &DatabaseConnection, table: &str, sql: &str, values: Vec<sea_orm::Value>) {
+    let stmt = Statement::from_sql_and_values(db.get_database_backend(), sql, values);
+    match db.execute(stmt).await {
+        Ok(result) => info!("pruned {} rows from {}", result.rows_affected(), table),
+        Err(e) => error!("prune {} failed: {}", table, e),
+    }
+}
@@ -0,0 +1,70 @@
+use std::convert::Infallible;
+use std::future::{ready, Ready};
+
+use actix_web::{dev::Payload, http::header, web, FromRequest, HttpRequest};
+
+use crate::config::AppConfig;
+
+/// Scheme and host the current request was addressed to, e.g. `https://blog.example.com`.
+/// `X-Forwarded-Proto`/`X-Forwarded-Host` are only honoured when `TRUSTED_PROXY` is set.
+#[derive(Clone, Debug)]
+pub struct RequestOrigin(pub String);
+
+impl RequestOrigin {
+    /// Prefers the configured `DOMAIN`; falls back to the request origin when it is empty.
+    pub fn resolve(&self, domain: &str) -> String {
+        let domain = domain.trim().trim_end_matches('/');
+        if domain.is_empty() {
+            self.0.clone()
+        } else {
+            domain.to_string()
+        }
+    }
+}
+
+impl FromRequest for RequestOrigin {
+    type Error = Infallible;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let trusted_proxy = req
+            .app_data::<web::Data<AppConfig>>()
+            .map(|cfg| cfg.trusted_proxy)
+            .unwrap_or(false);
+        let header_value = |name: &str| {
+            req.headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.split(',').next())
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+        };
+
+        let mut scheme = req.uri().scheme_str().unwrap_or("http").to_string();
+        let mut host = header_value(header::HOST.as_str()).unwrap_or_default();
+        if trusted_proxy {
+            if let Some(proto) = header_value("x-forwarded-proto") {
+                scheme = proto;
+            }
+            if let Some(forwarded_host) = header_value("x-forwarded-host") {
+                host = forwarded_host;
+            }
+        }
+
+        let origin = if host.is_empty() {
+            String::new()
+        } else {
+            format!("{}://{}", scheme, host)
+        };
+        ready(Ok(RequestOrigin(origin)))
+    }
+}
+
+/// Address the request came from, used to tell anonymous visitors apart. `X-Forwarded-For` and
+/// `Forwarded` are only believed with `TRUSTED_PROXY`; otherwise any client could pick its own.
+pub fn client_ip(req: &HttpRequest, trusted_proxy: bool) -> String {
+    if trusted_proxy {
+        return req.connection_info().realip_remote_addr().unwrap_or_default().to_string();
+    }
+    req.peer_addr().map(|addr| addr.ip().to_string()).unwrap_or_default()
+}
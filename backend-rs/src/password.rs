@@ -0,0 +1,55 @@
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use log::error;
+use rand::RngCore;
+
+use crate::config::AppConfig;
+use crate::error::AppError;
+
+const ALGO_ARGON2ID: &str = "argon2id";
+
+/// Hashes `password` with the algorithm selected by `PASSWORD_HASH_ALGO`.
+pub fn hash_password(config: &AppConfig, password: &str) -> Result<String, AppError> {
+    if config.password_hash_algo == ALGO_ARGON2ID {
+        let mut bytes = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let salt = SaltString::encode_b64(&bytes).map_err(|_| AppError::system_exception())?;
+        return Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|h| h.to_string())
+            .map_err(|e| {
+                error!("argon2 hash failed: {}", e);
+                AppError::system_exception()
+            });
+    }
+    bcrypt::hash(password, 10).map_err(|_| AppError::system_exception())
+}
+
+/// Verifies `password` against a stored bcrypt (`$2...`) or argon2 (`$argon2...`) hash.
+pub fn verify_password(password: &str, stored: &str) -> Result<bool, AppError> {
+    if stored.starts_with("$argon2") {
+        let parsed = PasswordHash::new(stored).map_err(|_| AppError::system_exception())?;
+        return Ok(Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok());
+    }
+    bcrypt::verify(password, stored).map_err(|_| AppError::system_exception())
+}
+
+/// Whether a stored hash was made with a different algorithm than the configured one.
+pub fn needs_rehash(config: &AppConfig, stored: &str) -> bool {
+    let is_argon2 = stored.starts_with("$argon2");
+    is_argon2 != (config.password_hash_algo == ALGO_ARGON2ID)
+}
+
+const MIN_PASSWORD_LEN: usize = 8;
+
+/// Minimal strength rule for passwords set on someone else's behalf: at least 8 characters
+/// mixing letters and digits.
+pub fn check_strength(password: &str) -> Result<(), AppError> {
+    let long_enough = password.chars().count() >= MIN_PASSWORD_LEN;
+    let has_letter = password.chars().any(|c| c.is_alphabetic());
+    let has_digit = password.chars().any(|c| c.is_ascii_digit());
+    if !(long_enough && has_letter && has_digit) {
+        return Err(AppError::param_error("密码至少8位且需包含字母和数字"));
+    }
+    Ok(())
+}
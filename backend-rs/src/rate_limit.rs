@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::{HeaderName, HeaderValue},
+    middleware::Next,
+    Error, HttpMessage, HttpRequest,
+};
+
+use crate::auth::AuthUser;
+use crate::config::AppConfig;
+use crate::error::AppError;
+
+/// Token bucket per dev token (`API` device) or per user (`WEB`), refilled continuously at
+/// `limit` tokens per minute up to `limit`.
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+static BUCKETS: OnceLock<Mutex<HashMap<String, Bucket>>> = OnceLock::new();
+
+/// Outcome of the request's charge, kept in the request extensions so [`headers`] can report it.
+#[derive(Clone, Copy)]
+struct Quota {
+    remaining: u64,
+    reset_secs: u64,
+}
+
+/// Takes one token for the authenticated caller, using `API_RATE_LIMIT_PER_MINUTE` for dev
+/// tokens and `WEB_RATE_LIMIT_PER_MINUTE` otherwise (0 = unlimited). A request is charged at
+/// most once even when several extractors authenticate it.
+pub fn charge(req: &HttpRequest, config: &AppConfig, auth: &AuthUser) -> Result<(), AppError> {
+    if req.extensions().contains::<Quota>() {
+        return Ok(());
+    }
+    let (key, limit) = match auth.dev_token_id {
+        Some(id) => (format!("api:{}", id), config.api_rate_limit_per_minute),
+        None => (format!("web:{}", auth.user_id), config.web_rate_limit_per_minute),
+    };
+    if limit == 0 {
+        return Ok(());
+    }
+
+    let capacity = limit as f64;
+    let per_sec = capacity / 60.0;
+    let now = Instant::now();
+    let mut buckets = BUCKETS
+        .get_or_init(|| Mutex::new(HashMap::new()))
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    let bucket = buckets.entry(key).or_insert(Bucket { tokens: capacity, updated: now });
+    bucket.tokens = (bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * per_sec).min(capacity);
+    bucket.updated = now;
+    let allowed = bucket.tokens >= 1.0;
+    if allowed {
+        bucket.tokens -= 1.0;
+    }
+    let quota = Quota {
+        remaining: bucket.tokens.floor() as u64,
+        reset_secs: ((capacity - bucket.tokens) / per_sec).ceil() as u64,
+    };
+    drop(buckets);
+
+    req.extensions_mut().insert(quota);
+    if !allowed {
+        return Err(AppError::too_many_requests("请求过于频繁,请稍后再试"));
+    }
+    Ok(())
+}
+
+/// Adds `X-RateLimit-Remaining` and `X-RateLimit-Reset` (seconds until the bucket is full) to
+/// responses of rate-limited callers.
+pub async fn headers(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let mut res = next.call(req).await?;
+    let quota = res.request().extensions().get::<Quota>().copied();
+    if let Some(quota) = quota {
+        let headers = res.headers_mut();
+        headers.insert(HeaderName::from_static("x-ratelimit-remaining"), HeaderValue::from(quota.remaining));
+        headers.insert(HeaderName::from_static("x-ratelimit-reset"), HeaderValue::from(quota.reset_secs));
+    }
+    Ok(res)
+}
@@ -0,0 +1,195 @@
+use pulldown_cmark::{html, Event, Options, Parser, Tag, TagEnd};
+
+/// Renders memo markdown to HTML and strips anything that could run script.
+pub fn to_safe_html(content: &str) -> String {
+    let parser = Parser::new_ext(content, Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TABLES);
+    let mut out = String::new();
+    html::push_html(&mut out, parser);
+    ammonia::clean(&out)
+}
+
+/// Flattens memo markdown to plain text, dropping markup and inline HTML.
+pub fn to_plain_text(content: &str) -> String {
+    let mut out = String::new();
+    for event in Parser::new_ext(content, Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TABLES) {
+        match event {
+            Event::Text(text) | Event::Code(text) => out.push_str(&text),
+            Event::SoftBreak | Event::HardBreak => out.push('\n'),
+            Event::End(TagEnd::Paragraph | TagEnd::Heading(_) | TagEnd::Item | TagEnd::CodeBlock) => {
+                out.push('\n')
+            }
+            Event::Start(Tag::Item) => out.push_str("- "),
+            _ => {}
+        }
+    }
+    out.trim().to_string()
+}
+
+/// Like [`to_safe_html`], but honours a memo's `content_format`: plaintext is escaped rather
+/// than parsed as markdown and stored html is only sanitized.
+pub fn to_safe_html_as(content: &str, content_format: Option<&str>) -> String {
+    match content_format {
+        Some("plaintext") => {
+            let escaped = content
+                .trim()
+                .replace('&', "&amp;")
+                .replace('<', "&lt;")
+                .replace('>', "&gt;")
+                .replace('"', "&quot;");
+            format!("<p>{}</p>", escaped.replace('\n', "<br>"))
+        }
+        Some("html") => ammonia::clean(content),
+        _ => to_safe_html(content),
+    }
+}
+
+/// Like [`to_plain_text`], but honours a memo's `content_format`.
+pub fn to_plain_text_as(content: &str, content_format: Option<&str>) -> String {
+    match content_format {
+        Some("plaintext") => content.trim().to_string(),
+        Some("html") => ammonia::Builder::empty().clean(content).to_string().trim().to_string(),
+        _ => to_plain_text(content),
+    }
+}
+
+/// Applies a `raw`/`text`/`html` output format to a memo stored as `content_format`; unknown
+/// output formats fall back to raw.
+pub fn format_content(content: &str, format: &str, content_format: Option<&str>) -> String {
+    match format {
+        "text" => to_plain_text_as(content, content_format),
+        "html" => to_safe_html_as(content, content_format),
+        _ => content.to_string(),
+    }
+}
+
+/// Tags a rendered comment may keep; anything else (images, tables, raw HTML) is dropped.
+const COMMENT_TAGS: [&str; 9] = ["a", "p", "br", "em", "strong", "del", "code", "pre", "blockquote"];
+
+/// Renders comment markdown to HTML under a tighter allowlist than memos, since comments can be
+/// anonymous. `mentions` are the `(user id, name)` pairs stored with the comment; their `@name`
+/// and `@[name](id)` forms become links to `/user/{id}`.
+pub fn comment_to_safe_html(content: &str, mentions: &[(i32, String)]) -> String {
+    let linked = link_mentions(content, mentions);
+    let parser = Parser::new_ext(&linked, Options::ENABLE_STRIKETHROUGH);
+    let mut out = String::new();
+    html::push_html(&mut out, parser);
+    ammonia::Builder::default()
+        .tags(COMMENT_TAGS.into_iter().collect())
+        .tag_attributes(std::iter::once(("a", std::iter::once("href").collect())).collect())
+        .url_schemes(["http", "https", "mailto"].into_iter().collect())
+        .link_rel(Some("nofollow noopener noreferrer"))
+        .clean(&out)
+        .to_string()
+}
+
+fn link_mentions(content: &str, mentions: &[(i32, String)]) -> String {
+    let mut out = String::with_capacity(content.len());
+    let mut rest = content;
+    while let Some(at) = rest.find('@') {
+        out.push_str(&rest[..at]);
+        let after = &rest[at + 1..];
+        let explicit = after.strip_prefix('[').and_then(|tail| {
+            let close = tail.find("](")?;
+            let end = close + 2 + tail[close + 2..].find(')')?;
+            let id = tail[close + 2..end].trim().parse::<i32>().ok()?;
+            let (_, name) = mentions.iter().find(|(mid, _)| *mid == id)?;
+            Some((id, name.as_str(), end + 2))
+        });
+        // 名字较长的优先,避免 @Jerry 抢先匹配 @JerryWang
+        let plain = || {
+            mentions
+                .iter()
+                .filter(|(_, name)| {
+                    !name.is_empty()
+                        && after.starts_with(name.as_str())
+                        && after[name.len()..]
+                            .chars()
+                            .next()
+                            .is_none_or(|c| c.is_whitespace() || ",.!?;:".contains(c))
+                })
+                .max_by_key(|(_, name)| name.len())
+                .map(|(id, name)| (*id, name.as_str(), name.len()))
+        };
+        match explicit.or_else(plain) {
+            Some((id, name, consumed)) => {
+                out.push_str(&format!("[@{}](/user/{})", escape_markdown(name), id));
+                rest = &after[consumed..];
+            }
+            None => {
+                out.push('@');
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+fn escape_markdown(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "\\[]()*_`<>".contains(c) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Stand-in text for memos that carry attachments but no content, used for RSS titles and
+/// webhook payloads.
+pub fn resource_placeholder(file_type: &str, file_name: &str) -> String {
+    if file_type.starts_with("image/") || file_name.trim().is_empty() {
+        "图片".to_string()
+    } else {
+        file_name.to_string()
+    }
+}
+
+/// Cleans user content before it is stored: normalizes line endings, drops control and
+/// zero-width characters (ZWJ is kept so emoji sequences survive), and collapses runs of
+/// blank lines longer than `max_blank_lines`.
+pub fn normalize_content(content: &str, max_blank_lines: usize) -> String {
+    let cleaned: String = content
+        .replace("\r\n", "\n")
+        .chars()
+        .filter(|c| {
+            !matches!(c, '\u{200B}' | '\u{200C}' | '\u{2060}' | '\u{FEFF}')
+                && (!c.is_control() || *c == '\n' || *c == '\t')
+        })
+        .collect();
+
+    let mut out: Vec<&str> = Vec::new();
+    let mut blank_run = 0;
+    for line in cleaned.split('\n') {
+        if line.trim().is_empty() {
+            blank_run += 1;
+            if blank_run > max_blank_lines {
+                continue;
+            }
+            out.push("");
+        } else {
+            blank_run = 0;
+            out.push(line);
+        }
+    }
+    out.join("\n")
+}
+
+/// Drops the `#` of inline tags and the `@` of mentions, e.g. `#rust @bob` becomes `rust bob`.
+pub fn strip_markers(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut prev: Option<char> = None;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        let at_word_start = prev.is_none_or(char::is_whitespace);
+        let marks_word = chars.peek().is_some_and(|n| !n.is_whitespace() && *n != c);
+        if (c == '#' || c == '@') && at_word_start && marks_word {
+            prev = Some(c);
+            continue;
+        }
+        out.push(c);
+        prev = Some(c);
+    }
+    out
+}
@@ -0,0 +1,157 @@
+use std::ops::{Deref, DerefMut};
+use std::sync::OnceLock;
+
+use actix_web::{dev::Payload, web, FromRequest, HttpRequest};
+use futures_util::future::LocalBoxFuture;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer};
+
+use crate::error::AppError;
+
+static JSON_ERROR_DETAIL: OnceLock<bool> = OnceLock::new();
+
+/// Whether [`Json`] names the offending field in deserialize errors, set once at startup via
+/// `JSON_ERROR_DETAIL`.
+pub fn init_json_error_detail(enabled: bool) {
+    let _ = JSON_ERROR_DETAIL.set(enabled);
+}
+
+/// JSON body extractor that reports which field failed to deserialize, e.g. "字段 page 类型错误".
+///
+/// The body is read through `web::Json<Value>` so `JsonConfig` (size limit, content type,
+/// `json_error_handler`) still applies; only the typed conversion is done here. Messages carry
+/// the field path and the kind of error, never the submitted value.
+pub struct Json<T>(pub T);
+
+impl<T> Json<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for Json<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for Json<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T: DeserializeOwned + 'static> FromRequest for Json<T> {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let body = web::Json::<serde_json::Value>::from_request(req, payload);
+        Box::pin(async move {
+            let value = body.await?.into_inner();
+            serde_path_to_error::deserialize(value)
+                .map(Json)
+                .map_err(|err| field_error(&err).into())
+        })
+    }
+}
+
+fn field_error(err: &serde_path_to_error::Error<serde_json::Error>) -> AppError {
+    if !JSON_ERROR_DETAIL.get().copied().unwrap_or(true) {
+        return AppError::param_error("请求参数不合法");
+    }
+    let path = err.path().to_string();
+    let msg = err.inner().to_string();
+    // serde 只在 missing/unknown field 的消息里用反引号标出字段名,不包含请求中的值;
+    // unknown field 的 path 已经以该字段结尾,missing field 的 path 只到所在对象
+    let quoted = msg.split('`').nth(1).filter(|name| !name.is_empty());
+    let field = |name: &str| {
+        if path == "." {
+            name.to_string()
+        } else {
+            format!("{}.{}", path, name)
+        }
+    };
+    match quoted {
+        Some(name) if msg.starts_with("missing field") => AppError::param_error(format!("缺少字段 {}", field(name))),
+        Some(name) if msg.starts_with("unknown field") => {
+            let name = if path == "." { name } else { path.as_str() };
+            AppError::param_error(format!("未知字段 {}", name))
+        }
+        _ if path != "." => AppError::param_error(format!("字段 {} 类型错误", path)),
+        _ => AppError::param_error("请求参数不合法"),
+    }
+}
+
+/// Deserializes an optional string, trimming it and mapping empty-after-trim to `None`.
+/// Use with `#[serde(default, deserialize_with = "crate::request::trimmed")]`.
+pub fn trimmed<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = Option::<String>::deserialize(deserializer)?;
+    Ok(value
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty()))
+}
+
+/// Like [`trimmed`], but a blank value becomes `Some("")` so update requests can tell "clear
+/// this field" apart from "leave it unchanged" (`None`).
+pub fn trimmed_or_clear<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = Option::<String>::deserialize(deserializer)?;
+    Ok(value.map(|v| v.trim().to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test as web_test;
+
+    use super::*;
+
+    #[derive(Debug, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct Page {
+        page: i64,
+    }
+
+    #[derive(Debug, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct Query {
+        filter: Page,
+    }
+
+    async fn extract(body: serde_json::Value) -> Result<Query, String> {
+        let (req, mut payload) = web_test::TestRequest::post().set_json(body).to_http_parts();
+        Json::<Query>::from_request(&req, &mut payload)
+            .await
+            .map(Json::into_inner)
+            .map_err(|e| e.as_error::<AppError>().unwrap().msg().to_string())
+    }
+
+    #[actix_web::test]
+    async fn bad_field_is_named_by_path() {
+        let err = extract(serde_json::json!({"filter": {"page": "two"}})).await.unwrap_err();
+        assert_eq!(err, "字段 filter.page 类型错误");
+        assert!(!err.contains("two"));
+
+        let err = extract(serde_json::json!({"filter": {}})).await.unwrap_err();
+        assert_eq!(err, "缺少字段 filter.page");
+
+        let err = extract(serde_json::json!({"filter": {"page": 1, "size": 2}})).await.unwrap_err();
+        assert_eq!(err, "未知字段 filter.size");
+
+        let err = extract(serde_json::json!({})).await.unwrap_err();
+        assert_eq!(err, "缺少字段 filter");
+
+        let err = extract(serde_json::json!({"filter": {"page": 1}, "sort": "id"})).await.unwrap_err();
+        assert_eq!(err, "未知字段 sort");
+
+        let query = extract(serde_json::json!({"filter": {"page": 2}})).await.unwrap();
+        assert_eq!(query.filter.page, 2);
+    }
+}
@@ -1,15 +1,61 @@
-use actix_web::{error::JsonPayloadError, HttpRequest, HttpResponse};
-use serde::Serialize;
+use std::sync::OnceLock;
+
+use actix_web::{error::JsonPayloadError, http::StatusCode, HttpRequest, HttpResponse};
+use serde::ser::SerializeStruct;
+use serde::{Serialize, Serializer};
 
 use crate::error::AppError;
 
-#[derive(Serialize)]
+/// Envelope used for every JSON response, chosen once at startup via `RESPONSE_FORMAT`.
+///
+/// - `legacy` (default): `{ data, code, msg }`, `code` 0 on success, always HTTP 200.
+/// - `standard`: `{ success, result, message, code }` where `result` is `data`, `message` is
+///   `msg` and `success` is `code == 0`; errors also set the HTTP status (param 400,
+///   login 401, file size 413, rate limit 429, other failures 400, system 500).
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ResponseFormat {
+    Legacy,
+    Standard,
+}
+
+static RESPONSE_FORMAT: OnceLock<ResponseFormat> = OnceLock::new();
+
+pub fn init_format(format: ResponseFormat) {
+    let _ = RESPONSE_FORMAT.set(format);
+}
+
+fn format() -> ResponseFormat {
+    RESPONSE_FORMAT.get().copied().unwrap_or(ResponseFormat::Legacy)
+}
+
 pub struct ResponseDto<T: Serialize> {
     pub data: Option<T>,
     pub code: i32,
     pub msg: String,
 }
 
+impl<T: Serialize> Serialize for ResponseDto<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match format() {
+            ResponseFormat::Legacy => {
+                let mut s = serializer.serialize_struct("ResponseDto", 3)?;
+                s.serialize_field("data", &self.data)?;
+                s.serialize_field("code", &self.code)?;
+                s.serialize_field("msg", &self.msg)?;
+                s.end()
+            }
+            ResponseFormat::Standard => {
+                let mut s = serializer.serialize_struct("ResponseDto", 4)?;
+                s.serialize_field("success", &(self.code == 0))?;
+                s.serialize_field("result", &self.data)?;
+                s.serialize_field("message", &self.msg)?;
+                s.serialize_field("code", &self.code)?;
+                s.end()
+            }
+        }
+    }
+}
+
 impl<T: Serialize> ResponseDto<T> {
     pub fn success(data: Option<T>) -> Self {
         Self {
@@ -38,8 +84,22 @@ pub fn json_error_handler(err: JsonPayloadError, _req: &HttpRequest) -> actix_we
     app_err.into()
 }
 
+/// HTTP status for an error response; only the standard envelope uses anything but 200.
+pub fn error_status(err: &AppError) -> StatusCode {
+    if format() == ResponseFormat::Legacy {
+        return StatusCode::OK;
+    }
+    match err.code() {
+        1 | 2 => StatusCode::BAD_REQUEST,
+        3 => StatusCode::UNAUTHORIZED,
+        4 => StatusCode::PAYLOAD_TOO_LARGE,
+        5 => StatusCode::TOO_MANY_REQUESTS,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
 pub fn response_from_error(err: &AppError) -> HttpResponse {
-    HttpResponse::Ok().json(ResponseDto::<()> {
+    HttpResponse::build(error_status(err)).json(ResponseDto::<()> {
         data: None,
         code: err.code(),
         msg: err.msg().to_string(),
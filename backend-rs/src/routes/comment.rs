@@ -1,23 +1,28 @@
 use actix_web::{web, HttpResponse};
 use chrono::{DateTime, NaiveDateTime, SecondsFormat, Utc};
 use sea_orm::{
-    ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait, QueryFilter, Set,
+    ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder, Set,
     Statement, TransactionError, TransactionTrait,
 };
 use serde::{Deserialize, Serialize};
 
 use crate::auth::{AuthUser, OptionalAuthUser};
-use crate::entity::{comment, memo, user};
+use crate::entity::{comment, comment_relation, memo, user};
 use crate::error::AppError;
+use crate::render;
+use crate::request::Json;
 use crate::response::ResponseDto;
 use crate::sys_config as sys_config_store;
+use crate::webhook;
 
 pub fn config(cfg: &mut web::ServiceConfig) {
     cfg.service(web::resource("/add").route(web::post().to(add)))
         .service(web::resource("/remove").route(web::post().to(remove)))
         .service(web::resource("/query").route(web::post().to(query)))
         .service(web::resource("/singleApprove").route(web::post().to(single_approve)))
-        .service(web::resource("/memoApprove").route(web::post().to(memo_approve)));
+        .service(web::resource("/memoApprove").route(web::post().to(memo_approve)))
+        .service(web::resource("/like").route(web::post().to(like)))
+        .service(web::resource("/unlike").route(web::post().to(unlike)));
 }
 
 #[derive(Deserialize)]
@@ -36,6 +41,9 @@ struct QueryCommentListRequest {
     page: i64,
     size: i64,
     memo_id: i32,
+    sort: Option<String>,
+    /// Also return each comment as sanitized HTML in `contentHtml`.
+    render: Option<bool>,
 }
 
 #[derive(Serialize)]
@@ -56,17 +64,20 @@ struct CommentDto {
     created: Option<String>,
     updated: Option<String>,
     content: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content_html: Option<String>,
     mentioned: Option<String>,
     mentioned_user_id: Option<String>,
     email: Option<String>,
     link: Option<String>,
     approved: i32,
+    like_count: i32,
 }
 
 async fn add(
     db: web::Data<DatabaseConnection>,
     auth: OptionalAuthUser,
-    payload: web::Json<SaveCommentRequest>,
+    payload: Json<SaveCommentRequest>,
 ) -> Result<HttpResponse, AppError> {
     let memo_item = memo::Entity::find_by_id(payload.memo_id)
         .one(db.get_ref())
@@ -104,12 +115,18 @@ async fn add(
         .await
         .map_err(|_| AppError::system_exception())?;
 
-    let (mentioned_names, mentioned_ids) = parse_mentions(db.get_ref(), &payload.content).await?;
+    let max_blank_lines = super::memo::load_max_blank_lines(db.get_ref()).await?;
+    let content = render::normalize_content(&payload.content, max_blank_lines);
+    if content.trim().is_empty() {
+        return Err(AppError::param_error("content"));
+    }
+
+    let (mentioned_names, mentioned_ids) = parse_mentions(db.get_ref(), &content).await?;
     let mut comment_model = comment::ActiveModel {
-        content: Set(payload.content.clone()),
+        content: Set(content.clone()),
         memo_id: Set(payload.memo_id),
         user_id: Set(user_id),
-        user_name: Set(author_name),
+        user_name: Set(author_name.clone()),
         mentioned: Set(mentioned_names.clone()),
         mentioned_user_id: Set(mentioned_ids.clone()),
         created: Set(Some(Utc::now())),
@@ -117,10 +134,14 @@ async fn add(
         ..Default::default()
     };
 
+    let pending_approval = auth.0.is_none()
+        && comment_approved
+        && !is_known_email(db.get_ref(), payload.email.as_deref()).await?;
+    let memo_id = payload.memo_id;
     if auth.0.is_none() {
         comment_model.email = Set(payload.email.clone());
         comment_model.link = Set(payload.link.clone());
-        comment_model.approved = Set(Some(if comment_approved { 0 } else { 1 }));
+        comment_model.approved = Set(Some(if pending_approval { 0 } else { 1 }));
     }
 
     db.transaction::<_, (), AppError>(|txn| {
@@ -142,9 +163,54 @@ async fn add(
     .await
     .map_err(map_tx_error)?;
 
+    if pending_approval {
+        webhook::notify_pending_comment_async(
+            db.get_ref().clone(),
+            memo_id,
+            author_name,
+            content,
+        );
+    }
+
     Ok(HttpResponse::Ok().json(ResponseDto::<()>::success(None)))
 }
 
+/// With `AUTO_APPROVE_KNOWN_EMAIL` on, an anonymous commenter whose email already has an
+/// approved comment skips the approval queue. The email is not verified, so this only trades
+/// moderation load for the risk of someone reusing a known address.
+async fn is_known_email(db: &DatabaseConnection, email: Option<&str>) -> Result<bool, AppError> {
+    let Some(email) = email.map(str::trim).filter(|e| is_valid_email(e)) else {
+        return Ok(false);
+    };
+    let enabled = sys_config_store::get_boolean(db, "AUTO_APPROVE_KNOWN_EMAIL")
+        .await
+        .map_err(|_| AppError::system_exception())?;
+    if !enabled {
+        return Ok(false);
+    }
+    let count = query_count(
+        db,
+        "select count(1) as cnt from t_comment where user_id < 0 and approved = 1 and lower(trim(email)) = ?",
+        vec![email.to_lowercase().into()],
+    )
+    .await?;
+    Ok(count > 0)
+}
+
+fn is_valid_email(email: &str) -> bool {
+    let Some((local, domain)) = email.split_once('@') else {
+        return false;
+    };
+    email.len() <= 254
+        && !local.is_empty()
+        && !domain.contains('@')
+        && !email.chars().any(char::is_whitespace)
+        && domain.contains('.')
+        && !domain.starts_with('.')
+        && !domain.ends_with('.')
+        && !domain.contains("..")
+}
+
 #[derive(Deserialize)]
 struct RemoveQuery {
     id: i32,
@@ -164,23 +230,35 @@ async fn remove(
     let comment_model = comment::Entity::find_by_id(query.id)
         .one(db.get_ref())
         .await
-        .map_err(|_| AppError::system_exception())?
-        .ok_or_else(|| AppError::fail("评论不存在"))?;
+        .map_err(|_| AppError::system_exception())?;
+    let Some(comment_model) = comment_model else {
+        return Ok(HttpResponse::Ok().json(ResponseDto::<()>::success(None)));
+    };
 
     let memo_item = memo::Entity::find_by_id(comment_model.memo_id)
         .one(db.get_ref())
         .await
-        .map_err(|_| AppError::system_exception())?
-        .ok_or_else(|| AppError::fail("memo不存在"))?;
+        .map_err(|_| AppError::system_exception())?;
 
-    if user_model.role.as_deref() != Some("ADMIN") && memo_item.user_id != user_model.id {
+    // memo已不存在时只有管理员可以清理遗留的评论
+    let memo_owner = memo_item.map(|m| m.user_id);
+    if user_model.role.as_deref() != Some("ADMIN") && memo_owner != Some(user_model.id) {
         return Err(AppError::fail("只能删除自己发的memo的评论"));
     }
 
     let memo_id = comment_model.memo_id;
     db.transaction::<_, (), AppError>(|txn| {
         Box::pin(async move {
-            comment::Entity::delete_by_id(query.id)
+            let deleted = comment::Entity::delete_by_id(query.id)
+                .exec(txn)
+                .await
+                .map_err(|_| AppError::system_exception())?;
+            // 并发删除同一条评论时只有真正删掉的那次扣减计数
+            if deleted.rows_affected == 0 {
+                return Ok(());
+            }
+            comment_relation::Entity::delete_many()
+                .filter(comment_relation::Column::CommentId.eq(query.id))
                 .exec(txn)
                 .await
                 .map_err(|_| AppError::system_exception())?;
@@ -202,11 +280,14 @@ async fn remove(
 async fn query(
     db: web::Data<DatabaseConnection>,
     auth: OptionalAuthUser,
-    payload: web::Json<QueryCommentListRequest>,
+    payload: Json<QueryCommentListRequest>,
 ) -> Result<HttpResponse, AppError> {
     let page = payload.page.max(1);
     let size = payload.size.max(1);
-    let offset = (page - 1) * size;
+    let offset = (page - 1).saturating_mul(size);
+    let max_offset = sys_config_store::get_max_page_offset(db.get_ref())
+        .await
+        .map_err(|_| AppError::system_exception())?;
 
     let mut where_sql = vec!["memo_id = ?".to_string()];
     let values: Vec<sea_orm::Value> = vec![payload.memo_id.into()];
@@ -216,19 +297,41 @@ async fn query(
         where_sql.push("(user_id > 0 or (user_id < 0 and approved = 1))".to_string());
     }
 
+    let order_by = match payload.sort.as_deref().unwrap_or("") {
+        "" => "created",
+        "top" => "like_count desc, created asc",
+        _ => return Err(AppError::param_error("sort")),
+    };
+
     let where_clause = where_sql.join(" and ");
     let count_sql = format!("select count(1) as cnt from t_comment where {}", where_clause);
     let total = query_count(db.get_ref(), &count_sql, values.clone()).await?;
 
     let list_sql = format!(
-        "select * from t_comment where {} order by created limit {},{}",
-        where_clause, offset, size
+        "select * from t_comment where {} order by {} limit {},{}",
+        where_clause, order_by, offset, size
     );
-    let rows = query_all(db.get_ref(), &list_sql, values).await?;
+    // 超过MAX_PAGE_OFFSET的页直接返回空列表,避免深分页扫描
+    let rows = if max_offset > 0 && offset > max_offset {
+        Vec::new()
+    } else {
+        query_all(db.get_ref(), &list_sql, values).await?
+    };
 
+    let render = payload.render.unwrap_or(false);
     let list = rows
         .into_iter()
         .map(|row| CommentDto {
+            content_html: render.then(|| {
+                let content: String = row.try_get("", "content").unwrap_or_default();
+                render::comment_to_safe_html(
+                    &content,
+                    &stored_mentions(
+                        row.try_get("", "mentioned").ok(),
+                        row.try_get("", "mentioned_user_id").ok(),
+                    ),
+                )
+            }),
             id: row.try_get("", "id").unwrap_or(0),
             memo_id: row.try_get("", "memo_id").unwrap_or(0),
             user_name: row.try_get("", "user_name").unwrap_or_default(),
@@ -241,6 +344,7 @@ async fn query(
             email: row.try_get("", "email").ok(),
             link: row.try_get("", "link").ok(),
             approved: row.try_get("", "approved").unwrap_or(0),
+            like_count: row.try_get("", "like_count").unwrap_or(0),
         })
         .collect::<Vec<_>>();
 
@@ -284,14 +388,159 @@ async fn memo_approve(
     Ok(HttpResponse::Ok().json(ResponseDto::<()>::success(None)))
 }
 
-async fn parse_mentions(
+#[derive(Deserialize)]
+struct LikeQuery {
+    id: i32,
+}
+
+async fn like(
+    db: web::Data<DatabaseConnection>,
+    auth: AuthUser,
+    query: web::Query<LikeQuery>,
+) -> Result<HttpResponse, AppError> {
+    require_open_like(db.get_ref()).await?;
+    comment::Entity::find_by_id(query.id)
+        .one(db.get_ref())
+        .await
+        .map_err(|_| AppError::system_exception())?
+        .ok_or_else(|| AppError::fail("评论不存在"))?;
+
+    let comment_id = query.id;
+    let user_id = auth.user_id;
+    db.transaction::<_, (), AppError>(|txn| {
+        Box::pin(async move {
+            let count = query_count(
+                txn,
+                "select count(1) as cnt from t_comment_relation where comment_id = ? and user_id = ? and fav_type = 'LIKE'",
+                vec![comment_id.into(), user_id.into()],
+            )
+            .await?;
+            if count > 0 {
+                return Err(AppError::fail("数据已存在"));
+            }
+
+            let relation = comment_relation::ActiveModel {
+                comment_id: Set(comment_id),
+                user_id: Set(user_id),
+                fav_type: Set("LIKE".to_string()),
+                created: Set(Some(Utc::now())),
+                ..Default::default()
+            };
+            relation
+                .insert(txn)
+                .await
+                .map_err(|_| AppError::system_exception())?;
+
+            exec_sql(
+                txn,
+                "update t_comment set like_count = coalesce(like_count, 0) + 1 where id = ?",
+                vec![comment_id.into()],
+            )
+            .await?;
+            Ok(())
+        })
+    })
+    .await
+    .map_err(map_tx_error)?;
+
+    Ok(HttpResponse::Ok().json(ResponseDto::<()>::success(None)))
+}
+
+async fn unlike(
+    db: web::Data<DatabaseConnection>,
+    auth: AuthUser,
+    query: web::Query<LikeQuery>,
+) -> Result<HttpResponse, AppError> {
+    require_open_like(db.get_ref()).await?;
+
+    let comment_id = query.id;
+    let user_id = auth.user_id;
+    db.transaction::<_, (), AppError>(|txn| {
+        Box::pin(async move {
+            let result = comment_relation::Entity::delete_many()
+                .filter(comment_relation::Column::CommentId.eq(comment_id))
+                .filter(comment_relation::Column::UserId.eq(user_id))
+                .filter(comment_relation::Column::FavType.eq("LIKE"))
+                .exec(txn)
+                .await
+                .map_err(|_| AppError::system_exception())?;
+            if result.rows_affected > 0 {
+                exec_sql(
+                    txn,
+                    "update t_comment set like_count = like_count - 1 where id = ? and like_count >= 1",
+                    vec![comment_id.into()],
+                )
+                .await?;
+            }
+            Ok(())
+        })
+    })
+    .await
+    .map_err(map_tx_error)?;
+
+    Ok(HttpResponse::Ok().json(ResponseDto::<()>::success(None)))
+}
+
+async fn require_open_like(db: &DatabaseConnection) -> Result<(), AppError> {
+    let open_like = sys_config_store::get_boolean(db, "OPEN_LIKE")
+        .await
+        .map_err(|_| AppError::system_exception())?;
+    if !open_like {
+        return Err(AppError::fail("禁止点赞"));
+    }
+    Ok(())
+}
+
+/// Resolves `@displayName` mentions plus the explicit `@[name](id)` form, which picks one user
+/// even if display names ever collide. A plain name matching several users mentions all of them.
+pub(crate) async fn parse_mentions(
     db: &DatabaseConnection,
     content: &str,
 ) -> Result<(Option<String>, Option<String>), AppError> {
     let mut names = Vec::new();
     let mut ids = Vec::new();
+    for u in resolve_mentions(db, content).await? {
+        ids.push(u.id.to_string());
+        names.push(u.display_name.unwrap_or(u.username));
+    }
+
+    let names_join = if names.is_empty() { None } else { Some(names.join(",")) };
+    let ids_join = if ids.is_empty() {
+        Some("".to_string())
+    } else {
+        Some(format!("#{}", ids.join(",#")) + ",")
+    };
+
+    Ok((names_join, ids_join))
+}
+
+/// Pairs the stored `mentioned` names with `mentioned_user_id` (`#1,#2,`), which
+/// [`parse_mentions`] writes in the same order.
+fn stored_mentions(names: Option<String>, ids: Option<String>) -> Vec<(i32, String)> {
+    let ids = ids.unwrap_or_default();
+    let names = names.unwrap_or_default();
+    ids.split(',')
+        .filter_map(|id| id.trim().trim_start_matches('#').parse::<i32>().ok())
+        .zip(names.split(',').map(str::to_string))
+        .collect()
+}
+
+/// The distinct users mentioned in `content`, in order of first mention; also backs the memo
+/// editor preview so highlighting matches what gets stored.
+pub(crate) async fn resolve_mentions(db: &DatabaseConnection, content: &str) -> Result<Vec<user::Model>, AppError> {
+    let (explicit_ids, rest) = split_explicit_mentions(content);
+    let mut matched = Vec::new();
+    if !explicit_ids.is_empty() {
+        matched.extend(
+            user::Entity::find()
+                .filter(user::Column::Id.is_in(explicit_ids))
+                .all(db)
+                .await
+                .map_err(|_| AppError::system_exception())?,
+        );
+    }
 
-    for token in content.split_whitespace() {
+    for token in rest.split_whitespace() {
         let Some(mut username) = token.strip_prefix('@').map(str::to_string) else {
             continue;
         };
@@ -304,25 +553,53 @@ async fn parse_mentions(
         if username.is_empty() {
             continue;
         }
-        let user_model = user::Entity::find()
-            .filter(user::Column::DisplayName.eq(username.clone()))
-            .one(db)
-            .await
-            .map_err(|_| AppError::system_exception())?;
-        if let Some(u) = user_model {
-            names.push(u.display_name.unwrap_or(u.username));
-            ids.push(u.id.to_string());
-        }
+        matched.extend(
+            user::Entity::find()
+                .filter(user::Column::DisplayName.eq(username.clone()))
+                .order_by_asc(user::Column::Id)
+                .all(db)
+                .await
+                .map_err(|_| AppError::system_exception())?,
+        );
     }
 
-    let names_join = if names.is_empty() { None } else { Some(names.join(",")) };
-    let ids_join = if ids.is_empty() {
-        Some("".to_string())
-    } else {
-        Some(format!("#{}", ids.join(",#")) + ",")
-    };
+    let mut users: Vec<user::Model> = Vec::new();
+    for u in matched {
+        if !users.iter().any(|seen| seen.id == u.id) {
+            users.push(u);
+        }
+    }
+    Ok(users)
+}
 
-    Ok((names_join, ids_join))
+/// Pulls `@[name](id)` mentions out of `content`, returning their ids and the remaining text.
+fn split_explicit_mentions(content: &str) -> (Vec<i32>, String) {
+    let mut ids = Vec::new();
+    let mut rest = String::with_capacity(content.len());
+    let mut remaining = content;
+    while let Some(start) = remaining.find("@[") {
+        let after = &remaining[start + 2..];
+        let parsed = after.find("](").and_then(|close| {
+            let tail = &after[close + 2..];
+            let end = tail.find(')')?;
+            let id = tail[..end].trim().parse::<i32>().ok()?;
+            Some((id, start + 2 + close + 2 + end + 1))
+        });
+        match parsed {
+            Some((id, consumed)) => {
+                ids.push(id);
+                rest.push_str(&remaining[..start]);
+                rest.push(' ');
+                remaining = &remaining[consumed..];
+            }
+            None => {
+                rest.push_str(&remaining[..start + 2]);
+                remaining = &remaining[start + 2..];
+            }
+        }
+    }
+    rest.push_str(remaining);
+    (ids, rest)
 }
 
 async fn exec_sql<C: ConnectionTrait>(
@@ -357,7 +634,7 @@ async fn query_count<C: ConnectionTrait>(
 ) -> Result<i64, AppError> {
     let row = query_all(db, sql, values).await?;
     Ok(row
-        .get(0)
+        .first()
         .and_then(|r| r.try_get("", "cnt").ok())
         .unwrap_or(0))
 }
@@ -399,3 +676,105 @@ fn parse_db_datetime(input: String) -> Option<NaiveDateTime> {
             .map(|dt| dt.naive_utc())
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{auth_user, TestEnv, ADMIN_ID};
+
+    async fn comment_count(env: &TestEnv, memo_id: i32) -> i32 {
+        memo::Entity::find_by_id(memo_id).one(&env.db).await.unwrap().unwrap().comment_count.unwrap_or(0)
+    }
+
+    #[actix_web::test]
+    async fn remove_is_idempotent() {
+        let env = TestEnv::new().await;
+        env.exec(
+            "insert into t_memo (id, user_id, content, comment_count) values (1, ?, 'memo', 2)",
+            vec![ADMIN_ID.into()],
+        )
+        .await;
+        env.exec(
+            "insert into t_comment (id, memo_id, content, user_id, user_name) values (10, 1, 'hi', ?, 'admin'), \
+             (11, 1, 'there', ?, 'admin')",
+            vec![ADMIN_ID.into(), ADMIN_ID.into()],
+        )
+        .await;
+        let db = web::Data::new(env.db.clone());
+        let remove_id = |id: i32| remove(db.clone(), auth_user(ADMIN_ID, "ADMIN"), web::Query(RemoveQuery { id }));
+
+        remove_id(10).await.unwrap();
+        assert_eq!(comment_count(&env, 1).await, 1);
+        remove_id(10).await.unwrap();
+        assert_eq!(comment_count(&env, 1).await, 1);
+        assert!(comment::Entity::find_by_id(11).one(&env.db).await.unwrap().is_some());
+    }
+
+    #[actix_web::test]
+    async fn remove_missing_comment_succeeds() {
+        let env = TestEnv::new().await;
+        let db = web::Data::new(env.db.clone());
+        let res = remove(db, auth_user(ADMIN_ID, "ADMIN"), web::Query(RemoveQuery { id: 404 }))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), actix_web::http::StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn approved_email_skips_the_queue_next_time() {
+        let env = TestEnv::new().await;
+        env.exec(
+            "insert into t_memo (id, user_id, content, enable_comment) values (1, ?, 'memo', 1)",
+            vec![ADMIN_ID.into()],
+        )
+        .await;
+        for key in ["OPEN_COMMENT", "ANONYMOUS_COMMENT", "COMMENT_APPROVED", "AUTO_APPROVE_KNOWN_EMAIL"] {
+            env.set_config(key, "true").await;
+        }
+        let db = web::Data::new(env.db.clone());
+        let comment_as = |email: &str| {
+            let payload = SaveCommentRequest {
+                content: "nice".to_string(),
+                memo_id: 1,
+                username: Some("amy".to_string()),
+                email: Some(email.to_string()),
+                link: None,
+            };
+            add(db.clone(), OptionalAuthUser(None), Json(payload))
+        };
+        let approved = || async {
+            let mut rows = comment::Entity::find().all(&env.db).await.unwrap();
+            rows.sort_by_key(|c| c.id);
+            rows.into_iter().map(|c| c.approved.unwrap_or(0)).collect::<Vec<_>>()
+        };
+
+        comment_as("Amy@Example.com").await.unwrap();
+        assert_eq!(approved().await, vec![0]);
+        env.exec("update t_comment set approved = 1", vec![]).await;
+
+        comment_as(" amy@example.com ").await.unwrap();
+        comment_as("eve@example.com").await.unwrap();
+        assert_eq!(approved().await, vec![1, 1, 0]);
+    }
+
+    #[actix_web::test]
+    async fn colliding_display_names_mention_every_match() {
+        let env = TestEnv::new().await;
+        // 旧库可能没有 display_name 唯一索引
+        env.exec("drop index t_user_T_USER_UNI1", vec![]).await;
+        env.exec(
+            "insert into t_user (id, username, password_hash, display_name) values \
+             (30, 'tom3', 'x', 'tom'), (20, 'tom2', 'x', 'tom'), (40, 'amy', 'x', 'amy')",
+            vec![],
+        )
+        .await;
+
+        let ids = |users: Vec<user::Model>| users.into_iter().map(|u| u.id).collect::<Vec<_>>();
+        assert_eq!(ids(resolve_mentions(&env.db, "hi @tom and @amy").await.unwrap()), vec![20, 30, 40]);
+        assert_eq!(ids(resolve_mentions(&env.db, "hi @[tom](30)").await.unwrap()), vec![30]);
+
+        let (names, mentioned) = parse_mentions(&env.db, "@tom, @tom!").await.unwrap();
+        assert_eq!(names.as_deref(), Some("tom,tom"));
+        assert_eq!(mentioned.as_deref(), Some("#20,#30,"));
+    }
+}
@@ -1,13 +1,72 @@
+use std::sync::RwLock;
+
 use actix_web::{
     body::{EitherBody, MessageBody},
     dev::{ServiceRequest, ServiceResponse},
     http::Method,
-    http::header::{HeaderName, HeaderValue},
+    http::header::{self, HeaderName, HeaderValue},
     middleware::Next,
+    web,
     Error,
     HttpResponse,
 };
- 
+use log::warn;
+use sea_orm::DatabaseConnection;
+
+use crate::config::AppConfig;
+use crate::sys_config as sys_config_store;
+
+/// Origins allowed by `CORS_DOMAIN_LIST` plus `MBLOG_FRONT_DOMAIN`, loaded at startup and
+/// reloaded whenever an admin saves the list. Empty means any origin (`*`).
+pub struct CorsOrigins {
+    front_domain: String,
+    origins: RwLock<Vec<String>>,
+}
+
+impl CorsOrigins {
+    pub async fn load(db: &DatabaseConnection, config: &AppConfig) -> Self {
+        let cors = CorsOrigins {
+            front_domain: config.safe_domain.clone(),
+            origins: RwLock::new(Vec::new()),
+        };
+        cors.reload(db).await;
+        cors
+    }
+
+    pub async fn reload(&self, db: &DatabaseConnection) {
+        let list = match sys_config_store::get_cors_domain_list(db).await {
+            Ok(list) => list.unwrap_or_default(),
+            Err(err) => {
+                warn!("load CORS_DOMAIN_LIST failed: {}", err);
+                return;
+            }
+        };
+        let mut origins: Vec<String> = list
+            .split(',')
+            .chain(std::iter::once(self.front_domain.as_str()))
+            .map(normalize_origin)
+            .filter(|s| !s.is_empty())
+            .collect();
+        origins.sort();
+        origins.dedup();
+        *self.origins.write().unwrap_or_else(|e| e.into_inner()) = origins;
+    }
+
+    /// Echoes the request's `Origin` when it is listed, `*` when no list is configured.
+    fn allow_origin(&self, req: &ServiceRequest) -> Option<HeaderValue> {
+        let origins = self.origins.read().unwrap_or_else(|e| e.into_inner());
+        if origins.is_empty() {
+            return Some(HeaderValue::from_static("*"));
+        }
+        let origin = req.headers().get(header::ORIGIN)?;
+        let normalized = normalize_origin(origin.to_str().ok()?);
+        origins.contains(&normalized).then(|| origin.clone())
+    }
+}
+
+fn normalize_origin(origin: &str) -> String {
+    origin.trim().trim_end_matches('/').to_ascii_lowercase()
+}
 
 pub async fn cors_handler<B>(
     req: ServiceRequest,
@@ -16,6 +75,10 @@ pub async fn cors_handler<B>(
 where
     B: MessageBody,
 {
+    let allow_origin = match req.app_data::<web::Data<CorsOrigins>>() {
+        Some(cors) => cors.allow_origin(&req),
+        None => Some(HeaderValue::from_static("*")),
+    };
     let mut res = if req.method() == Method::OPTIONS {
         let res = HttpResponse::Ok().finish().map_into_right_body();
         req.into_response(res)
@@ -24,10 +87,23 @@ where
     };
 
     let headers = res.headers_mut();
-    headers.insert(
-        HeaderName::from_static("access-control-allow-origin"),
-        HeaderValue::from_static("*"),
-    );
+    // 配置了域名列表时只回显匹配的 Origin,不匹配则不带该头,由浏览器拒绝
+    match allow_origin {
+        Some(origin) if origin == "*" => {
+            headers.insert(HeaderName::from_static("access-control-allow-origin"), origin);
+        }
+        Some(origin) => {
+            headers.insert(HeaderName::from_static("access-control-allow-origin"), origin);
+            headers.insert(
+                HeaderName::from_static("access-control-allow-credentials"),
+                HeaderValue::from_static("true"),
+            );
+            headers.append(header::VARY, HeaderValue::from_static("Origin"));
+        }
+        None => {
+            headers.append(header::VARY, HeaderValue::from_static("Origin"));
+        }
+    }
     headers.insert(
         HeaderName::from_static("access-control-allow-methods"),
         HeaderValue::from_static("POST, PUT, GET, OPTIONS, DELETE"),
@@ -40,14 +116,17 @@ where
         HeaderName::from_static("access-control-allow-headers"),
         HeaderValue::from_static("Origin, X-Requested-With, Content-Type, Accept, token"),
     );
-    headers.insert(
-        HeaderName::from_static("cache-control"),
-        HeaderValue::from_static("no-cache, no-store, must-revalidate"),
-    );
-    headers.insert(
-        HeaderName::from_static("pragma"),
-        HeaderValue::from_static("no-cache"),
-    );
+    // 资源下载等自己设置了缓存策略的响应保持不变
+    if !headers.contains_key("cache-control") {
+        headers.insert(
+            HeaderName::from_static("cache-control"),
+            HeaderValue::from_static("no-cache, no-store, must-revalidate"),
+        );
+        headers.insert(
+            HeaderName::from_static("pragma"),
+            HeaderValue::from_static("no-cache"),
+        );
+    }
 
     Ok(res)
 }
@@ -1,27 +1,49 @@
-use actix_web::{web, HttpResponse};
+use actix_multipart::Multipart;
+use actix_web::{http::header, web, HttpRequest, HttpResponse};
 use chrono::{DateTime, Duration, NaiveDateTime, SecondsFormat, Utc};
-use log::{debug, error};
+use futures_util::StreamExt;
+use log::{debug, error, warn};
 use sea_orm::{
-    ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait, QueryFilter,
+    ActiveModelTrait, ColumnTrait, Condition, ConnectionTrait, DatabaseConnection, DbBackend, EntityTrait, PaginatorTrait, QueryFilter, QueryOrder, QuerySelect,
     Set, Statement, TransactionTrait, TransactionError,
 };
 use serde::{Deserialize, Serialize};
 
-use crate::auth::{AuthUser, OptionalAuthUser};
-use crate::entity::{comment, memo, resource, tag, user, user_memo_relation};
+use crate::auth::{self, AuthUser, OptionalAuthUser};
+use crate::config::AppConfig;
+use crate::entity::{comment, memo, resource, user, user_memo_relation};
 use crate::error::AppError;
+use crate::importer;
+use crate::origin::{client_ip, RequestOrigin};
+use crate::render;
+use crate::request::Json;
 use crate::response::ResponseDto;
 use crate::sys_config as sys_config_store;
+use crate::unfurl;
+use crate::view_counter;
+use crate::webhook;
 
 pub fn config(cfg: &mut web::ServiceConfig) {
     cfg.service(web::resource("/save").route(web::post().to(save)))
         .service(web::resource("/update").route(web::post().to(update)))
+        .service(web::resource("/patch").route(web::post().to(patch)))
+        .service(web::resource("/toggleComment").route(web::post().to(toggle_comment)))
+        .service(web::resource("/shareLink").route(web::post().to(share_link)))
         .service(web::resource("/remove").route(web::post().to(remove)))
+        .service(web::resource("/emptyTrash").route(web::post().to(empty_trash)))
+        .service(web::resource("/batchRemove").route(web::post().to(batch_remove)))
         .service(web::resource("/setPriority").route(web::post().to(set_priority)))
+        .service(web::resource("/normalizePriority").route(web::post().to(normalize_priority)))
         .service(web::resource("/list").route(web::post().to(list)))
         .service(web::resource("/{id:\\d+}").route(web::post().to(get)))
+        .service(web::resource("/batchGet").route(web::post().to(batch_get)))
         .service(web::resource("/statistics").route(web::post().to(statistics)))
-        .service(web::resource("/relation").route(web::post().to(relation)));
+        .service(web::resource("/siteStats").route(web::get().to(site_stats)))
+        .service(web::resource("/relation").route(web::post().to(relation)))
+        .service(web::resource("/random").route(web::post().to(random)))
+        .service(web::resource("/mentions").route(web::post().to(mentions)))
+        .service(web::resource("/parsePreview").route(web::post().to(parse_preview)))
+        .service(web::resource("/importExternal").route(web::post().to(import_external)));
 }
 
 #[derive(Deserialize)]
@@ -30,9 +52,34 @@ struct SaveMemoRequest {
     id: Option<i32>,
     content: Option<String>,
     public_ids: Option<Vec<String>>,
+    #[serde(default, deserialize_with = "crate::request::trimmed")]
     visibility: Option<String>,
     enable_comment: Option<bool>,
+    #[serde(default, deserialize_with = "crate::request::trimmed")]
     source: Option<String>,
+    /// `markdown`/`plaintext`/`html`; new memos default to markdown.
+    #[serde(default, deserialize_with = "crate::request::trimmed")]
+    content_format: Option<String>,
+    /// `updated` of the memo as the client loaded it; update is refused if it changed since.
+    #[serde(default, deserialize_with = "crate::request::trimmed")]
+    if_updated_at: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PatchMemoRequest {
+    id: Option<i32>,
+    #[serde(default, deserialize_with = "crate::request::trimmed")]
+    visibility: Option<String>,
+    enable_comment: Option<bool>,
+    #[serde(default, deserialize_with = "crate::request::trimmed")]
+    source: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ToggleCommentRequest {
+    id: i32,
+    enable: bool,
 }
 
 #[derive(Deserialize)]
@@ -49,6 +96,11 @@ struct ListMemoRequest {
     liked: Option<bool>,
     commented: Option<bool>,
     mentioned: Option<bool>,
+    order_by: Option<String>,
+    preview_length: Option<usize>,
+    /// Keyset paging: `nextCursor` of the previous page, or `""` for the first page. Replaces
+    /// `page`; only valid with the default newest-first order and ignores pinning.
+    cursor: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -57,6 +109,8 @@ struct ListMemoResponse {
     items: Vec<MemoDto>,
     total: i64,
     total_page: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_cursor: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -83,6 +137,11 @@ struct MemoDto {
     liked: i32,
     resources: Vec<ResourceDto>,
     source: Option<String>,
+    source_title: Option<String>,
+    source_image: Option<String>,
+    title: Option<String>,
+    content_format: Option<String>,
+    truncated: bool,
 }
 
 #[derive(Serialize)]
@@ -90,10 +149,12 @@ struct MemoDto {
 struct ResourceDto {
     public_id: String,
     url: String,
+    thumbnail_url: Option<String>,
     file_type: Option<String>,
     suffix: Option<String>,
     storage_type: Option<String>,
     file_name: Option<String>,
+    alt: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -112,6 +173,15 @@ struct StatisticsResponse {
     items: Vec<StatisticsItem>,
 }
 
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SiteStatsResponse {
+    total_memos: i64,
+    total_tags: i64,
+    total_users: i64,
+    since: Option<String>,
+}
+
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
 struct StatisticsItem {
@@ -119,6 +189,12 @@ struct StatisticsItem {
     total: i64,
 }
 
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RandomMemoRequest {
+    user_id: Option<i32>,
+}
+
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct MemoRelationRequest {
@@ -129,25 +205,42 @@ struct MemoRelationRequest {
 
 async fn save(
     db: web::Data<DatabaseConnection>,
+    config: web::Data<AppConfig>,
     auth: AuthUser,
-    payload: web::Json<SaveMemoRequest>,
+    payload: Json<SaveMemoRequest>,
 ) -> Result<HttpResponse, AppError> {
-    let content = payload.content.clone().unwrap_or_default();
+    let max_blank_lines = load_max_blank_lines(db.get_ref()).await?;
+    let content = render::normalize_content(&payload.content.clone().unwrap_or_default(), max_blank_lines);
     let public_ids = payload.public_ids.clone().unwrap_or_default();
-    check_content_and_resource(&content, &public_ids)?;
-
-    let tags = parse_tags(&content);
+    check_content_and_resource(&content, &public_ids, load_length_bounds(db.get_ref()).await?)?;
+    validate_public_ids(db.get_ref(), auth.user_id, None, &public_ids).await?;
+    check_memo_quota(db.get_ref(), &auth).await?;
+    let content_format = check_content_format(payload.content_format.as_deref())?;
+
+    let parsed = parse_memo_content(
+        &content,
+        load_title_mode(db.get_ref()).await?,
+        load_strip_tags(db.get_ref()).await?,
+    );
+    let mentioned_user_id = parse_memo_mentions(db.get_ref(), &parsed.content).await?;
+    let tags = parsed.tags;
     let visibility = payload
         .visibility
         .clone()
-        .filter(|v| !v.trim().is_empty())
         .or_else(|| Some("PUBLIC".to_string()));
+    let enable_comment = match payload.enable_comment {
+        Some(enable) => enable,
+        None => default_enable_comment(db.get_ref(), auth.user_id).await?,
+    };
     let memo_model = memo::ActiveModel {
         user_id: Set(auth.user_id),
         tags: Set(Some(format_tags(&tags))),
         visibility: Set(visibility),
-        enable_comment: Set(Some(if payload.enable_comment.unwrap_or(false) { 1 } else { 0 })),
-        content: Set(Some(replace_first_line(&content, &tags).trim().to_string())),
+        enable_comment: Set(Some(if enable_comment { 1 } else { 0 })),
+        content: Set(Some(parsed.content)),
+        title: Set(parsed.title),
+        content_format: Set(Some(content_format.unwrap_or(CONTENT_FORMAT_MARKDOWN).to_string())),
+        mentioned_user_id: Set(mentioned_user_id),
         created: Set(Some(Utc::now())),
         updated: Set(Some(Utc::now())),
         source: Set(payload.source.clone()),
@@ -173,6 +266,7 @@ async fn save(
                     attach_resources(txn, inserted.id, &public_ids_clone).await?;
                     debug!("memo resources attached id={}", inserted.id);
                 }
+                webhook::enqueue(txn, inserted.id).await?;
                 Ok(inserted)
             })
         })
@@ -180,7 +274,10 @@ async fn save(
         .map_err(map_tx_error)?;
 
     let memo_id = result.id;
-    notify_webhook_async(db.get_ref().clone(), memo_id);
+    webhook::deliver_pending_async(db.get_ref().clone(), config.webhook_max_attempts);
+    if let Some(source) = result.source.filter(|s| !s.trim().is_empty()) {
+        unfurl::spawn(db.get_ref().clone(), memo_id, source);
+    }
 
     Ok(HttpResponse::Ok().json(ResponseDto::success(Some(memo_id))))
 }
@@ -188,12 +285,24 @@ async fn save(
 async fn update(
     db: web::Data<DatabaseConnection>,
     auth: AuthUser,
-    payload: web::Json<SaveMemoRequest>,
+    payload: Json<SaveMemoRequest>,
 ) -> Result<HttpResponse, AppError> {
     let id = payload.id.ok_or_else(|| AppError::param_error("memoID"))?;
-    let content = payload.content.clone().unwrap_or_default();
+    let max_blank_lines = load_max_blank_lines(db.get_ref()).await?;
+    let content = render::normalize_content(&payload.content.clone().unwrap_or_default(), max_blank_lines);
     let public_ids = payload.public_ids.clone().unwrap_or_default();
-    check_content_and_resource(&content, &public_ids)?;
+    check_content_and_resource(&content, &public_ids, load_length_bounds(db.get_ref()).await?)?;
+    validate_public_ids(db.get_ref(), auth.user_id, Some(id), &public_ids).await?;
+    let content_format = check_content_format(payload.content_format.as_deref())?;
+    let if_updated_at = payload
+        .if_updated_at
+        .as_deref()
+        .map(|v| {
+            DateTime::parse_from_rfc3339(v)
+                .map(|dt| dt.timestamp_millis())
+                .map_err(|_| AppError::param_error("ifUpdatedAt"))
+        })
+        .transpose()?;
 
     let exist = memo::Entity::find_by_id(id)
         .one(db.get_ref())
@@ -201,36 +310,61 @@ async fn update(
         .map_err(|_| AppError::system_exception())?
         .ok_or_else(|| AppError::fail("memo不存在"))?;
 
-    let tags = parse_tags(&content);
+    let parsed = parse_memo_content(
+        &content,
+        load_title_mode(db.get_ref()).await?,
+        load_strip_tags(db.get_ref()).await?,
+    );
+    let mentioned_user_id = parse_memo_mentions(db.get_ref(), &parsed.content).await?;
+    let tags = parsed.tags;
     let old_tags = split_tags(exist.tags.clone());
 
     let visibility = payload
         .visibility
         .clone()
-        .filter(|v| !v.trim().is_empty())
         .or_else(|| exist.visibility.clone());
     let enable_comment = payload
         .enable_comment
         .map(|v| if v { 1 } else { 0 })
         .or(exist.enable_comment);
 
-    let memo_model = memo::ActiveModel {
+    let source = payload.source.clone().or(exist.source.clone());
+    let source_changed = source != exist.source;
+    let mut memo_model = memo::ActiveModel {
         id: Set(id),
         tags: Set(Some(format_tags(&tags))),
-        content: Set(Some(replace_first_line(&content, &tags).trim().to_string())),
+        content: Set(Some(parsed.content)),
+        title: Set(parsed.title),
+        content_format: Set(content_format.map(str::to_string).or(exist.content_format.clone())),
+        mentioned_user_id: Set(mentioned_user_id),
         enable_comment: Set(enable_comment),
         updated: Set(Some(Utc::now())),
         visibility: Set(visibility),
         created: Set(exist.created),
-        source: Set(payload.source.clone().or(exist.source.clone())),
+        source: Set(source.clone()),
         ..Default::default()
     };
+    if source_changed {
+        memo_model.source_title = Set(None);
+        memo_model.source_image = Set(None);
+    }
 
     db.transaction::<_, (), AppError>(|txn| {
         let tags_clone = tags.clone();
         let old_tags_clone = old_tags.clone();
         let public_ids_clone = public_ids.clone();
         Box::pin(async move {
+            // 在事务里重新读取,避免检查和写入之间被其他请求改掉
+            if let Some(if_updated_at) = if_updated_at {
+                let current = memo::Entity::find_by_id(id)
+                    .one(txn)
+                    .await
+                    .map_err(|_| AppError::system_exception())?
+                    .and_then(|m| m.updated);
+                if current.is_some_and(|updated| updated.timestamp_millis() > if_updated_at) {
+                    return Err(AppError::fail("memo已被其他人修改"));
+                }
+            }
             memo::Entity::update(memo_model)
                 .exec(txn)
                 .await
@@ -247,6 +381,90 @@ async fn update(
     .await
     .map_err(map_tx_error)?;
 
+    if source_changed && let Some(source) = source.filter(|s| !s.trim().is_empty()) {
+        unfurl::spawn(db.get_ref().clone(), id, source);
+    }
+    Ok(HttpResponse::Ok().json(ResponseDto::<()>::success(None)))
+}
+
+/// Sets `enable_comment` on one memo and returns the resulting state.
+async fn toggle_comment(
+    db: web::Data<DatabaseConnection>,
+    auth: AuthUser,
+    payload: Json<ToggleCommentRequest>,
+) -> Result<HttpResponse, AppError> {
+    let exist = memo::Entity::find_by_id(payload.id)
+        .one(db.get_ref())
+        .await
+        .map_err(|_| AppError::system_exception())?
+        .ok_or_else(|| AppError::fail("memo不存在"))?;
+    if auth.role.as_deref() != Some("ADMIN") && exist.user_id != auth.user_id {
+        return Err(AppError::fail("不能操作其他人的记录"));
+    }
+
+    let enable_comment = if payload.enable { 1 } else { 0 };
+    if exist.enable_comment != Some(enable_comment) {
+        let memo_model = memo::ActiveModel {
+            id: Set(payload.id),
+            enable_comment: Set(Some(enable_comment)),
+            updated: Set(Some(Utc::now())),
+            ..Default::default()
+        };
+        memo::Entity::update(memo_model)
+            .exec(db.get_ref())
+            .await
+            .map_err(|_| AppError::system_exception())?;
+    }
+    Ok(HttpResponse::Ok().json(ResponseDto::success(Some(payload.enable))))
+}
+
+/// Updates only the supplied flag columns, leaving content, tags and resources untouched.
+async fn patch(
+    db: web::Data<DatabaseConnection>,
+    auth: AuthUser,
+    payload: Json<PatchMemoRequest>,
+) -> Result<HttpResponse, AppError> {
+    let id = payload.id.ok_or_else(|| AppError::param_error("memoID"))?;
+    let exist = memo::Entity::find_by_id(id)
+        .one(db.get_ref())
+        .await
+        .map_err(|_| AppError::system_exception())?
+        .ok_or_else(|| AppError::fail("memo不存在"))?;
+    if auth.role.as_deref() != Some("ADMIN") && exist.user_id != auth.user_id {
+        return Err(AppError::fail("不能操作其他人的记录"));
+    }
+
+    let mut memo_model = memo::ActiveModel {
+        id: Set(id),
+        ..Default::default()
+    };
+    if let Some(visibility) = payload.visibility.as_deref() {
+        if !matches!(visibility, "PUBLIC" | "PROTECT" | "PRIVATE") {
+            return Err(AppError::param_error("visibility"));
+        }
+        memo_model.visibility = Set(Some(visibility.to_string()));
+    }
+    if let Some(enable_comment) = payload.enable_comment {
+        memo_model.enable_comment = Set(Some(if enable_comment { 1 } else { 0 }));
+    }
+    let new_source = payload.source.clone().filter(|s| exist.source.as_ref() != Some(s));
+    if let Some(source) = new_source.clone() {
+        memo_model.source = Set(Some(source));
+        memo_model.source_title = Set(None);
+        memo_model.source_image = Set(None);
+    }
+    if !memo_model.is_changed() {
+        return Ok(HttpResponse::Ok().json(ResponseDto::<()>::success(None)));
+    }
+    memo_model.updated = Set(Some(Utc::now()));
+
+    memo::Entity::update(memo_model)
+        .exec(db.get_ref())
+        .await
+        .map_err(|_| AppError::system_exception())?;
+    if let Some(source) = new_source.filter(|s| !s.trim().is_empty()) {
+        unfurl::spawn(db.get_ref().clone(), id, source);
+    }
     Ok(HttpResponse::Ok().json(ResponseDto::<()>::success(None)))
 }
 
@@ -274,35 +492,317 @@ async fn remove(
     }
 
     let tags = split_tags(memo_item.tags.clone());
+    let resources = resource::Entity::find()
+        .filter(resource::Column::MemoId.eq(memo_id))
+        .all(db.get_ref())
+        .await
+        .map_err(|_| AppError::system_exception())?;
+    let owner_id = memo_item.user_id;
     db.transaction::<_, (), AppError>(|txn| {
         let tags_clone = tags.clone();
-        Box::pin(async move {
-            for tag_name in tags_clone {
-                decrement_tag_count(txn, auth.user_id, &tag_name).await?;
-            }
-            resource::Entity::delete_many()
-                .filter(resource::Column::MemoId.eq(memo_id))
-                .exec(txn)
-                .await
-                .map_err(|_| AppError::system_exception())?;
-            memo::Entity::delete_by_id(memo_id)
-                .exec(txn)
-                .await
-                .map_err(|_| AppError::system_exception())?;
-            comment::Entity::delete_many()
-                .filter(comment::Column::MemoId.eq(memo_id))
-                .exec(txn)
-                .await
-                .map_err(|_| AppError::system_exception())?;
-            Ok(())
-        })
+        Box::pin(async move { delete_memo_rows(txn, memo_id, owner_id, &tags_clone).await })
     })
     .await
     .map_err(map_tx_error)?;
 
+    release_resource_files(db.get_ref(), resources).await;
+
     Ok(HttpResponse::Ok().json(ResponseDto::<()>::success(None)))
 }
 
+/// Hard-deletes a memo with its comments and resource rows and releases its tag counts.
+async fn delete_memo_rows<C: ConnectionTrait>(
+    txn: &C,
+    memo_id: i32,
+    owner_id: i32,
+    tags: &[String],
+) -> Result<(), AppError> {
+    for tag_name in tags {
+        decrement_tag_count(txn, owner_id, tag_name).await?;
+    }
+    resource::Entity::delete_many()
+        .filter(resource::Column::MemoId.eq(memo_id))
+        .exec(txn)
+        .await
+        .map_err(|_| AppError::system_exception())?;
+    memo::Entity::delete_by_id(memo_id)
+        .exec(txn)
+        .await
+        .map_err(|_| AppError::system_exception())?;
+    exec_sql(
+        txn,
+        "delete from t_comment_relation where comment_id in (select id from t_comment where memo_id = ?)",
+        vec![memo_id.into()],
+    )
+    .await?;
+    comment::Entity::delete_many()
+        .filter(comment::Column::MemoId.eq(memo_id))
+        .exec(txn)
+        .await
+        .map_err(|_| AppError::system_exception())?;
+    Ok(())
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ImportSummary {
+    total: usize,
+    imported: usize,
+    skipped: usize,
+    rejected: Vec<ImportRejection>,
+}
+
+/// An export item that was not imported, with the same message saving it would give.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ImportRejection {
+    /// Position of the item in the export file, from 0.
+    index: usize,
+    reason: String,
+}
+
+const IMPORT_MAX_BYTES: usize = 20 * 1024 * 1024;
+const IMPORT_BATCH: usize = 100;
+
+/// Imports a Memos (JSON) or flomo (CSV) export sent as multipart fields `format` and `file`,
+/// keeping the original timestamps and syncing tags. Each item goes through the same length and
+/// `MEMO_MAX_PER_USER` checks as saving; items failing them are listed in `rejected`.
+async fn import_external(
+    db: web::Data<DatabaseConnection>,
+    auth: AuthUser,
+    mut payload: Multipart,
+) -> Result<HttpResponse, AppError> {
+    let mut format = String::new();
+    let mut data: Vec<u8> = Vec::new();
+    while let Some(field) = payload.next().await {
+        let mut field = field.map_err(|_| AppError::fail("上传文件异常"))?;
+        let name = field.name().to_string();
+        let mut buf: Vec<u8> = Vec::new();
+        while let Some(chunk) = field.next().await {
+            let chunk = chunk.map_err(|_| AppError::fail("上传文件异常"))?;
+            if buf.len() + chunk.len() > IMPORT_MAX_BYTES {
+                return Err(AppError::file_size_limit("导入文件不能超过20MB"));
+            }
+            buf.extend_from_slice(&chunk);
+        }
+        match name.as_str() {
+            "format" => format = String::from_utf8_lossy(&buf).trim().to_string(),
+            "file" => data = buf,
+            _ => {}
+        }
+    }
+    let importer = importer::importer_for(&format).ok_or_else(|| AppError::param_error("format"))?;
+    if data.is_empty() {
+        return Err(AppError::param_error("file"));
+    }
+    let items = importer.parse(&data)?;
+
+    let max_blank_lines = load_max_blank_lines(db.get_ref()).await?;
+    let title_mode = load_title_mode(db.get_ref()).await?;
+    let strip_tags = load_strip_tags(db.get_ref()).await?;
+    let bounds = load_length_bounds(db.get_ref()).await?;
+    let enable_comment = default_enable_comment(db.get_ref(), auth.user_id).await?;
+    let mut quota = memo_quota(db.get_ref(), &auth).await?;
+    let total = items.len();
+    let mut models = Vec::new();
+    let mut rejected = Vec::new();
+    for (index, item) in items.into_iter().enumerate() {
+        let content = render::normalize_content(&item.content, max_blank_lines);
+        let checked = check_content_and_resource(&content, &[], bounds).and_then(|_| match quota.as_mut() {
+            Some((max, used)) if *used >= *max => Err(AppError::fail(format!("最多只能发布{}条memo", max))),
+            Some((_, used)) => {
+                *used += 1;
+                Ok(())
+            }
+            None => Ok(()),
+        });
+        if let Err(e) = checked {
+            rejected.push(ImportRejection { index, reason: e.msg().to_string() });
+            continue;
+        }
+        // 和 save 一样按首行模式解析标题和标签,导出文件自带的标签补在后面
+        let parsed = parse_memo_content(&content, title_mode, strip_tags);
+        let mut tags = parsed.tags;
+        for tag in item.tags {
+            let tag = format!("#{}", tag);
+            if !tags.contains(&tag) {
+                tags.push(tag);
+            }
+        }
+        let created = item.created.unwrap_or_else(Utc::now);
+        let visibility = item
+            .visibility
+            .filter(|v| matches!(v.as_str(), "PUBLIC" | "PROTECT" | "PRIVATE"))
+            .unwrap_or_else(|| "PRIVATE".to_string());
+        let model = memo::ActiveModel {
+            user_id: Set(auth.user_id),
+            tags: Set(Some(format_tags(&tags))),
+            visibility: Set(Some(visibility)),
+            enable_comment: Set(Some(if enable_comment { 1 } else { 0 })),
+            content: Set(Some(parsed.content)),
+            title: Set(parsed.title),
+            created: Set(Some(created)),
+            updated: Set(Some(created)),
+            source: Set(Some(format.clone())),
+            ..Default::default()
+        };
+        models.push((model, tags));
+    }
+    let imported = models.len();
+
+    let mut iter = models.into_iter().peekable();
+    while iter.peek().is_some() {
+        let batch: Vec<_> = iter.by_ref().take(IMPORT_BATCH).collect();
+        db.transaction::<_, (), AppError>(|txn| {
+            Box::pin(async move {
+                for (model, tags) in batch {
+                    model.insert(txn).await.map_err(|e| {
+                        error!("import memo insert failed: {}", e);
+                        AppError::system_exception()
+                    })?;
+                    sync_tags_on_save(txn, auth.user_id, &tags).await?;
+                }
+                Ok(())
+            })
+        })
+        .await
+        .map_err(map_tx_error)?;
+    }
+
+    let summary = ImportSummary {
+        total,
+        imported,
+        skipped: total - imported,
+        rejected,
+    };
+    Ok(HttpResponse::Ok().json(ResponseDto::success(Some(summary))))
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct EmptyTrashRequest {
+    user_id: Option<i32>,
+}
+
+const EMPTY_TRASH_BATCH: u64 = 100;
+
+/// Purges the caller's `TRASH` memos (admins may pass `userId`) in bounded batches, each in
+/// its own transaction, and returns how many were deleted.
+async fn empty_trash(
+    db: web::Data<DatabaseConnection>,
+    auth: AuthUser,
+    payload: Option<Json<EmptyTrashRequest>>,
+) -> Result<HttpResponse, AppError> {
+    let payload = payload.map(|p| p.into_inner()).unwrap_or_default();
+    let user_id = match payload.user_id {
+        Some(id) if id != auth.user_id => {
+            if auth.role.as_deref() != Some("ADMIN") {
+                return Err(AppError::fail("不能操作其他人的记录"));
+            }
+            id
+        }
+        _ => auth.user_id,
+    };
+
+    let mut purged: u64 = 0;
+    loop {
+        let batch = memo::Entity::find()
+            .filter(memo::Column::UserId.eq(user_id))
+            .filter(memo::Column::Status.eq("TRASH"))
+            .limit(EMPTY_TRASH_BATCH)
+            .all(db.get_ref())
+            .await
+            .map_err(|_| AppError::system_exception())?;
+        if batch.is_empty() {
+            break;
+        }
+        let ids: Vec<i32> = batch.iter().map(|m| m.id).collect();
+        let resources = resource::Entity::find()
+            .filter(resource::Column::MemoId.is_in(ids))
+            .all(db.get_ref())
+            .await
+            .map_err(|_| AppError::system_exception())?;
+        let count = batch.len() as u64;
+        db.transaction::<_, (), AppError>(|txn| {
+            Box::pin(async move {
+                for memo_item in batch {
+                    let tags = split_tags(memo_item.tags.clone());
+                    delete_memo_rows(txn, memo_item.id, memo_item.user_id, &tags).await?;
+                }
+                Ok(())
+            })
+        })
+        .await
+        .map_err(map_tx_error)?;
+        release_resource_files(db.get_ref(), resources).await;
+        purged += count;
+    }
+
+    Ok(HttpResponse::Ok().json(ResponseDto::success(Some(purged))))
+}
+
+#[derive(Deserialize)]
+struct BatchRemoveRequest {
+    ids: Vec<i32>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BatchRemoveResponse {
+    removed: u64,
+    skipped: Vec<i32>,
+}
+
+const BATCH_REMOVE_MAX: usize = 100;
+
+/// Hard-deletes the listed memos the caller owns (admins: any) in one transaction, like
+/// `remove`. Ids owned by someone else come back in `skipped`; unknown ids are ignored.
+async fn batch_remove(
+    db: web::Data<DatabaseConnection>,
+    auth: AuthUser,
+    payload: Json<BatchRemoveRequest>,
+) -> Result<HttpResponse, AppError> {
+    let mut ids = payload.ids.clone();
+    ids.sort_unstable();
+    ids.dedup();
+    if ids.is_empty() || ids.len() > BATCH_REMOVE_MAX {
+        return Err(AppError::param_error("ids"));
+    }
+
+    let is_admin = auth.role.as_deref() == Some("ADMIN");
+    let (owned, others): (Vec<memo::Model>, Vec<memo::Model>) = memo::Entity::find()
+        .filter(memo::Column::Id.is_in(ids))
+        .all(db.get_ref())
+        .await
+        .map_err(|_| AppError::system_exception())?
+        .into_iter()
+        .partition(|m| is_admin || m.user_id == auth.user_id);
+    let skipped = others.into_iter().map(|m| m.id).collect::<Vec<_>>();
+
+    let removed = owned.len() as u64;
+    if !owned.is_empty() {
+        let resources = resource::Entity::find()
+            .filter(resource::Column::MemoId.is_in(owned.iter().map(|m| m.id)))
+            .all(db.get_ref())
+            .await
+            .map_err(|_| AppError::system_exception())?;
+        db.transaction::<_, (), AppError>(|txn| {
+            Box::pin(async move {
+                for memo_item in owned {
+                    let tags = split_tags(memo_item.tags.clone());
+                    delete_memo_rows(txn, memo_item.id, memo_item.user_id, &tags).await?;
+                }
+                Ok(())
+            })
+        })
+        .await
+        .map_err(map_tx_error)?;
+        release_resource_files(db.get_ref(), resources).await;
+    }
+
+    Ok(HttpResponse::Ok().json(ResponseDto::success(Some(BatchRemoveResponse { removed, skipped }))))
+}
+
 #[derive(Deserialize)]
 struct SetPriorityQuery {
     id: i32,
@@ -326,41 +826,174 @@ async fn set_priority(
         return Err(AppError::fail("不能操作其他人的记录"));
     }
 
-    if query.set {
-        let sql = "update t_memo set priority = ((select max(x.priority) from (select * from t_memo) as x)+1) where id = ?";
-        exec_sql(db.get_ref(), sql, vec![memo_item.id.into()]).await?;
-    } else {
-        exec_sql(db.get_ref(), "update t_memo set priority = 0 where id = ?", vec![memo_item.id.into()]).await?;
+    let id = memo_item.id;
+    let set = query.set;
+    db.transaction::<_, (), AppError>(|txn| {
+        Box::pin(async move {
+            if !set {
+                return exec_sql(txn, "update t_memo set priority = 0 where id = ?", vec![id.into()]).await;
+            }
+            let max = query_one(txn, "select coalesce(max(priority), 0) as max_priority from t_memo", vec![])
+                .await?
+                .and_then(|r| r.try_get::<i64>("", "max_priority").ok())
+                .unwrap_or(0);
+            if max >= PRIORITY_RENORMALIZE_THRESHOLD {
+                renormalize_priorities(txn).await?;
+            }
+            let sql = "update t_memo set priority = ((select max(x.priority) from (select * from t_memo) as x)+1) where id = ?";
+            exec_sql(txn, sql, vec![id.into()]).await
+        })
+    })
+    .await
+    .map_err(map_tx_error)?;
+
+    Ok(HttpResponse::Ok().json(ResponseDto::<()>::success(None)))
+}
+
+/// The `user_id` likes are stored under: the logged-in user, or with `ANONYMOUS_LIKE` on a
+/// negative id derived from the visitor's IP, so each address likes a memo at most once.
+async fn liker_id(
+    db: &DatabaseConnection,
+    config: &AppConfig,
+    auth: &OptionalAuthUser,
+    req: &HttpRequest,
+) -> Result<Option<i32>, AppError> {
+    if let Some(a) = auth.0.as_ref() {
+        return Ok(Some(a.user_id));
+    }
+    let anonymous_like = sys_config_store::get_boolean(db, "ANONYMOUS_LIKE")
+        .await
+        .map_err(|_| AppError::system_exception())?;
+    if !anonymous_like {
+        return Ok(None);
     }
+    let ip = client_ip(req, config.trusted_proxy);
+    let hash = view_counter::viewer_hash(&format!("like:{}:{}", config.jwt_secret, ip));
+    let value = u32::from_str_radix(&hash[..8], 16).map_err(|_| AppError::system_exception())?;
+    Ok(Some(-((value & 0x3fff_ffff) as i32) - 1))
+}
+
+/// Pinning always takes `max(priority)+1`; past this value pinned memos are compacted first so
+/// `priority` never approaches `i32::MAX`.
+const PRIORITY_RENORMALIZE_THRESHOLD: i64 = 1_000_000;
 
+/// Rewrites pinned priorities to `1..N` keeping their order; unpinned memos stay at 0.
+async fn renormalize_priorities<C: ConnectionTrait>(db: &C) -> Result<(), AppError> {
+    let sql = "update t_memo set priority = (select r.rn from \
+        (select id, row_number() over (order by priority, id) as rn from t_memo where priority > 0) r \
+        where r.id = t_memo.id) where priority > 0";
+    exec_sql(db, sql, vec![]).await
+}
+
+/// Admin maintenance: compacts pinned priorities to `1..N` on demand.
+async fn normalize_priority(
+    db: web::Data<DatabaseConnection>,
+    auth: AuthUser,
+) -> Result<HttpResponse, AppError> {
+    if auth.role.as_deref() != Some("ADMIN") {
+        return Err(AppError::need_login());
+    }
+    db.transaction::<_, (), AppError>(|txn| Box::pin(async move { renormalize_priorities(txn).await }))
+        .await
+        .map_err(map_tx_error)?;
     Ok(HttpResponse::Ok().json(ResponseDto::<()>::success(None)))
 }
 
 #[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
 struct GetQuery {
     count: Option<bool>,
+    share_token: Option<String>,
+    /// Owner/admin only: return the content with its tag line restored, ready to re-save.
+    edit: Option<bool>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ShareLinkRequest {
+    id: i32,
+    /// 有效期(秒),默认 7 天,最长 30 天
+    expires_in: Option<i64>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ShareLinkResponse {
+    url: String,
+    token: String,
+    expires_at: String,
+}
+
+const SHARE_LINK_DEFAULT_SECONDS: i64 = 7 * 24 * 3600;
+const SHARE_LINK_MAX_SECONDS: i64 = 30 * 24 * 3600;
+
+async fn share_link(
+    db: web::Data<DatabaseConnection>,
+    config: web::Data<AppConfig>,
+    origin: RequestOrigin,
+    auth: AuthUser,
+    payload: Json<ShareLinkRequest>,
+) -> Result<HttpResponse, AppError> {
+    let memo_item = memo::Entity::find_by_id(payload.id)
+        .one(db.get_ref())
+        .await
+        .map_err(|_| AppError::system_exception())?
+        .ok_or_else(|| AppError::fail("memo不存在"))?;
+    if memo_item.user_id != auth.user_id {
+        return Err(AppError::fail("只能分享自己的记录"));
+    }
+
+    let expires_in = payload.expires_in.unwrap_or(SHARE_LINK_DEFAULT_SECONDS);
+    if expires_in <= 0 || expires_in > SHARE_LINK_MAX_SECONDS {
+        return Err(AppError::param_error("expiresIn"));
+    }
+    let expires_at = Utc::now() + Duration::seconds(expires_in);
+    let token = auth::encode_share_token(&config, memo_item.id, expires_at.timestamp() as usize)?;
+
+    let domain = sys_config_store::get_string(db.get_ref(), "DOMAIN")
+        .await
+        .map_err(|_| AppError::system_exception())?
+        .unwrap_or_default();
+    let domain = origin.resolve(&domain);
+    let response = ShareLinkResponse {
+        url: format!("{}/memo/{}?shareToken={}", domain, memo_item.id, token),
+        token,
+        expires_at: expires_at.to_rfc3339(),
+    };
+    Ok(HttpResponse::Ok().json(ResponseDto::success(Some(response))))
 }
 
 async fn get(
+    req: HttpRequest,
     db: web::Data<DatabaseConnection>,
+    config: web::Data<AppConfig>,
+    origin: RequestOrigin,
     auth: OptionalAuthUser,
     path: web::Path<i32>,
     query: web::Query<GetQuery>,
 ) -> Result<HttpResponse, AppError> {
     let memo_id = *path;
     let is_login = auth.0.is_some();
+    // 有效的分享 token 只对其签发的那条 memo 跳过可见性校验
+    let shared = query
+        .share_token
+        .as_deref()
+        .and_then(|t| auth::verify_share_token(&config, t))
+        == Some(memo_id);
     let mut conditions = Vec::new();
     conditions.push("t.id = ?".to_string());
 
-    if is_login {
-        conditions.push("(t.visibility in ('PUBLIC','PROTECT') or (t.visibility = 'PRIVATE' and t.user_id = ?))".to_string());
-    } else {
-        conditions.push("t.visibility = 'PUBLIC'".to_string());
+    if !shared {
+        if is_login {
+            conditions.push(logged_in_visibility_sql(db.get_ref()).await?.to_string());
+        } else {
+            conditions.push("t.visibility = 'PUBLIC'".to_string());
+        }
     }
 
     let sql = format!("select t.* from t_memo t where {}", conditions.join(" and "));
     let mut values: Vec<sea_orm::Value> = vec![memo_id.into()];
-    if is_login {
+    if is_login && !shared {
         values.push(auth.0.as_ref().unwrap().user_id.into());
     }
     let memo_row = query_one(db.get_ref(), &sql, values).await?;
@@ -369,22 +1002,158 @@ async fn get(
     }
 
     if query.count.unwrap_or(false) {
-        exec_sql(db.get_ref(), "update t_memo set view_count = view_count + 1 where id = ?", vec![memo_id.into()]).await?;
+        if view_counter::event_mode(db.get_ref()).await? {
+            let identity = match auth.0.as_ref() {
+                Some(a) => format!("user:{}", a.user_id),
+                None => {
+                    let ip = client_ip(&req, config.trusted_proxy);
+                    let agent = req
+                        .headers()
+                        .get(header::USER_AGENT)
+                        .and_then(|v| v.to_str().ok())
+                        .unwrap_or_default();
+                    format!("anon:{}:{}", ip, agent)
+                }
+            };
+            view_counter::record(db.get_ref(), memo_id, view_counter::viewer_hash(&identity)).await?;
+        } else {
+            exec_sql(db.get_ref(), "update t_memo set view_count = view_count + 1 where id = ?", vec![memo_id.into()]).await?;
+        }
     }
 
     let memo_item = row_to_memo_model(memo_row.unwrap());
-    let dto = build_memo_dto(db.get_ref(), memo_item, auth.0.as_ref().map(|a| a.user_id)).await?;
+    let editable = query.edit.unwrap_or(false)
+        && auth
+            .0
+            .as_ref()
+            .is_some_and(|a| a.user_id == memo_item.user_id || a.role.as_deref() == Some("ADMIN"));
+    let tags = split_tags(memo_item.tags.clone());
+    let liker = liker_id(db.get_ref(), &config, &auth, &req).await?;
+    let mut dto = build_memo_dto(db.get_ref(), &origin, memo_item, liker).await?;
+    if editable {
+        let title_mode = load_title_mode(db.get_ref()).await?;
+        dto.content = dto.content.map(|c| editable_content(&c, &tags, title_mode));
+    }
+    Ok(HttpResponse::Ok().json(ResponseDto::success(Some(dto))))
+}
+
+#[derive(Deserialize)]
+struct BatchGetRequest {
+    ids: Vec<i32>,
+}
+
+const BATCH_GET_MAX: usize = 100;
+
+/// Returns the memos among `ids` the caller may read, in the requested order; unknown or hidden
+/// ids are left out. Uses the same visibility rule as `get` and one query for all of them.
+async fn batch_get(
+    req: HttpRequest,
+    db: web::Data<DatabaseConnection>,
+    config: web::Data<AppConfig>,
+    origin: RequestOrigin,
+    auth: OptionalAuthUser,
+    payload: Json<BatchGetRequest>,
+) -> Result<HttpResponse, AppError> {
+    let mut ids = payload.ids.clone();
+    let mut seen = std::collections::HashSet::new();
+    ids.retain(|id| seen.insert(*id));
+    if ids.len() > BATCH_GET_MAX {
+        return Err(AppError::param_error("ids"));
+    }
+    if ids.is_empty() {
+        return Ok(HttpResponse::Ok().json(ResponseDto::success(Some(Vec::<MemoDto>::new()))));
+    }
+
+    let placeholders = vec!["?"; ids.len()].join(",");
+    let mut values: Vec<sea_orm::Value> = ids.iter().map(|id| (*id).into()).collect();
+    let visibility = match auth.0.as_ref() {
+        Some(a) => {
+            values.push(a.user_id.into());
+            logged_in_visibility_sql(db.get_ref()).await?
+        }
+        None => "t.visibility = 'PUBLIC'",
+    };
+    let where_clause = format!("t.id in ({}) and {}", placeholders, visibility);
+    let liker = liker_id(db.get_ref(), &config, &auth, &req).await?;
+    let max_resources = load_max_resources(db.get_ref()).await?;
+    let list_sql = memo_page_sql("", &where_clause, "t.id", "x.id", liker, max_resources);
+    values.push(0.into());
+    values.push((ids.len() as i64).into());
+    let rows = query_all(db.get_ref(), &list_sql, values).await?;
+    let items = build_memo_list_from_rows(db.get_ref(), &origin, rows, liker.is_some()).await?;
+
+    let mut by_id = items
+        .into_iter()
+        .map(|item| (item.id, item))
+        .collect::<std::collections::HashMap<_, _>>();
+    let ordered = ids.iter().filter_map(|id| by_id.remove(id)).collect::<Vec<_>>();
+    Ok(HttpResponse::Ok().json(ResponseDto::success(Some(ordered))))
+}
+
+async fn random(
+    db: web::Data<DatabaseConnection>,
+    origin: RequestOrigin,
+    auth: OptionalAuthUser,
+    payload: Option<Json<RandomMemoRequest>>,
+) -> Result<HttpResponse, AppError> {
+    let requested = payload.and_then(|p| p.user_id).filter(|id| *id > 0);
+    let user_id = match (requested, auth.0.as_ref()) {
+        (Some(id), _) => id,
+        (None, Some(auth)) => auth.user_id,
+        (None, None) => auth::primary_admin_id(db.get_ref()).await?,
+    };
+
+    let mut conditions = vec!["t.status = 'NORMAL'".to_string(), "t.user_id = ?".to_string()];
+    let mut values: Vec<sea_orm::Value> = vec![user_id.into()];
+    if let Some(auth) = auth.0.as_ref() {
+        conditions.push(logged_in_visibility_sql(db.get_ref()).await?.to_string());
+        values.push(auth.user_id.into());
+    } else {
+        conditions.push("t.visibility = 'PUBLIC'".to_string());
+    }
+
+    let random_fn = match db.get_database_backend() {
+        DbBackend::MySql => "RAND()",
+        _ => "RANDOM()",
+    };
+    let sql = format!(
+        "select t.* from t_memo t where {} order by {} limit 1",
+        conditions.join(" and "),
+        random_fn
+    );
+    let memo_row = query_one(db.get_ref(), &sql, values).await?;
+    let Some(memo_row) = memo_row else {
+        return Ok(HttpResponse::Ok().json(ResponseDto::<MemoDto>::success(None)));
+    };
+
+    let memo_item = row_to_memo_model(memo_row);
+    let dto = build_memo_dto(db.get_ref(), &origin, memo_item, auth.0.as_ref().map(|a| a.user_id)).await?;
     Ok(HttpResponse::Ok().json(ResponseDto::success(Some(dto))))
 }
 
+/// Largest `size` a memo list page may ask for, as for tags.
+const MAX_PAGE_SIZE: i64 = 200;
+
+/// Requested page size, defaulting to 20 and kept within `1..=MAX_PAGE_SIZE`.
+fn page_size(size: Option<i64>) -> i64 {
+    size.unwrap_or(20).clamp(1, MAX_PAGE_SIZE)
+}
+
 async fn list(
+    req: HttpRequest,
     db: web::Data<DatabaseConnection>,
+    config: web::Data<AppConfig>,
+    origin: RequestOrigin,
     auth: OptionalAuthUser,
-    payload: web::Json<ListMemoRequest>,
+    payload: Json<ListMemoRequest>,
 ) -> Result<HttpResponse, AppError> {
-    let page = payload.page.unwrap_or(1).max(1);
-    let size = payload.size.unwrap_or(20).max(1);
-    let offset = (page - 1) * size;
+    let size = page_size(payload.size);
+    let cursor = payload.cursor.as_deref().map(parse_memo_cursor).transpose()?;
+    let offset = match cursor {
+        Some(_) => 0,
+        None => (payload.page.unwrap_or(1).max(1) - 1).saturating_mul(size),
+    };
+    let past_offset_cap = offset_exceeds_cap(db.get_ref(), offset).await?;
 
     let is_login = auth.0.is_some();
     let current_user_id = auth.0.as_ref().map(|a| a.user_id);
@@ -392,27 +1161,31 @@ async fn list(
     let mut values = Vec::<sea_orm::Value>::new();
 
     if let Some(search) = payload.search.clone().filter(|s| !s.is_empty()) {
-        where_sql.push("t.content like ?".to_string());
-        values.push(format!("%{}%", search).into());
+        // 附件的文件名和描述也参与搜索,用exists避免一条memo因多个附件重复出现
+        where_sql.push("(t.content like ? or exists (select 1 from t_resource rs where rs.memo_id = t.id and (rs.file_name like ? or rs.alt like ?)))".to_string());
+        let pattern = format!("%{}%", search);
+        values.push(pattern.clone().into());
+        values.push(pattern.clone().into());
+        values.push(pattern.into());
     }
 
-    if let (Some(begin), Some(end)) = (payload.begin.clone(), payload.end.clone()) {
-        if let (Ok(begin), Ok(end)) = (parse_date(&begin), parse_date(&end)) {
-            where_sql.push("t.created between ? and ?".to_string());
-            values.push(begin.into());
-            values.push(end.into());
-        }
+    if let (Some(begin), Some(end)) = (payload.begin.clone(), payload.end.clone())
+        && let (Ok(begin), Ok(end)) = (parse_date(&begin), parse_date(&end))
+    {
+        where_sql.push("t.created between ? and ?".to_string());
+        values.push(begin.into());
+        values.push(end.into());
     }
 
     if is_login {
         let uid = current_user_id.unwrap();
-        where_sql.push("(t.visibility in ('PUBLIC','PROTECT') or (t.visibility = 'PRIVATE' and t.user_id = ?))".to_string());
+        where_sql.push(logged_in_visibility_sql(db.get_ref()).await?.to_string());
         values.push(uid.into());
-        if let Some(user_id) = payload.user_id {
-            if user_id > 0 {
-                where_sql.push("t.user_id = ?".to_string());
-                values.push(user_id.into());
-            }
+        if let Some(user_id) = payload.user_id
+            && user_id > 0
+        {
+            where_sql.push("t.user_id = ?".to_string());
+            values.push(user_id.into());
         }
         if payload.liked.unwrap_or(false) {
             where_sql.push("tumr.memo_id = t.id and tumr.user_id = ? and tumr.fav_type = 'LIKE'".to_string());
@@ -430,11 +1203,11 @@ async fn list(
         }
     } else {
         where_sql.push("t.visibility = 'PUBLIC'".to_string());
-        if let Some(user_id) = payload.user_id {
-            if user_id > 0 {
-                where_sql.push("t.user_id = ?".to_string());
-                values.push(user_id.into());
-            }
+        if let Some(user_id) = payload.user_id
+            && user_id > 0
+        {
+            where_sql.push("t.user_id = ?".to_string());
+            values.push(user_id.into());
         }
     }
 
@@ -460,48 +1233,238 @@ async fn list(
     let count_sql = format!("select count(1) as cnt from t_memo t{} where {}", join_clause, where_clause);
     let total = query_count(db.get_ref(), &count_sql, values.clone()).await?;
 
-    let mut order = String::new();
-    if !payload.liked.unwrap_or(false) && !payload.commented.unwrap_or(false) && !payload.mentioned.unwrap_or(false) {
-        order.push_str("t.priority desc, ");
+    let pin_priority = cursor.is_none()
+        && !payload.liked.unwrap_or(false)
+        && !payload.commented.unwrap_or(false)
+        && !payload.mentioned.unwrap_or(false);
+    if cursor.is_some() && !matches!(payload.order_by.as_deref(), None | Some("" | "newest")) {
+        return Err(AppError::param_error("cursor"));
     }
+    let (inner_order, outer_order) = list_order_clause(payload.order_by.as_deref(), pin_priority)?;
 
-    let list_sql = format!(
-        "select x.*,u.display_name as authorName,u.role as authorRole,u.email,u.bio,r.external_link as url,r.public_id as publicId,r.suffix,r.file_type as fileType,r.storage_type as storageType,r.file_name as fileName{} \
-        from (select t.id,t.created,t.updated,t.content,t.priority,t.visibility,t.tags,t.status,t.user_id as userId,t.view_count as viewCount,t.enable_comment as enableComment,t.like_count as likeCount,t.comment_count as commentCount,t.source as source \
-        from t_memo t{} where {} order by {} t.created desc limit ?,?) x \
-        left join t_user u on u.id = x.userId \
-        left join t_resource r on r.memo_id = x.id{} \
-        order by {} x.created desc, r.created",
-        if is_login {", mr.id as liked"} else {""},
-        join_clause,
-        where_clause,
-        order,
-        if is_login {format!(" left join t_user_memo_relation mr on mr.memo_id = x.id and mr.user_id = {} and mr.fav_type = 'LIKE'", current_user_id.unwrap())} else {"".to_string()},
-        if !payload.liked.unwrap_or(false) && !payload.commented.unwrap_or(false) && !payload.mentioned.unwrap_or(false) {"x.priority desc,"} else {""},
-    );
+    let mut where_clause = where_clause;
+    if let Some(Some((created, id))) = cursor {
+        where_clause.push_str(" and (t.created, t.id) < (?, ?)");
+        values.push(created.into());
+        values.push(id.into());
+    }
 
+    let liker = liker_id(db.get_ref(), &config, &auth, &req).await?;
+    let max_resources = load_max_resources(db.get_ref()).await?;
+    let list_sql = memo_page_sql(&join_clause, &where_clause, inner_order, outer_order, liker, max_resources);
     values.push(offset.into());
     values.push(size.into());
-    let rows = query_all(db.get_ref(), &list_sql, values).await?;
-    let mut items = build_memo_list_from_rows(db.get_ref(), rows, is_login).await?;
-
-    if is_login && payload.commented.unwrap_or(false) && payload.mentioned.unwrap_or(false) {
-        if let Some(uid) = current_user_id {
-            let mut u = user::ActiveModel { id: Set(uid), ..Default::default() };
-            u.last_clicked_mentioned = Set(Some(Utc::now()));
-            let _ = user::Entity::update(u).exec(db.get_ref()).await;
+    let rows = if past_offset_cap { Vec::new() } else { query_all(db.get_ref(), &list_sql, values).await? };
+    // 外层按 created desc, id desc 排序,最后一行就是本页最后一条memo
+    let last_key = rows
+        .last()
+        .and_then(|row| Some((get_datetime_utc(row, "created")?, row.try_get::<i32>("", "id").ok()?)));
+    let mut items = build_memo_list_from_rows(db.get_ref(), &origin, rows, liker.is_some()).await?;
+    if let Some(preview_length) = payload.preview_length.filter(|l| *l > 0) {
+        for item in items.iter_mut() {
+            if let Some(content) = item.content.as_mut()
+                && let Some((idx, _)) = content.char_indices().nth(preview_length)
+            {
+                content.truncate(idx);
+                item.truncated = true;
+            }
         }
     }
 
+    if is_login && payload.commented.unwrap_or(false) && payload.mentioned.unwrap_or(false)
+        && let Some(uid) = current_user_id
+    {
+        let mut u = user::ActiveModel { id: Set(uid), ..Default::default() };
+        u.last_clicked_mentioned = Set(Some(Utc::now()));
+        let _ = user::Entity::update(u).exec(db.get_ref()).await;
+    }
+
     let total_page = if total % size == 0 { total / size } else { total / size + 1 };
-    let response = ListMemoResponse { items: items.drain(..).collect(), total, total_page };
+    let next_cursor = match (cursor, last_key) {
+        (Some(_), Some((created, id))) if items.len() as i64 == size => Some(format_memo_cursor(created, id)),
+        _ => None,
+    };
+    let response = ListMemoResponse { items, total, total_page, next_cursor };
+    Ok(HttpResponse::Ok().json(ResponseDto::success(Some(response))))
+}
+
+/// Cursor of a memo list page: `created` as RFC 3339 at full precision (so it binds to the same
+/// text the row was stored with) and the id, joined by a comma.
+fn format_memo_cursor(created: DateTime<Utc>, id: i32) -> String {
+    format!("{},{}", created.to_rfc3339_opts(SecondsFormat::AutoSi, false), id)
+}
+
+/// `None` for an empty cursor (the first page in cursor mode).
+fn parse_memo_cursor(cursor: &str) -> Result<Option<(DateTime<Utc>, i32)>, AppError> {
+    let cursor = cursor.trim();
+    if cursor.is_empty() {
+        return Ok(None);
+    }
+    let (created, id) = cursor.rsplit_once(',').ok_or_else(|| AppError::param_error("cursor"))?;
+    let created = DateTime::parse_from_rfc3339(created).map_err(|_| AppError::param_error("cursor"))?;
+    let id = id.parse::<i32>().map_err(|_| AppError::param_error("cursor"))?;
+    Ok(Some((created.with_timezone(&Utc), id)))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MentionsRequest {
+    page: Option<i64>,
+    size: Option<i64>,
+}
+
+/// Lists memos whose content or comments mention the caller, newest first, and marks the
+/// mentions as read.
+async fn mentions(
+    db: web::Data<DatabaseConnection>,
+    origin: RequestOrigin,
+    auth: AuthUser,
+    payload: Option<Json<MentionsRequest>>,
+) -> Result<HttpResponse, AppError> {
+    let (page, size) = payload.map(|p| (p.page, p.size)).unwrap_or_default();
+    let page = page.unwrap_or(1).max(1);
+    let size = page_size(size);
+    let offset = (page - 1).saturating_mul(size);
+    let past_offset_cap = offset_exceeds_cap(db.get_ref(), offset).await?;
+
+    let where_clause = format!(
+        "t.status = 'NORMAL' and {} \
+        and (t.mentioned_user_id like ? or t.id in (select tc.memo_id from t_comment tc where tc.mentioned_user_id like ?))",
+        logged_in_visibility_sql(db.get_ref()).await?
+    );
+    let pattern = format!("%#{},%", auth.user_id);
+    let mut values: Vec<sea_orm::Value> = vec![auth.user_id.into(), pattern.clone().into(), pattern.into()];
+
+    let count_sql = format!("select count(1) as cnt from t_memo t where {}", where_clause);
+    let total = query_count(db.get_ref(), &count_sql, values.clone()).await?;
+
+    let (inner_order, outer_order) = list_order_clause(None, false)?;
+    let max_resources = load_max_resources(db.get_ref()).await?;
+    let list_sql = memo_page_sql("", &where_clause, inner_order, outer_order, Some(auth.user_id), max_resources);
+    values.push(offset.into());
+    values.push(size.into());
+    let rows = if past_offset_cap { Vec::new() } else { query_all(db.get_ref(), &list_sql, values).await? };
+    let items = build_memo_list_from_rows(db.get_ref(), &origin, rows, true).await?;
+
+    let mut u = user::ActiveModel { id: Set(auth.user_id), ..Default::default() };
+    u.last_clicked_mentioned = Set(Some(Utc::now()));
+    let _ = user::Entity::update(u).exec(db.get_ref()).await;
+
+    let total_page = if total % size == 0 { total / size } else { total / size + 1 };
+    let response = ListMemoResponse { items, total, total_page, next_cursor: None };
+    Ok(HttpResponse::Ok().json(ResponseDto::success(Some(response))))
+}
+
+/// Builds the paged memo query shared by `list` and `mentions`: one row per memo resource,
+/// with author columns and, for a logged-in viewer, whether they liked it. The caller binds
+/// the `where` values followed by offset and size. With `max_resources` > 0 only the first
+/// that many resources of each memo are joined, since older memos may exceed the limit.
+fn memo_page_sql(
+    join_clause: &str,
+    where_clause: &str,
+    inner_order: &str,
+    outer_order: &str,
+    viewer_id: Option<i32>,
+    max_resources: usize,
+) -> String {
+    let resource_join = if max_resources > 0 {
+        format!(
+            "(select rs.*, row_number() over (partition by rs.memo_id order by rs.created, rs.public_id) as rn from t_resource rs) r \
+            on r.memo_id = x.id and r.rn <= {}",
+            max_resources
+        )
+    } else {
+        "t_resource r on r.memo_id = x.id".to_string()
+    };
+    format!(
+        "select x.*,u.display_name as authorName,u.role as authorRole,u.email,u.bio,r.external_link as url,r.public_id as publicId,r.suffix,r.file_type as fileType,r.storage_type as storageType,r.file_name as fileName,r.alt as alt{} \
+        from (select t.id,t.created,t.updated,t.content,t.priority,t.visibility,t.tags,t.status,t.user_id as userId,t.view_count as viewCount,t.enable_comment as enableComment,t.like_count as likeCount,t.comment_count as commentCount,t.source as source,t.source_title as sourceTitle,t.source_image as sourceImage,t.title as title,t.content_format as contentFormat \
+        from t_memo t{} where {} order by {} limit ?,?) x \
+        left join t_user u on u.id = x.userId \
+        left join {}{} \
+        order by {}, r.created",
+        if viewer_id.is_some() {", mr.id as liked"} else {""},
+        join_clause,
+        where_clause,
+        inner_order,
+        resource_join,
+        viewer_id.map(|uid| format!(" left join t_user_memo_relation mr on mr.memo_id = x.id and mr.user_id = {} and mr.fav_type = 'LIKE'", uid)).unwrap_or_default(),
+        outer_order,
+    )
+}
+
+/// Maps the `orderBy` option to fixed ORDER BY clauses for the inner (`t`) and outer (`x`)
+/// list queries; user input is never interpolated. Pinned memos only float to the top for the
+/// default `newest` ordering, and `id` breaks ties so pages stay stable for equal timestamps.
+fn list_order_clause(order_by: Option<&str>, pin_priority: bool) -> Result<(&'static str, &'static str), AppError> {
+    match order_by.unwrap_or("") {
+        "" | "newest" if pin_priority => Ok(("t.priority desc, t.created desc, t.id desc", "x.priority desc, x.created desc, x.id desc")),
+        "" | "newest" => Ok(("t.created desc, t.id desc", "x.created desc, x.id desc")),
+        "oldest" => Ok(("t.created asc, t.id asc", "x.created asc, x.id asc")),
+        "views" => Ok(("t.view_count desc, t.created desc, t.id desc", "x.viewCount desc, x.created desc, x.id desc")),
+        "likes" => Ok(("t.like_count desc, t.created desc, t.id desc", "x.likeCount desc, x.created desc, x.id desc")),
+        _ => Err(AppError::param_error("orderBy")),
+    }
+}
+
+async fn site_stats(
+    db: web::Data<DatabaseConnection>,
+    auth: OptionalAuthUser,
+) -> Result<HttpResponse, AppError> {
+    // 匿名访问只统计公开的memo
+    let protect_readable = sys_config_store::protect_readable_by_logged_in(db.get_ref())
+        .await
+        .map_err(|_| AppError::system_exception())?;
+    let visibilities = if auth.0.is_some() && protect_readable {
+        vec!["PUBLIC", "PROTECT"]
+    } else {
+        vec!["PUBLIC"]
+    };
+    let visible = memo::Entity::find()
+        .filter(memo::Column::Status.eq("NORMAL"))
+        .filter(memo::Column::Visibility.is_in(visibilities.clone()));
+
+    let total_memos = visible
+        .clone()
+        .count(db.get_ref())
+        .await
+        .map_err(|_| AppError::system_exception())?;
+    let earliest = visible
+        .filter(memo::Column::Created.is_not_null())
+        .order_by_asc(memo::Column::Created)
+        .one(db.get_ref())
+        .await
+        .map_err(|_| AppError::system_exception())?;
+    // 只数可见memo上出现过的标签,tags 形如 #a,#b, 前面补逗号后按整段匹配
+    let total_tags = query_count(
+        db.get_ref(),
+        &format!(
+            "select count(distinct tg.name) as cnt from t_tag tg where exists (select 1 from t_memo t \
+            where t.user_id = tg.user_id and t.status = 'NORMAL' and t.visibility in ({}) \
+            and instr(',' || t.tags, ',' || tg.name || ',') > 0)",
+            vec!["?"; visibilities.len()].join(",")
+        ),
+        visibilities.into_iter().map(Into::into).collect(),
+    )
+    .await?;
+    let total_users = user::Entity::find()
+        .count(db.get_ref())
+        .await
+        .map_err(|_| AppError::system_exception())?;
+
+    let response = SiteStatsResponse {
+        total_memos: total_memos as i64,
+        total_tags,
+        total_users: total_users as i64,
+        since: earliest.and_then(|m| m.created).map(to_rfc3339_utc),
+    };
     Ok(HttpResponse::Ok().json(ResponseDto::success(Some(response))))
 }
 
 async fn statistics(
     db: web::Data<DatabaseConnection>,
     auth: OptionalAuthUser,
-    payload: web::Json<StatisticsRequest>,
+    payload: Json<StatisticsRequest>,
 ) -> Result<HttpResponse, AppError> {
     let begin = payload.begin.clone().and_then(|b| parse_date(&b).ok());
     let end = payload.end.clone().and_then(|e| parse_date(&e).ok());
@@ -515,13 +1478,7 @@ async fn statistics(
     let user_id = if let Some(auth) = auth.0 {
         auth.user_id
     } else {
-        let admin = user::Entity::find()
-            .filter(user::Column::Role.eq("ADMIN"))
-            .one(db.get_ref())
-            .await
-            .map_err(|_| AppError::system_exception())?
-            .ok_or_else(|| AppError::fail("管理员不存在"))?;
-        admin.id
+        auth::primary_admin_id(db.get_ref()).await?
     };
 
     let user_model = user::Entity::find_by_id(user_id)
@@ -551,12 +1508,20 @@ async fn statistics(
     )
     .await?;
 
-    let stats_sql = "select date(created/1000,'unixepoch') as day,count(1) as count from t_memo where user_id = ? and created between ? and ? group by date(created/1000,'unixepoch') order by date(created/1000,'unixepoch') desc";
+    // created 存的是 UTC 时间,不是毫秒时间戳;按日期截断后统一返回 YYYY-MM-DD 字符串
+    let day_expr = match db.get_database_backend() {
+        DbBackend::MySql => "date_format(created, '%Y-%m-%d')",
+        _ => "date(created)",
+    };
+    let stats_sql = format!(
+        "select {} as day,count(1) as count from t_memo where user_id = ? and created between ? and ? group by day order by day desc",
+        day_expr
+    );
 
     let rows = query_all(
         db.get_ref(),
-        stats_sql,
-        vec![user_id.into(), begin.into(), end.into()],
+        &stats_sql,
+        vec![user_id.into(), begin.and_utc().into(), end.and_utc().into()],
     )
     .await?;
 
@@ -579,9 +1544,11 @@ async fn statistics(
 }
 
 async fn relation(
+    req: HttpRequest,
     db: web::Data<DatabaseConnection>,
-    auth: AuthUser,
-    payload: web::Json<MemoRelationRequest>,
+    config: web::Data<AppConfig>,
+    auth: OptionalAuthUser,
+    payload: Json<MemoRelationRequest>,
 ) -> Result<HttpResponse, AppError> {
     let open_like = sys_config_store::get_boolean(db.get_ref(), "OPEN_LIKE")
         .await
@@ -589,34 +1556,30 @@ async fn relation(
     if !open_like {
         return Err(AppError::fail("禁止点赞"));
     }
+    let liker_id = liker_id(db.get_ref(), &config, &auth, &req)
+        .await?
+        .ok_or_else(AppError::need_login)?;
 
     if payload.operate_type == "ADD" {
         db.transaction::<_, (), AppError>(|txn| {
             let memo_id = payload.memo_id;
-            let user_id = auth.user_id;
+            let user_id = liker_id;
             let fav_type = payload.r#type.clone();
             Box::pin(async move {
-                let count = query_count(
-                    txn,
-                    "select count(1) as cnt from t_user_memo_relation where memo_id = ? and user_id = ? and fav_type = ?",
-                    vec![memo_id.into(), user_id.into(), fav_type.clone().into()],
-                )
-                .await?;
-                if count > 0 {
-                    return Err(AppError::fail("数据已存在"));
-                }
-
-                let relation = user_memo_relation::ActiveModel {
-                    memo_id: Set(memo_id),
-                    user_id: Set(user_id),
-                    fav_type: Set(fav_type),
-                    created: Set(Some(Utc::now())),
-                    ..Default::default()
-                };
-                relation
-                    .insert(txn)
+                // 依赖(memo_id, user_id, fav_type)唯一索引,重复点赞不插入也不计数
+                let stmt = Statement::from_sql_and_values(
+                    txn.get_database_backend(),
+                    "insert into t_user_memo_relation (memo_id, user_id, fav_type, created) values (?, ?, ?, ?) \
+                     on conflict (memo_id, user_id, fav_type) do nothing",
+                    vec![memo_id.into(), user_id.into(), fav_type.into(), Utc::now().into()],
+                );
+                let inserted = txn
+                    .execute(stmt)
                     .await
                     .map_err(|_| AppError::system_exception())?;
+                if inserted.rows_affected() == 0 {
+                    return Ok(());
+                }
 
                 exec_sql(
                     txn,
@@ -632,7 +1595,7 @@ async fn relation(
     } else if payload.operate_type == "REMOVE" {
         db.transaction::<_, (), AppError>(|txn| {
             let memo_id = payload.memo_id;
-            let user_id = auth.user_id;
+            let user_id = liker_id;
             let fav_type = payload.r#type.clone();
             Box::pin(async move {
                 let result = user_memo_relation::Entity::delete_many()
@@ -660,25 +1623,240 @@ async fn relation(
     Ok(HttpResponse::Ok().json(ResponseDto::<()>::success(None)))
 }
 
-fn check_content_and_resource(content: &str, public_ids: &[String]) -> Result<(), AppError> {
+pub(crate) async fn load_max_blank_lines(db: &DatabaseConnection) -> Result<usize, AppError> {
+    let value = sys_config_store::get_i64(db, "MAX_BLANK_LINES")
+        .await
+        .map_err(|_| AppError::system_exception())?;
+    Ok(value.unwrap_or(2).max(0) as usize)
+}
+
+async fn load_length_bounds(db: &DatabaseConnection) -> Result<(usize, usize), AppError> {
+    let min = sys_config_store::get_i64(db, "MEMO_MIN_LENGTH")
+        .await
+        .map_err(|_| AppError::system_exception())?;
+    // MEMO_MAX_LENGTH 是前端折叠显示的高度,不是字数上限
+    let max = sys_config_store::get_i64(db, "MEMO_MAX_CONTENT_LENGTH")
+        .await
+        .map_err(|_| AppError::system_exception())?;
+    Ok((min.unwrap_or(0).max(0) as usize, max.unwrap_or(0).max(0) as usize))
+}
+
+/// `MEMO_FIRST_LINE_MODE` is `tags` (default, the first line holds tags) or `title`.
+async fn load_title_mode(db: &DatabaseConnection) -> Result<bool, AppError> {
+    let mode = sys_config_store::get_string(db, "MEMO_FIRST_LINE_MODE")
+        .await
+        .map_err(|_| AppError::system_exception())?
+        .unwrap_or_default();
+    Ok(mode.trim().eq_ignore_ascii_case("title"))
+}
+
+/// `STRIP_TAGS_FROM_CONTENT` (default on) removes the tags on the tag line from the stored
+/// content; off keeps the content exactly as written, tags included.
+async fn load_strip_tags(db: &DatabaseConnection) -> Result<bool, AppError> {
+    let value = sys_config_store::get_string(db, "STRIP_TAGS_FROM_CONTENT")
+        .await
+        .map_err(|_| AppError::system_exception())?
+        .unwrap_or_default();
+    Ok(!value.trim().eq_ignore_ascii_case("false"))
+}
+
+const MEMO_TITLE_MAX_CHARS: usize = 200;
+
+struct ParsedContent {
+    title: Option<String>,
+    tags: Vec<String>,
+    content: String,
+}
+
+#[derive(Deserialize)]
+struct ParsePreviewRequest {
+    content: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ParsePreviewResponse {
+    title: Option<String>,
+    tags: Vec<String>,
+    mentions: Vec<MentionedUserDto>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct MentionedUserDto {
+    id: i32,
+    display_name: String,
+}
+
+/// Runs save's title/tag parsing and comment mention resolution without storing anything.
+async fn parse_preview(
+    db: web::Data<DatabaseConnection>,
+    _auth: AuthUser,
+    payload: Json<ParsePreviewRequest>,
+) -> Result<HttpResponse, AppError> {
+    let parsed = parse_memo_content(
+        &payload.content,
+        load_title_mode(db.get_ref()).await?,
+        load_strip_tags(db.get_ref()).await?,
+    );
+    let mentions = super::comment::resolve_mentions(db.get_ref(), &parsed.content)
+        .await?
+        .into_iter()
+        .map(|u| MentionedUserDto {
+            id: u.id,
+            display_name: u.display_name.unwrap_or(u.username),
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(ResponseDto::success(Some(ParsePreviewResponse {
+        title: parsed.title,
+        tags: parsed.tags,
+        mentions,
+    }))))
+}
+
+const CONTENT_FORMAT_MARKDOWN: &str = "markdown";
+
+fn check_content_format(format: Option<&str>) -> Result<Option<&str>, AppError> {
+    match format {
+        None | Some(CONTENT_FORMAT_MARKDOWN | "plaintext" | "html") => Ok(format),
+        Some(_) => Err(AppError::param_error("contentFormat")),
+    }
+}
+
+/// Splits tags (and in title mode the title) off the submitted content. Tags are collected
+/// from the whole content, but only the tag line (the first line, or in title mode the line
+/// after the title) loses them, and only when `strip_tags` is set. In title mode the title
+/// line stays in the content and is not scanned for tags.
+fn parse_memo_content(content: &str, title_mode: bool, strip_tags: bool) -> ParsedContent {
+    let strip = |text: &str, tags: &[String]| {
+        if strip_tags { replace_first_line(text, tags) } else { text.to_string() }
+    };
+    if !title_mode {
+        let tags = parse_tags(content);
+        let content = strip(content, &tags).trim().to_string();
+        return ParsedContent { title: None, tags, content };
+    }
+
+    let trimmed = content.trim_start();
+    let (first, rest) = trimmed.split_once('\n').unwrap_or((trimmed, ""));
+    let title = Some(first.trim().chars().take(MEMO_TITLE_MAX_CHARS).collect::<String>())
+        .filter(|t| !t.is_empty());
+    let tags = parse_tags(rest);
+    let body = strip(rest, &tags);
+    let content = if body.trim().is_empty() {
+        first.trim().to_string()
+    } else {
+        format!("{}\n{}", first.trim_end(), body).trim().to_string()
+    };
+    ParsedContent { title, tags, content }
+}
+
+/// `bounds` is `(MEMO_MIN_LENGTH, MEMO_MAX_CONTENT_LENGTH)` in characters of trimmed content, 0 meaning
+/// no bound. Memos with resources skip the minimum so image-only posts stay valid.
+fn check_content_and_resource(content: &str, public_ids: &[String], bounds: (usize, usize)) -> Result<(), AppError> {
     if content.trim().is_empty() && public_ids.is_empty() {
         return Err(AppError::fail("内容和图片都为空"));
     }
+    let (min, max) = bounds;
+    let length = content.trim().chars().count();
+    if public_ids.is_empty() && length < min {
+        return Err(AppError::fail(format!("内容不能少于{}个字", min)));
+    }
+    if max > 0 && length > max {
+        return Err(AppError::fail(format!("内容不能超过{}个字", max)));
+    }
     Ok(())
 }
 
+/// `#tag` tokens from every line outside ``` fenced code blocks, in order of first appearance.
+/// Tokens are split on whitespace and commas; trailing sentence punctuation is dropped and
+/// markdown heading markers (`#`, `##`, ...) are not tags.
 fn parse_tags(content: &str) -> Vec<String> {
-    let mut lines = content.split('\n');
-    let first_line = lines.next().unwrap_or("");
-    if first_line.trim().is_empty() {
-        return Vec::new();
+    let mut tags: Vec<String> = Vec::new();
+    let mut in_code_block = false;
+    for line in content.lines() {
+        if line.trim_start().starts_with("```") {
+            in_code_block = !in_code_block;
+            continue;
+        }
+        if in_code_block {
+            continue;
+        }
+        for s in line.split(is_tag_separator).filter_map(tag_token) {
+            if !tags.iter().any(|t| t == s) {
+                tags.push(s.to_string());
+            }
+        }
+    }
+    tags
+}
+
+fn is_tag_separator(c: char) -> bool {
+    c.is_whitespace() || c == ',' || c == '，'
+}
+
+/// The `#tag` a word spells, without trailing punctuation, or `None` if it is not a tag.
+fn tag_token(word: &str) -> Option<&str> {
+    let tag = word.trim_end_matches(['.', '!', '?', ';', ':', '。', '！', '？', '；', '：', '、']);
+    (tag.starts_with('#') && !tag.trim_start_matches('#').is_empty()).then_some(tag)
+}
+
+/// Rewrites every `old` tag in `content` to `new`, matching words the way [`parse_tags`] reads
+/// them (code blocks untouched), so a renamed tag is not parsed back from the body on next edit.
+pub(crate) fn rename_tag_in_content(content: &str, old: &str, new: &str) -> String {
+    let mut out = String::with_capacity(content.len());
+    let mut in_code_block = false;
+    for line in content.split_inclusive('\n') {
+        let fence = line.trim_start().starts_with("```");
+        if fence {
+            in_code_block = !in_code_block;
+        }
+        if fence || in_code_block {
+            out.push_str(line);
+            continue;
+        }
+        let mut start = 0;
+        for (i, c) in line.char_indices().filter(|(_, c)| is_tag_separator(*c)).chain([(line.len(), ' ')]) {
+            let word = &line[start..i];
+            match tag_token(word) {
+                Some(tag) if tag == old => {
+                    out.push_str(new);
+                    out.push_str(&word[tag.len()..]);
+                }
+                _ => out.push_str(word),
+            }
+            if i < line.len() {
+                out.push(c);
+            }
+            start = i + c.len_utf8();
+        }
+    }
+    out
+}
+
+/// Same `@name` / `@[name](id)` resolution as comments, stored as `#id,` entries.
+async fn parse_memo_mentions(db: &DatabaseConnection, content: &str) -> Result<Option<String>, AppError> {
+    let (_, ids) = super::comment::parse_mentions(db, content).await?;
+    Ok(ids.filter(|ids| !ids.is_empty()))
+}
+
+/// Inverse of [`parse_memo_content`]: puts the stored tags that no longer appear in the content
+/// back on their own line (after the title line in title mode), so saving the result again
+/// yields the same content and tags.
+fn editable_content(content: &str, tags: &[String], title_mode: bool) -> String {
+    let present = parse_tags(content);
+    let missing: Vec<&str> = tags.iter().filter(|t| !present.contains(t)).map(|t| t.as_str()).collect();
+    if missing.is_empty() {
+        return content.to_string();
+    }
+    let tag_line = missing.join(" ");
+    if !title_mode {
+        return if content.is_empty() { tag_line } else { format!("{}\n{}", tag_line, content) };
+    }
+    match content.split_once('\n') {
+        Some((first, rest)) => format!("{}\n{}\n{}", first, tag_line, rest),
+        None => format!("{}\n{}", content, tag_line),
     }
-    first_line
-        .split(|c: char| c.is_whitespace() || c == ',')
-        .filter(|s| !s.is_empty())
-        .filter(|s| s.starts_with('#') && s.len() > 1)
-        .map(|s| s.to_string())
-        .collect()
 }
 
 fn replace_first_line(content: &str, tags: &[String]) -> String {
@@ -686,19 +1864,29 @@ fn replace_first_line(content: &str, tags: &[String]) -> String {
         return "".to_string();
     }
     let mut lines: Vec<String> = content.lines().map(|s| s.to_string()).collect();
-    if lines.is_empty() {
+    // 标签行是第一个非空行,前面的空行不算
+    let Some(index) = lines.iter().position(|l| !l.trim().is_empty()) else {
         return "".to_string();
-    }
-    let mut first = lines[0].clone();
-    for tag in tags {
-        first = first.replace(&format!("{},", tag), "");
-        first = first.replace(&format!("{} ", tag), "");
-        first = first.replace(tag, "");
+    };
+    // 只删掉 tag_token 认得的整词,免得误伤链接里的 #片段
+    let line = lines[index].as_str();
+    let mut first = String::with_capacity(line.len());
+    let mut start = 0;
+    for (i, c) in line.char_indices().filter(|(_, c)| is_tag_separator(*c)).chain([(line.len(), ' ')]) {
+        let word = &line[start..i];
+        let is_tag = tag_token(word).is_some_and(|tag| tags.iter().any(|t| t == tag));
+        if !is_tag {
+            first.push_str(word);
+            if i < line.len() {
+                first.push(c);
+            }
+        }
+        start = i + c.len_utf8();
     }
     if first.trim().is_empty() {
-        lines.remove(0);
+        lines.remove(index);
     } else {
-        lines[0] = first;
+        lines[index] = first.trim_end().to_string();
     }
     lines.join("\n")
 }
@@ -723,43 +1911,21 @@ async fn sync_tags_on_save<C: ConnectionTrait>(
     user_id: i32,
     tags: &[String],
 ) -> Result<(), AppError> {
-    if tags.is_empty() {
-        return Ok(());
-    }
-    let existing = tag::Entity::find()
-        .filter(tag::Column::UserId.eq(user_id))
-        .filter(tag::Column::Name.is_in(tags.iter().cloned()))
-        .all(db)
-        .await
-        .map_err(|e| {
-            error!("sync_tags_on_save find failed: {}", e);
-            AppError::system_exception()
-        })?;
-
-    let mut new_tags: Vec<String> = tags.to_vec();
-    for t in &existing {
-        new_tags.retain(|x| x != &t.name);
-    }
-
-    for name in new_tags {
-        let active = tag::ActiveModel {
-            name: Set(name),
-            user_id: Set(user_id),
-            memo_count: Set(Some(1)),
-            created: Set(Some(Utc::now())),
-            updated: Set(Some(Utc::now())),
-            ..Default::default()
-        };
-        active.insert(db).await.map_err(|e| {
-            error!("sync_tags_on_save insert failed: {}", e);
-            AppError::system_exception()
-        })?;
-    }
-
-    for t in existing {
-        increment_tag_count(db, user_id, &t.name).await?;
+    // 依赖 (user_id,name) 唯一索引做 upsert,避免并发保存时先查后写的竞争
+    let sql = match db.get_database_backend() {
+        DbBackend::MySql => {
+            "insert into t_tag (user_id, name, memo_count, created, updated) values (?, ?, 1, ?, ?) \
+             on duplicate key update memo_count = memo_count + 1, updated = values(updated)"
+        }
+        _ => {
+            "insert into t_tag (user_id, name, memo_count, created, updated) values (?, ?, 1, ?, ?) \
+             on conflict(user_id, name) do update set memo_count = memo_count + 1, updated = excluded.updated"
+        }
+    };
+    for name in tags {
+        let now = Utc::now();
+        exec_sql(db, sql, vec![user_id.into(), name.clone().into(), now.into(), now.into()]).await?;
     }
-
     Ok(())
 }
 
@@ -776,22 +1942,118 @@ async fn sync_tags_on_update<C: ConnectionTrait>(
     Ok(())
 }
 
-async fn increment_tag_count<C: ConnectionTrait>(db: &C, user_id: i32, name: &str) -> Result<(), AppError> {
+async fn decrement_tag_count<C: ConnectionTrait>(db: &C, user_id: i32, name: &str) -> Result<(), AppError> {
     exec_sql(
         db,
-        "update t_tag set memo_count = memo_count + 1 where user_id = ? and name = ?",
+        "update t_tag set memo_count = memo_count - 1 where user_id = ? and name = ? and memo_count >= 1",
         vec![user_id.into(), name.into()],
     )
     .await
 }
 
-async fn decrement_tag_count<C: ConnectionTrait>(db: &C, user_id: i32, name: &str) -> Result<(), AppError> {
-    exec_sql(
+/// Deletes local files of removed resources unless another resource row still points at the
+/// same file, so memos sharing an upload keep working.
+async fn release_resource_files(db: &DatabaseConnection, resources: Vec<resource::Model>) {
+    for r in resources {
+        if r.storage_type.as_deref() != Some("LOCAL") {
+            continue;
+        }
+        let Some(path) = r.internal_path.filter(|p| !p.is_empty()) else {
+            continue;
+        };
+        let refs = resource::Entity::find()
+            .filter(resource::Column::InternalPath.eq(path.as_str()))
+            .count(db)
+            .await;
+        match refs {
+            Ok(0) => {
+                if let Err(e) = std::fs::remove_file(&path)
+                    && e.kind() != std::io::ErrorKind::NotFound
+                {
+                    warn!("remove resource file {} failed: {}", path, e);
+                }
+                let _ = std::fs::remove_file(super::resource::thumbnail_path(std::path::Path::new(&path)));
+            }
+            Ok(_) => {}
+            Err(e) => warn!("count resource references for {} failed: {}", path, e),
+        }
+    }
+}
+
+/// Enforces `MEMO_MAX_PER_USER` (0 = unlimited) for non-admins; trashed memos count until
+/// `emptyTrash` purges them.
+async fn check_memo_quota(db: &DatabaseConnection, auth: &AuthUser) -> Result<(), AppError> {
+    match memo_quota(db, auth).await? {
+        Some((max, used)) if used >= max => Err(AppError::fail(format!("最多只能发布{}条memo", max))),
+        _ => Ok(()),
+    }
+}
+
+/// `MEMO_MAX_PER_USER` and how many memos the user already has, or None when unlimited.
+async fn memo_quota(db: &DatabaseConnection, auth: &AuthUser) -> Result<Option<(i64, i64)>, AppError> {
+    if auth.role.as_deref() == Some("ADMIN") {
+        return Ok(None);
+    }
+    let max = sys_config_store::get_i64(db, "MEMO_MAX_PER_USER")
+        .await
+        .map_err(|_| AppError::system_exception())?
+        .unwrap_or(0);
+    if max <= 0 {
+        return Ok(None);
+    }
+    let used = query_count(
         db,
-        "update t_tag set memo_count = memo_count - 1 where user_id = ? and name = ? and memo_count >= 1",
-        vec![user_id.into(), name.into()],
+        "select count(1) as cnt from t_memo where user_id = ?",
+        vec![auth.user_id.into()],
     )
-    .await
+    .await?;
+    Ok(Some((max, used)))
+}
+
+/// `MEMO_MAX_RESOURCES`, the number of resources a memo may carry (0 = unlimited).
+async fn load_max_resources(db: &DatabaseConnection) -> Result<usize, AppError> {
+    let max = sys_config_store::get_i64(db, "MEMO_MAX_RESOURCES")
+        .await
+        .map_err(|_| AppError::system_exception())?
+        .unwrap_or(0);
+    Ok(max.max(0) as usize)
+}
+
+/// Checks that every id is an upload of `user_id` that is still unattached (or already on
+/// `memo_id` when updating), and that the list stays within `MEMO_MAX_RESOURCES`.
+async fn validate_public_ids(
+    db: &DatabaseConnection,
+    user_id: i32,
+    memo_id: Option<i32>,
+    public_ids: &[String],
+) -> Result<(), AppError> {
+    if public_ids.is_empty() {
+        return Ok(());
+    }
+    let max = load_max_resources(db).await?;
+    if max > 0 && public_ids.len() > max {
+        return Err(AppError::fail(format!("最多只能关联{}个资源", max)));
+    }
+
+    let mut usable = Condition::any().add(
+        Condition::all()
+            .add(resource::Column::MemoId.eq(0))
+            .add(resource::Column::UserId.eq(user_id)),
+    );
+    if let Some(memo_id) = memo_id {
+        usable = usable.add(resource::Column::MemoId.eq(memo_id));
+    }
+    let owned = resource::Entity::find()
+        .filter(resource::Column::PublicId.is_in(public_ids.iter().cloned()))
+        .filter(usable)
+        .count(db)
+        .await
+        .map_err(|_| AppError::system_exception())?;
+    let unique = public_ids.iter().collect::<std::collections::HashSet<_>>().len();
+    if owned as usize != unique {
+        return Err(AppError::param_error("publicIds"));
+    }
+    Ok(())
 }
 
 async fn attach_resources<C: ConnectionTrait>(
@@ -799,15 +2061,14 @@ async fn attach_resources<C: ConnectionTrait>(
     memo_id: i32,
     public_ids: &[String],
 ) -> Result<(), AppError> {
-    for public_id in public_ids {
-        exec_sql(
-            db,
-            "update t_resource set memo_id = ? where memo_id = 0 and public_id = ?",
-            vec![memo_id.into(), public_id.clone().into()],
-        )
-        .await?;
-    }
-    Ok(())
+    let placeholders = vec!["?"; public_ids.len()].join(",");
+    let sql = format!(
+        "update t_resource set memo_id = ? where memo_id = 0 and public_id in ({})",
+        placeholders
+    );
+    let mut values: Vec<sea_orm::Value> = vec![memo_id.into()];
+    values.extend(public_ids.iter().map(|id| id.clone().into()));
+    exec_sql(db, &sql, values).await
 }
 
 async fn clear_memo_resources<C: ConnectionTrait>(db: &C, memo_id: i32) -> Result<(), AppError> {
@@ -854,6 +2115,54 @@ async fn query_all<C: ConnectionTrait>(db: &C, sql: &str, values: Vec<sea_orm::V
         .map_err(|_| AppError::system_exception())
 }
 
+/// Whether `offset` is past `MAX_PAGE_OFFSET`; such pages are answered with an empty list.
+async fn offset_exceeds_cap(db: &DatabaseConnection, offset: i64) -> Result<bool, AppError> {
+    let cap = sys_config_store::get_max_page_offset(db)
+        .await
+        .map_err(|_| AppError::system_exception())?;
+    Ok(cap > 0 && offset > cap)
+}
+
+/// `enableComment` for a new memo that didn't send one: the author's `default_enable_comment`,
+/// else the site's `DEFAULT_ENABLE_COMMENT`, else off.
+async fn default_enable_comment(db: &DatabaseConnection, user_id: i32) -> Result<bool, AppError> {
+    let user_default = user::Entity::find_by_id(user_id)
+        .one(db)
+        .await
+        .map_err(|_| AppError::system_exception())?
+        .and_then(|u| u.default_enable_comment)
+        .and_then(|v| parse_bool(&v));
+    if let Some(enabled) = user_default {
+        return Ok(enabled);
+    }
+    let site_default = sys_config_store::get_string(db, "DEFAULT_ENABLE_COMMENT")
+        .await
+        .map_err(|_| AppError::system_exception())?
+        .and_then(|v| parse_bool(&v));
+    Ok(site_default.unwrap_or(false))
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "true" | "1" | "yes" => Some(true),
+        "false" | "0" | "no" => Some(false),
+        _ => None,
+    }
+}
+
+/// Visibility filter for a logged-in viewer, binding their user id once. PROTECT memos are
+/// readable by everyone logged in or only by their author, depending on `PROTECT_SCOPE`.
+pub(crate) async fn logged_in_visibility_sql(db: &DatabaseConnection) -> Result<&'static str, AppError> {
+    let protect_readable = sys_config_store::protect_readable_by_logged_in(db)
+        .await
+        .map_err(|_| AppError::system_exception())?;
+    Ok(if protect_readable {
+        "(t.visibility in ('PUBLIC','PROTECT') or (t.visibility = 'PRIVATE' and t.user_id = ?))"
+    } else {
+        "(t.visibility = 'PUBLIC' or (t.visibility in ('PROTECT','PRIVATE') and t.user_id = ?))"
+    })
+}
+
 async fn query_count<C: ConnectionTrait>(db: &C, sql: &str, values: Vec<sea_orm::Value>) -> Result<i64, AppError> {
     let row = query_one(db, sql, values).await?;
     Ok(row
@@ -889,11 +2198,17 @@ fn row_to_memo_model(row: sea_orm::QueryResult) -> memo::Model {
         enable_comment: row.try_get("", "enable_comment").ok(),
         view_count: row.try_get("", "view_count").ok(),
         source: row.try_get("", "source").ok(),
+        source_title: row.try_get("", "source_title").ok(),
+        source_image: row.try_get("", "source_image").ok(),
+        title: row.try_get("", "title").ok(),
+        content_format: row.try_get("", "content_format").ok(),
+        mentioned_user_id: row.try_get("", "mentioned_user_id").ok(),
     }
 }
 
 async fn build_memo_dto(
     db: &DatabaseConnection,
+    origin: &RequestOrigin,
     memo_item: memo::Model,
     current_user_id: Option<i32>,
 ) -> Result<MemoDto, AppError> {
@@ -903,10 +2218,7 @@ async fn build_memo_dto(
         .map_err(|_| AppError::system_exception())?
         .ok_or_else(|| AppError::fail("用户不存在"))?;
 
-    let domain = sys_config_store::get_string(db, "DOMAIN")
-        .await
-        .map_err(|_| AppError::system_exception())?
-        .unwrap_or_default();
+    let url_config = load_resource_url_config(db, origin).await?;
 
     let resources = resource::Entity::find()
         .filter(resource::Column::MemoId.eq(memo_item.id))
@@ -916,7 +2228,7 @@ async fn build_memo_dto(
 
     let resource_dto = resources
         .into_iter()
-        .map(|r| convert_resource(&domain, r))
+        .map(|r| convert_resource(&url_config, r))
         .collect::<Vec<_>>();
 
     let unapproved_count = query_count(
@@ -960,84 +2272,139 @@ async fn build_memo_dto(
         liked,
         resources: resource_dto,
         source: memo_item.source,
+        source_title: memo_item.source_title,
+        source_image: memo_item.source_image,
+        title: memo_item.title,
+        content_format: memo_item.content_format,
+        truncated: false,
     })
 }
 
 async fn build_memo_list_from_rows(
     db: &DatabaseConnection,
+    origin: &RequestOrigin,
     rows: Vec<sea_orm::QueryResult>,
     is_login: bool,
 ) -> Result<Vec<MemoDto>, AppError> {
-    let mut map: std::collections::HashMap<i32, MemoDto> = std::collections::HashMap::new();
-    let domain = sys_config_store::get_string(db, "DOMAIN")
-        .await
-        .map_err(|_| AppError::system_exception())?
-        .unwrap_or_default();
+    // rows arrive sorted by the list query; keep that order while grouping resources per memo
+    let mut list: Vec<MemoDto> = Vec::new();
+    let mut index: std::collections::HashMap<i32, usize> = std::collections::HashMap::new();
+    let url_config = load_resource_url_config(db, origin).await?;
 
     for row in rows {
         let memo_id: i32 = row.try_get("", "id").unwrap_or(0);
-        let entry = map.entry(memo_id).or_insert_with(|| MemoDto {
-            id: memo_id,
-            user_id: row.try_get("", "userId").unwrap_or(0),
-            content: row.try_get("", "content").ok(),
-            tags: row.try_get("", "tags").ok(),
-            visibility: row.try_get("", "visibility").ok(),
-            status: row.try_get("", "status").ok(),
-            created: get_naive_datetime(&row, "created").map(to_rfc3339_naive),
-            updated: get_naive_datetime(&row, "updated").map(to_rfc3339_naive),
-            author_name: row.try_get("", "authorName").ok(),
-            author_role: row.try_get("", "authorRole").ok(),
-            email: row.try_get("", "email").ok(),
-            bio: row.try_get("", "bio").ok(),
-            priority: row.try_get("", "priority").unwrap_or(0),
-            comment_count: row.try_get("", "commentCount").unwrap_or(0),
-            un_approved_comment_count: 0,
-            like_count: row.try_get("", "likeCount").unwrap_or(0),
-            enable_comment: row.try_get("", "enableComment").unwrap_or(0),
-            view_count: row.try_get("", "viewCount").unwrap_or(0),
-            liked: if is_login { if row.try_get::<Option<i32>>("", "liked").unwrap_or(None).is_some() { 1 } else { 0 } } else { 0 },
-            resources: Vec::new(),
-            source: row.try_get("", "source").ok(),
+        let pos = *index.entry(memo_id).or_insert_with(|| {
+            list.push(MemoDto {
+                id: memo_id,
+                user_id: row.try_get("", "userId").unwrap_or(0),
+                content: row.try_get("", "content").ok(),
+                tags: row.try_get("", "tags").ok(),
+                visibility: row.try_get("", "visibility").ok(),
+                status: row.try_get("", "status").ok(),
+                created: get_naive_datetime(&row, "created").map(to_rfc3339_naive),
+                updated: get_naive_datetime(&row, "updated").map(to_rfc3339_naive),
+                author_name: row.try_get("", "authorName").ok(),
+                author_role: row.try_get("", "authorRole").ok(),
+                email: row.try_get("", "email").ok(),
+                bio: row.try_get("", "bio").ok(),
+                priority: row.try_get("", "priority").unwrap_or(0),
+                comment_count: row.try_get("", "commentCount").unwrap_or(0),
+                un_approved_comment_count: 0,
+                like_count: row.try_get("", "likeCount").unwrap_or(0),
+                enable_comment: row.try_get("", "enableComment").unwrap_or(0),
+                view_count: row.try_get("", "viewCount").unwrap_or(0),
+                liked: if is_login { if row.try_get::<Option<i32>>("", "liked").unwrap_or(None).is_some() { 1 } else { 0 } } else { 0 },
+                resources: Vec::new(),
+                source: row.try_get("", "source").ok(),
+                source_title: row.try_get("", "sourceTitle").ok(),
+                source_image: row.try_get("", "sourceImage").ok(),
+                title: row.try_get("", "title").ok(),
+                content_format: row.try_get("", "contentFormat").ok(),
+                truncated: false,
+            });
+            list.len() - 1
         });
+        let entry = &mut list[pos];
 
         let public_id = row.try_get::<String>("", "publicId").ok();
-        if let Some(public_id) = public_id {
-            if !public_id.is_empty() {
-                let resource_dto = ResourceDto {
-                    public_id,
-                    url: build_resource_url(&domain, row.try_get("", "url").ok(), row.try_get("", "storageType").ok()),
-                    file_type: row.try_get("", "fileType").ok(),
-                    suffix: row.try_get("", "suffix").ok(),
-                    storage_type: row.try_get("", "storageType").ok(),
-                    file_name: row.try_get("", "fileName").ok(),
-                };
-                entry.resources.push(resource_dto);
-            }
+        if let Some(public_id) = public_id
+            && !public_id.is_empty()
+        {
+            let link: Option<String> = row.try_get("", "url").ok();
+            let storage_type: Option<String> = row.try_get("", "storageType").ok();
+            let file_type: Option<String> = row.try_get("", "fileType").ok();
+            let resource_dto = ResourceDto {
+                public_id,
+                url: build_resource_url(&url_config.domain, link.clone(), storage_type.clone()),
+                thumbnail_url: build_thumbnail_url(&url_config, link, storage_type, file_type.as_deref()),
+                file_type,
+                suffix: row.try_get("", "suffix").ok(),
+                storage_type: row.try_get("", "storageType").ok(),
+                file_name: row.try_get("", "fileName").ok(),
+                alt: row.try_get("", "alt").ok(),
+            };
+            entry.resources.push(resource_dto);
         }
     }
 
-    for memo in map.values_mut() {
-        let count = query_count(
-            db,
-            "select count(1) as cnt from t_comment where memo_id = ? and user_id < 0 and approved = 0",
-            vec![memo.id.into()],
-        )
-        .await?;
-        memo.un_approved_comment_count = count;
+    if list.is_empty() {
+        return Ok(list);
+    }
+    // 一次查出整页的待审核评论数,不再每条memo单独count
+    let unapproved_sql = format!(
+        "select memo_id, count(1) as cnt from t_comment where memo_id in ({}) and user_id < 0 and approved = 0 group by memo_id",
+        vec!["?"; list.len()].join(",")
+    );
+    let unapproved = query_all(db, &unapproved_sql, list.iter().map(|m| m.id.into()).collect())
+        .await?
+        .into_iter()
+        .map(|row| {
+            (
+                row.try_get::<i32>("", "memo_id").unwrap_or(0),
+                row.try_get::<i64>("", "cnt").unwrap_or(0),
+            )
+        })
+        .collect::<std::collections::HashMap<_, _>>();
+    for memo in list.iter_mut() {
+        memo.un_approved_comment_count = unapproved.get(&memo.id).copied().unwrap_or(0);
     }
 
-    Ok(map.into_values().collect())
+    Ok(list)
+}
+
+struct ResourceUrlConfig {
+    domain: String,
+    thumbnail_template: String,
+    thumbnail_size: String,
 }
 
-fn convert_resource(domain: &str, r: resource::Model) -> ResourceDto {
-    let url = build_resource_url(domain, r.external_link.clone(), r.storage_type.clone());
+async fn load_resource_url_config(db: &DatabaseConnection, origin: &RequestOrigin) -> Result<ResourceUrlConfig, AppError> {
+    let get = |key: &'static str| async move {
+        sys_config_store::get_string(db, key)
+            .await
+            .map_err(|_| AppError::system_exception())
+            .map(|v| v.unwrap_or_default())
+    };
+    Ok(ResourceUrlConfig {
+        domain: origin.resolve(&get("DOMAIN").await?),
+        thumbnail_template: get("THUMBNAIL_URL_TEMPLATE").await?,
+        thumbnail_size: get("THUMBNAIL_SIZE").await?,
+    })
+}
+
+fn convert_resource(url_config: &ResourceUrlConfig, r: resource::Model) -> ResourceDto {
+    let url = build_resource_url(&url_config.domain, r.external_link.clone(), r.storage_type.clone());
+    let thumbnail_url =
+        build_thumbnail_url(url_config, r.external_link.clone(), r.storage_type.clone(), Some(&r.file_type));
     ResourceDto {
         public_id: r.public_id,
         url,
+        thumbnail_url,
         file_type: Some(r.file_type),
         suffix: r.suffix,
         storage_type: r.storage_type,
         file_name: Some(r.file_name),
+        alt: r.alt,
     }
 }
 
@@ -1050,6 +2417,41 @@ fn build_resource_url(domain: &str, external_link: Option<String>, storage_type:
     }
 }
 
+/// Local images the resource endpoint can resize get its `?thumbnail=true` URL (served by
+/// `resource::get_resource`, which falls back to the original); images on other storages are
+/// rendered through `THUMBNAIL_URL_TEMPLATE` (`{url}`, `{size}`, `{width}`, `{height}`). Anything
+/// else has no thumbnail.
+fn build_thumbnail_url(
+    url_config: &ResourceUrlConfig,
+    external_link: Option<String>,
+    storage_type: Option<String>,
+    file_type: Option<&str>,
+) -> Option<String> {
+    let file_type = file_type.unwrap_or_default();
+    let is_local = storage_type.as_deref() == Some("LOCAL");
+    let url = build_resource_url(&url_config.domain, external_link, storage_type);
+    if url.is_empty() || !file_type.starts_with("image/") {
+        return None;
+    }
+    if is_local {
+        return super::resource::has_thumbnail(file_type).then(|| format!("{}?thumbnail=true", url));
+    }
+    if url_config.thumbnail_template.trim().is_empty() {
+        return None;
+    }
+    let mut dims = url_config.thumbnail_size.split(',').map(str::trim);
+    let width = dims.next().unwrap_or("");
+    let height = dims.next().unwrap_or(width);
+    Some(
+        url_config
+            .thumbnail_template
+            .replace("{url}", &url)
+            .replace("{size}", &url_config.thumbnail_size)
+            .replace("{width}", width)
+            .replace("{height}", height),
+    )
+}
+
 fn to_rfc3339_naive(dt: NaiveDateTime) -> String {
     chrono::DateTime::<Utc>::from_naive_utc_and_offset(dt, Utc)
         .to_rfc3339_opts(SecondsFormat::Millis, false)
@@ -1094,80 +2496,568 @@ fn parse_db_datetime(input: String) -> Option<NaiveDateTime> {
     })
 }
 
-fn to_millis(dt: DateTime<Utc>) -> i64 {
-    dt.timestamp_millis()
-}
+#[cfg(test)]
+mod tests {
+    use actix_web::{test as web_test, App};
+
+    use super::*;
+    use crate::test_util::{auth_user, TestEnv, ADMIN_ID};
+
+    fn memo_app(
+        app_config: AppConfig,
+        db: DatabaseConnection,
+    ) -> App<
+        impl actix_web::dev::ServiceFactory<
+            actix_web::dev::ServiceRequest,
+            Config = (),
+            Response = actix_web::dev::ServiceResponse,
+            Error = actix_web::Error,
+            InitError = (),
+        >,
+    > {
+        App::new()
+            .app_data(web::Data::new(app_config))
+            .app_data(web::Data::new(db))
+            .service(web::scope("/api/memo").configure(config))
+    }
 
-fn notify_webhook_async(db: DatabaseConnection, memo_id: i32) {
-    actix_web::rt::spawn(async move {
-        let _ = notify_webhook(&db, memo_id).await;
-    });
-}
+    #[test]
+    fn parse_tags_reads_every_body_line() {
+        assert_eq!(parse_tags("body text #rust\nmore #async"), vec!["#rust", "#async"]);
+    }
 
-async fn notify_webhook(db: &DatabaseConnection, memo_id: i32) -> Result<(), AppError> {
-    let url = sys_config_store::get_string(db, "WEB_HOOK_URL")
-        .await
-        .map_err(|_| AppError::system_exception())?
-        .unwrap_or_default();
-    let token = sys_config_store::get_string(db, "WEB_HOOK_TOKEN")
-        .await
-        .map_err(|_| AppError::system_exception())?
-        .unwrap_or_default();
+    #[test]
+    fn parse_tags_skips_code_blocks_and_duplicates() {
+        let content = "#a, #b。\n```\n#not_a_tag\n```\n#a";
+        assert_eq!(parse_tags(content), vec!["#a", "#b"]);
+    }
 
-    let memo_item = memo::Entity::find_by_id(memo_id)
-        .one(db)
-        .await
-        .map_err(|_| AppError::system_exception())?
-        .ok_or_else(|| AppError::fail("memo不存在"))?;
-    if memo_item.visibility.as_deref() != Some("PUBLIC") || url.is_empty() {
-        return Ok(());
+    #[actix_web::test]
+    async fn save_counts_a_repeated_tag_once() {
+        let env = TestEnv::new().await;
+        let token = env.token(ADMIN_ID).await;
+        let app = web_test::init_service(memo_app(env.config.clone(), env.db.clone())).await;
+        let req = web_test::TestRequest::post()
+            .uri("/api/memo/save")
+            .insert_header(("token", token))
+            .set_json(serde_json::json!({"content": "#a #a"}))
+            .to_request();
+        let res: serde_json::Value = web_test::call_and_read_body_json(&app, req).await;
+        assert_eq!(res["code"], 0, "{res}");
+
+        let saved = memo::Entity::find_by_id(res["data"].as_i64().unwrap() as i32).one(&env.db).await.unwrap().unwrap();
+        assert_eq!(saved.tags.as_deref(), Some("#a,"));
+        let tag = crate::entity::tag::Entity::find()
+            .filter(crate::entity::tag::Column::Name.eq("#a"))
+            .one(&env.db)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(tag.memo_count, Some(1));
     }
 
-    let user_model = user::Entity::find_by_id(memo_item.user_id)
-        .one(db)
-        .await
-        .map_err(|_| AppError::system_exception())?
-        .ok_or_else(|| AppError::fail("用户不存在"))?;
+    #[actix_web::test]
+    async fn image_only_memo_saves_and_feeds_with_placeholder_title() {
+        let env = TestEnv::new().await;
+        env.exec(
+            "insert into t_resource (public_id, memo_id, user_id, file_type, file_name, file_hash, size, storage_type) \
+             values ('img1', 0, ?, 'image/png', 'cat.png', 'h', 1, 'LOCAL')",
+            vec![ADMIN_ID.into()],
+        )
+        .await;
+        let token = env.token(ADMIN_ID).await;
+        let app = web_test::init_service(
+            memo_app(env.config.clone(), env.db.clone()).service(web::scope("/rss").configure(crate::routes::rss::config)),
+        )
+        .await;
+
+        let req = web_test::TestRequest::post()
+            .uri("/api/memo/save")
+            .insert_header(("token", token))
+            .set_json(serde_json::json!({"content": "", "publicIds": ["img1"], "visibility": "PUBLIC"}))
+            .to_request();
+        let res: serde_json::Value = web_test::call_and_read_body_json(&app, req).await;
+        assert_eq!(res["code"], 0, "{res}");
+        let memo_id = res["data"].as_i64().unwrap();
+
+        let req = web_test::TestRequest::post().uri(&format!("/api/memo/{}", memo_id)).to_request();
+        let res: serde_json::Value = web_test::call_and_read_body_json(&app, req).await;
+        assert_eq!(res["data"]["content"], "");
+        assert_eq!(res["data"]["resources"][0]["publicId"], "img1");
+
+        let req = web_test::TestRequest::get().uri("/rss").to_request();
+        let xml = String::from_utf8(web_test::call_and_read_body(&app, req).await.to_vec()).unwrap();
+        assert!(xml.contains(&format!("<title>{}</title>", render::resource_placeholder("image/png", "cat.png"))), "{xml}");
+    }
 
-    let resources = resource::Entity::find()
-        .filter(resource::Column::MemoId.eq(memo_id))
-        .all(db)
-        .await
-        .map_err(|_| AppError::system_exception())?;
+    #[test]
+    fn rename_tag_rewrites_body_outside_code() {
+        let content = "#rust notes\nlearning #rust, #rusty!\n```\n#rust\n```";
+        let renamed = rename_tag_in_content(content, "#rust", "#lang");
+        assert_eq!(renamed, "#lang notes\nlearning #lang, #rusty!\n```\n#rust\n```");
+        assert_eq!(parse_tags(&renamed), vec!["#lang", "#rusty"]);
+    }
 
-    let backend_url = sys_config_store::get_string(db, "DOMAIN")
-        .await
-        .map_err(|_| AppError::system_exception())?
-        .unwrap_or_default();
+    #[test]
+    fn replace_first_line_removes_whole_tags_only() {
+        let tags = vec!["#rust".to_string(), "#a".to_string()];
+        assert_eq!(
+            replace_first_line("see http://x.com/#rust-guide #rust, #a\nbody", &tags),
+            "see http://x.com/#rust-guide\nbody"
+        );
+        assert_eq!(replace_first_line("#rust #a\nbody #rust", &tags), "body #rust");
+        assert_eq!(replace_first_line("#rusty #rust! notes", &tags), "#rusty notes");
+    }
 
-    let resource_urls = resources
-        .into_iter()
-        .map(|r| format!("{}/api/resource/{}", backend_url, r.public_id))
-        .collect::<Vec<_>>();
+    #[test]
+    fn parse_bool_accepts_common_spellings() {
+        for value in ["true", " TRUE ", "1", "yes", "Yes"] {
+            assert_eq!(parse_bool(value), Some(true), "{value:?}");
+        }
+        for value in ["false", "0", "no", " NO"] {
+            assert_eq!(parse_bool(value), Some(false), "{value:?}");
+        }
+        for value in ["", "on", "2", "maybe"] {
+            assert_eq!(parse_bool(value), None, "{value:?}");
+        }
+    }
 
-    #[derive(Serialize)]
-    #[serde(rename_all = "camelCase")]
-    struct Payload {
-        content: Option<String>,
-        tags: Option<String>,
-        created: i64,
-        author_name: Option<String>,
-        resources: Vec<String>,
-    }
-
-    let payload = Payload {
-        content: memo_item.content.clone(),
-        tags: memo_item.tags.clone(),
-        created: memo_item.created.map(to_millis).unwrap_or(0),
-        author_name: user_model.display_name.clone(),
-        resources: resource_urls,
-    };
+    #[actix_web::test]
+    async fn default_enable_comment_falls_back_from_user_to_site() {
+        let env = TestEnv::new().await;
+        let set_user = |value: Option<&str>| {
+            env.exec(
+                "update t_user set default_enable_comment = ? where id = ?",
+                vec![value.map(str::to_string).into(), ADMIN_ID.into()],
+            )
+        };
 
-    let client = reqwest::Client::new();
-    let mut req = client.post(url).json(&payload);
-    if !token.is_empty() {
-        req = req.header("token", token);
+        set_user(None).await;
+        env.set_config("DEFAULT_ENABLE_COMMENT", "").await;
+        assert!(!default_enable_comment(&env.db, ADMIN_ID).await.unwrap());
+
+        env.set_config("DEFAULT_ENABLE_COMMENT", "true").await;
+        assert!(default_enable_comment(&env.db, ADMIN_ID).await.unwrap());
+        set_user(Some("not a bool")).await;
+        assert!(default_enable_comment(&env.db, ADMIN_ID).await.unwrap());
+
+        set_user(Some("no")).await;
+        assert!(!default_enable_comment(&env.db, ADMIN_ID).await.unwrap());
+        env.set_config("DEFAULT_ENABLE_COMMENT", "false").await;
+        set_user(Some("yes")).await;
+        assert!(default_enable_comment(&env.db, ADMIN_ID).await.unwrap());
+    }
+
+    #[test]
+    fn editable_content_round_trips_through_parse() {
+        let inputs = [
+            "#rust #async\nbody text",
+            "#rust, #async body on the tag line\nmore",
+            "Weekly notes\n#rust\nbody with #async inline",
+            "Title only #rust",
+            "plain text without tags",
+            "Title\n\n#rust\n```\n#not-a-tag\n```",
+        ];
+        for title_mode in [false, true] {
+            for strip_tags in [false, true] {
+                for input in inputs {
+                    let stored = parse_memo_content(input, title_mode, strip_tags);
+                    let edited = editable_content(&stored.content, &stored.tags, title_mode);
+                    let saved = parse_memo_content(&edited, title_mode, strip_tags);
+                    let case = format!("{input:?} title_mode={title_mode} strip_tags={strip_tags}");
+                    assert_eq!(saved.content, stored.content, "{case}");
+                    assert_eq!(saved.tags, stored.tags, "{case}");
+                    assert_eq!(saved.title, stored.title, "{case}");
+                }
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn update_rejects_stale_if_updated_at() {
+        let env = TestEnv::new().await;
+        env.exec("insert into t_memo (id, user_id, content) values (1, ?, 'first')", vec![ADMIN_ID.into()])
+            .await;
+        let db = web::Data::new(env.db.clone());
+        let save = |content: &str, if_updated_at: Option<String>| {
+            let payload: SaveMemoRequest = serde_json::from_value(serde_json::json!({
+                "id": 1,
+                "content": content,
+                "ifUpdatedAt": if_updated_at,
+            }))
+            .unwrap();
+            update(db.clone(), auth_user(ADMIN_ID, "ADMIN"), Json(payload))
+        };
+        let load = || async { memo::Entity::find_by_id(1).one(&env.db).await.unwrap().unwrap() };
+
+        save("second", None).await.unwrap();
+        let loaded = load().await.updated.unwrap();
+        let stale = (loaded - Duration::seconds(1)).to_rfc3339();
+
+        let err = save("lost update", Some(stale)).await.unwrap_err();
+        assert_eq!(err.msg(), "memo已被其他人修改");
+        assert_eq!(load().await.content.as_deref(), Some("second"));
+
+        save("third", Some(loaded.to_rfc3339())).await.unwrap();
+        assert_eq!(load().await.content.as_deref(), Some("third"));
+
+        let err = save("fourth", Some("yesterday".to_string())).await.unwrap_err();
+        assert_eq!(err.code(), 1);
+    }
+
+    #[actix_web::test]
+    async fn page_past_offset_cap_is_empty_with_total() {
+        let env = TestEnv::new().await;
+        for content in ["one", "two", "three"] {
+            env.exec("insert into t_memo (user_id, content) values (?, ?)", vec![ADMIN_ID.into(), content.into()])
+                .await;
+        }
+        env.set_config("MAX_PAGE_OFFSET", "1").await;
+        assert!(!offset_exceeds_cap(&env.db, 1).await.unwrap());
+        assert!(offset_exceeds_cap(&env.db, 2).await.unwrap());
+
+        let app = web_test::init_service(memo_app(env.config.clone(), env.db.clone())).await;
+        let page = |page: i64| {
+            web_test::TestRequest::post()
+                .uri("/api/memo/list")
+                .set_json(serde_json::json!({"page": page, "size": 1}))
+                .to_request()
+        };
+
+        let res: serde_json::Value = web_test::call_and_read_body_json(&app, page(2)).await;
+        assert_eq!(res["data"]["items"].as_array().unwrap().len(), 1);
+
+        let res: serde_json::Value = web_test::call_and_read_body_json(&app, page(3)).await;
+        assert_eq!(res["code"], 0);
+        assert!(res["data"]["items"].as_array().unwrap().is_empty());
+        assert_eq!(res["data"]["total"], 3);
+        assert_eq!(res["data"]["totalPage"], 3);
+
+        env.set_config("MAX_PAGE_OFFSET", "0").await;
+        assert!(!offset_exceeds_cap(&env.db, 1_000_000).await.unwrap());
+    }
+
+    #[actix_web::test]
+    async fn pages_are_stable_for_identical_created() {
+        let env = TestEnv::new().await;
+        for id in 1..=4 {
+            env.exec(
+                "insert into t_memo (id, user_id, content, created) values (?, ?, 'same time', '2024-01-01 00:00:00')",
+                vec![id.into(), ADMIN_ID.into()],
+            )
+            .await;
+        }
+        let app = web_test::init_service(memo_app(env.config.clone(), env.db.clone())).await;
+        let page = |page: i64| {
+            web_test::TestRequest::post()
+                .uri("/api/memo/list")
+                .set_json(serde_json::json!({"page": page, "size": 2}))
+                .to_request()
+        };
+        let ids = |body: serde_json::Value| {
+            body["data"]["items"].as_array().unwrap().iter().map(|m| m["id"].as_i64().unwrap()).collect::<Vec<_>>()
+        };
+
+        for _ in 0..3 {
+            assert_eq!(ids(web_test::call_and_read_body_json(&app, page(1)).await), vec![4, 3]);
+            assert_eq!(ids(web_test::call_and_read_body_json(&app, page(2)).await), vec![2, 1]);
+        }
+    }
+
+    #[actix_web::test]
+    async fn concurrent_likes_count_once() {
+        let env = TestEnv::new().await;
+        env.exec("insert into t_memo (id, user_id, content) values (1, ?, 'like me')", vec![ADMIN_ID.into()]).await;
+        env.set_config("OPEN_LIKE", "true").await;
+        let token = env.token(ADMIN_ID).await;
+        let app = web_test::init_service(memo_app(env.config.clone(), env.db.clone())).await;
+        let like = || {
+            web_test::TestRequest::post()
+                .uri("/api/memo/relation")
+                .insert_header(("token", token.clone()))
+                .set_json(serde_json::json!({"memoId": 1, "type": "LIKE", "operateType": "ADD"}))
+                .to_request()
+        };
+
+        let (a, b) = futures_util::join!(web_test::call_service(&app, like()), web_test::call_service(&app, like()));
+        assert!(a.status().is_success() && b.status().is_success());
+
+        let relations = user_memo_relation::Entity::find()
+            .filter(user_memo_relation::Column::MemoId.eq(1))
+            .count(&env.db)
+            .await
+            .unwrap();
+        assert_eq!(relations, 1);
+        let memo = memo::Entity::find_by_id(1).one(&env.db).await.unwrap().unwrap();
+        assert_eq!(memo.like_count, Some(1));
+    }
+
+    #[actix_web::test]
+    async fn list_caps_resources_per_memo_in_sql() {
+        let env = TestEnv::new().await;
+        env.exec("insert into t_memo (id, user_id, content) values (1, ?, 'pics')", vec![ADMIN_ID.into()]).await;
+        env.exec("insert into t_memo (id, user_id, content) values (2, ?, 'one pic')", vec![ADMIN_ID.into()]).await;
+        env.exec(
+            "insert into t_resource (public_id, memo_id, user_id, file_type, file_name, file_hash, size, storage_type) values \
+             ('r1', 1, ?, 'image/png', 'a.png', 'h', 1, 'LOCAL'), ('r2', 1, ?, 'image/png', 'b.png', 'h', 1, 'LOCAL'), \
+             ('r3', 1, ?, 'image/png', 'c.png', 'h', 1, 'LOCAL'), ('r4', 2, ?, 'image/png', 'd.png', 'h', 1, 'LOCAL')",
+            vec![ADMIN_ID.into(), ADMIN_ID.into(), ADMIN_ID.into(), ADMIN_ID.into()],
+        )
+        .await;
+        let app = web_test::init_service(memo_app(env.config.clone(), env.db.clone())).await;
+        let resources = |body: serde_json::Value| {
+            body["data"]["items"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|m| (m["id"].as_i64().unwrap(), m["resources"].as_array().unwrap().len()))
+                .collect::<Vec<_>>()
+        };
+        let list = || web_test::TestRequest::post().uri("/api/memo/list").set_json(serde_json::json!({})).to_request();
+
+        let res: serde_json::Value = web_test::call_and_read_body_json(&app, list()).await;
+        assert_eq!(resources(res), vec![(2, 1), (1, 3)]);
+
+        env.set_config("MEMO_MAX_RESOURCES", "2").await;
+        let res: serde_json::Value = web_test::call_and_read_body_json(&app, list()).await;
+        assert_eq!(resources(res), vec![(2, 1), (1, 2)]);
+    }
+
+    /// A `memos` format `importExternal` upload of the JSON `export`.
+    fn import_request(token: String, export: &str) -> web_test::TestRequest {
+        let boundary = "mblogimportboundary";
+        let body = format!(
+            "--{b}\r\nContent-Disposition: form-data; name=\"format\"\r\n\r\nmemos\r\n\
+             --{b}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"memos.json\"\r\n\
+             Content-Type: application/json\r\n\r\n{export}\r\n--{b}--\r\n",
+            b = boundary
+        );
+        web_test::TestRequest::post()
+            .uri("/api/memo/importExternal")
+            .insert_header(("token", token))
+            .insert_header((
+                actix_web::http::header::CONTENT_TYPE,
+                format!("multipart/form-data; boundary={}", boundary),
+            ))
+            .set_payload(body)
+    }
+
+    #[actix_web::test]
+    async fn import_applies_length_and_quota_checks_per_item() {
+        let env = TestEnv::new().await;
+        let user_id = ADMIN_ID + 1;
+        env.exec(
+            "insert into t_user (id, username, password_hash, display_name, role) values (?, 'bob', 'x', 'bob', 'USER')",
+            vec![user_id.into()],
+        )
+        .await;
+        env.set_config("MEMO_MIN_LENGTH", "3").await;
+        env.set_config("MEMO_MAX_PER_USER", "2").await;
+        let token = env.token(user_id).await;
+        let app = web_test::init_service(memo_app(env.config.clone(), env.db.clone())).await;
+
+        let export = r#"[{"content":"hello world"},{"content":"hi"},{"content":""},{"content":"another memo"},{"content":"over quota"}]"#;
+        let res: serde_json::Value = web_test::call_and_read_body_json(&app, import_request(token, export).to_request()).await;
+
+        assert_eq!(res["code"], 0, "{res}");
+        assert_eq!(res["data"]["total"], 5);
+        assert_eq!(res["data"]["imported"], 2);
+        assert_eq!(res["data"]["skipped"], 3);
+        let rejected = res["data"]["rejected"].as_array().unwrap();
+        let indexes: Vec<_> = rejected.iter().map(|r| r["index"].as_u64().unwrap()).collect();
+        assert_eq!(indexes, vec![1, 2, 4]);
+        assert_eq!(rejected[0]["reason"], "内容不能少于3个字");
+        assert_eq!(rejected[2]["reason"], "最多只能发布2条memo");
+
+        let contents: Vec<_> = memo::Entity::find()
+            .filter(memo::Column::UserId.eq(user_id))
+            .order_by_asc(memo::Column::Id)
+            .all(&env.db)
+            .await
+            .unwrap()
+            .into_iter()
+            .filter_map(|m| m.content)
+            .collect();
+        assert_eq!(contents, vec!["hello world", "another memo"]);
+    }
+
+    #[actix_web::test]
+    async fn import_parses_like_save() {
+        let env = TestEnv::new().await;
+        env.set_config("MEMO_FIRST_LINE_MODE", "title").await;
+        env.set_config("DEFAULT_ENABLE_COMMENT", "true").await;
+        env.exec("update t_user set default_enable_comment = null where id = ?", vec![ADMIN_ID.into()]).await;
+        let token = env.token(ADMIN_ID).await;
+        let app = web_test::init_service(memo_app(env.config.clone(), env.db.clone())).await;
+
+        let export = r#"[{"content":"Trip notes\n#travel see http://x.com/#map-2\nday one"}]"#;
+        let res: serde_json::Value = web_test::call_and_read_body_json(&app, import_request(token, export).to_request()).await;
+        assert_eq!(res["data"]["imported"], 1, "{res}");
+
+        let imported = memo::Entity::find().one(&env.db).await.unwrap().unwrap();
+        assert_eq!(imported.title.as_deref(), Some("Trip notes"));
+        assert_eq!(imported.tags.as_deref(), Some("#travel,"));
+        assert_eq!(imported.content.as_deref(), Some("Trip notes\nsee http://x.com/#map-2\nday one"));
+        assert_eq!(imported.enable_comment, Some(1));
+    }
+
+    #[test]
+    fn thumbnail_url_only_for_images() {
+        let url_config = ResourceUrlConfig {
+            domain: "https://example.com".to_string(),
+            thumbnail_template: "{url}?imageView2/2/w/{width}/h/{height}".to_string(),
+            thumbnail_size: "200,100".to_string(),
+        };
+        let thumb = |link: &str, storage: &str, file_type: &str| {
+            build_thumbnail_url(&url_config, Some(link.to_string()), Some(storage.to_string()), Some(file_type))
+        };
+
+        assert_eq!(
+            thumb("/api/resource/a", "LOCAL", "image/png").as_deref(),
+            Some("https://example.com/api/resource/a?thumbnail=true")
+        );
+        assert_eq!(thumb("/api/resource/a", "LOCAL", "image/gif"), None);
+        assert_eq!(thumb("/api/resource/a", "LOCAL", "application/pdf"), None);
+        assert_eq!(
+            thumb("https://cdn.example.com/b.gif", "QINIU", "image/gif").as_deref(),
+            Some("https://cdn.example.com/b.gif?imageView2/2/w/200/h/100")
+        );
+        assert_eq!(thumb("https://cdn.example.com/b.mp4", "QINIU", "video/mp4"), None);
+    }
+
+    #[test]
+    fn page_size_is_clamped() {
+        assert_eq!(page_size(None), 20);
+        assert_eq!(page_size(Some(0)), 1);
+        assert_eq!(page_size(Some(-5)), 1);
+        assert_eq!(page_size(Some(50)), 50);
+        assert_eq!(page_size(Some(1_000_000)), MAX_PAGE_SIZE);
+    }
+
+    #[actix_web::test]
+    async fn site_stats_counts_tags_of_visible_memos_only() {
+        let env = TestEnv::new().await;
+        let user_id = ADMIN_ID + 1;
+        env.exec("insert into t_user (id, username, password_hash) values (?, 'bob', 'x')", vec![user_id.into()]).await;
+        for (owner, visibility, status, tags) in [
+            (ADMIN_ID, "PUBLIC", "NORMAL", "#a,#b,"),
+            (user_id, "PUBLIC", "NORMAL", "#a,"),
+            (ADMIN_ID, "PROTECT", "NORMAL", "#c,"),
+            (ADMIN_ID, "PRIVATE", "NORMAL", "#d,"),
+            (ADMIN_ID, "PUBLIC", "TRASH", "#e,"),
+        ] {
+            env.exec(
+                "insert into t_memo (user_id, content, visibility, status, tags) values (?, 'memo', ?, ?, ?)",
+                vec![owner.into(), visibility.into(), status.into(), tags.into()],
+            )
+            .await;
+        }
+        let tags = [(ADMIN_ID, "#a"), (ADMIN_ID, "#b"), (ADMIN_ID, "#c"), (ADMIN_ID, "#d"), (ADMIN_ID, "#e")];
+        for (owner, name) in tags.into_iter().chain([(user_id, "#a")]) {
+            env.exec(
+                "insert into t_tag (name, user_id, memo_count) values (?, ?, 1)",
+                vec![name.into(), owner.into()],
+            )
+            .await;
+        }
+        let app = web_test::init_service(memo_app(env.config.clone(), env.db.clone())).await;
+        let stats = |token: Option<String>| {
+            let req = web_test::TestRequest::get().uri("/api/memo/siteStats");
+            match token {
+                Some(token) => req.insert_header(("token", token)).to_request(),
+                None => req.to_request(),
+            }
+        };
+
+        let res: serde_json::Value = web_test::call_and_read_body_json(&app, stats(None)).await;
+        assert_eq!(res["data"]["totalMemos"], 2);
+        assert_eq!(res["data"]["totalTags"], 2);
+        let res: serde_json::Value = web_test::call_and_read_body_json(&app, stats(Some(env.token(user_id).await))).await;
+        assert_eq!(res["data"]["totalTags"], 3);
+    }
+
+    #[actix_web::test]
+    async fn missing_ids_read_as_null_and_remove_as_noop() {
+        let env = TestEnv::new().await;
+        env.exec("insert into t_memo (id, user_id, content, tags) values (1, ?, 'kept', '#a,')", vec![ADMIN_ID.into()])
+            .await;
+        env.exec("insert into t_tag (id, name, user_id, memo_count) values (1, '#a', ?, 1)", vec![ADMIN_ID.into()])
+            .await;
+        let token = env.token(ADMIN_ID).await;
+        let app = web_test::init_service(
+            memo_app(env.config.clone(), env.db.clone())
+                .service(web::scope("/api/user").configure(crate::routes::user::config))
+                .service(web::scope("/api/tag").configure(crate::routes::tag::config))
+                .service(web::scope("/api/resource").configure(crate::routes::resource::config)),
+        )
+        .await;
+        let call = |uri: &str| web_test::TestRequest::post().uri(uri).insert_header(("token", token.clone())).to_request();
+
+        for uri in ["/api/memo/999", "/api/user/999"] {
+            let res: serde_json::Value = web_test::call_and_read_body_json(&app, call(uri)).await;
+            assert_eq!(res["code"], 0, "{uri}: {res}");
+            assert!(res["data"].is_null(), "{uri}: {res}");
+        }
+        for uri in ["/api/memo/remove?id=999", "/api/tag/remove?id=999", "/api/resource/remove?publicId=missing"] {
+            let res: serde_json::Value = web_test::call_and_read_body_json(&app, call(uri)).await;
+            assert_eq!(res["code"], 0, "{uri}: {res}");
+        }
+
+        assert_eq!(memo::Entity::find().count(&env.db).await.unwrap(), 1);
+        let tag = crate::entity::tag::Entity::find_by_id(1).one(&env.db).await.unwrap().unwrap();
+        assert_eq!(tag.memo_count, Some(1));
+    }
+
+    #[actix_web::test]
+    async fn empty_trash_purges_trashed_memos_in_batches() {
+        let env = TestEnv::new().await;
+        let user_id = ADMIN_ID + 1;
+        env.exec(
+            "insert into t_user (id, username, password_hash, display_name, role) values (?, 'bob', 'x', 'bob', 'USER')",
+            vec![user_id.into()],
+        )
+        .await;
+        env.exec("insert into t_tag (name, user_id, memo_count) values ('#a', ?, ?)", vec![user_id.into(), 151.into()])
+            .await;
+        let trashed = EMPTY_TRASH_BATCH as i32 + 50;
+        for id in 1..=trashed + 1 {
+            let status = if id <= trashed { "TRASH" } else { "NORMAL" };
+            env.exec(
+                "insert into t_memo (id, user_id, content, tags, status) values (?, ?, 'x', '#a,', ?)",
+                vec![id.into(), user_id.into(), status.into()],
+            )
+            .await;
+        }
+        env.exec(
+            "insert into t_comment (memo_id, content, user_id, user_name) values (1, 'c', ?, 'bob'), (?, 'c', ?, 'bob')",
+            vec![user_id.into(), (trashed + 1).into(), user_id.into()],
+        )
+        .await;
+        env.exec(
+            "insert into t_resource (public_id, memo_id, user_id, file_type, file_name, file_hash, size, storage_type) \
+             values ('r1', 2, ?, 'image/png', 'a.png', 'h', 1, 'QINIU')",
+            vec![user_id.into()],
+        )
+        .await;
+        let db = web::Data::new(env.db.clone());
+        let purge = |auth: AuthUser, target: Option<i32>| {
+            let payload: EmptyTrashRequest = serde_json::from_value(serde_json::json!({ "userId": target })).unwrap();
+            empty_trash(db.clone(), auth, Some(Json(payload)))
+        };
+
+        let err = purge(auth_user(ADMIN_ID + 2, "USER"), Some(user_id)).await.unwrap_err();
+        assert_eq!(err.msg(), "不能操作其他人的记录");
+
+        let res = purge(auth_user(ADMIN_ID, "ADMIN"), Some(user_id)).await.unwrap();
+        let body: serde_json::Value =
+            serde_json::from_slice(&actix_web::body::to_bytes(res.into_body()).await.unwrap()).unwrap();
+        assert_eq!(body["data"], trashed);
+
+        let left: Vec<i32> = memo::Entity::find().all(&env.db).await.unwrap().into_iter().map(|m| m.id).collect();
+        assert_eq!(left, vec![trashed + 1]);
+        assert_eq!(comment::Entity::find().all(&env.db).await.unwrap().len(), 1);
+        assert!(resource::Entity::find().all(&env.db).await.unwrap().is_empty());
+        let tag = crate::entity::tag::Entity::find().one(&env.db).await.unwrap().unwrap();
+        assert_eq!(tag.memo_count, Some(1));
     }
-    let _ = req.send().await;
-    Ok(())
 }
@@ -1,5 +1,15 @@
+//! HTTP handlers, one module per API prefix.
+//!
+//! Missing-resource policy shared by all handlers:
+//! - fetches of a single record (`/api/memo/{id}`, `/api/user/{id}`) return `data: null`,
+//!   also when the caller may not see the record;
+//! - removals (`memo/remove`, `comment/remove`, `tag/remove`) are idempotent and succeed
+//!   when the target is already gone;
+//! - any other mutation of a specific record fails with `AppError::fail("...不存在")`.
+
 pub mod cors;
 pub mod memo;
+pub mod read_only;
 pub mod tag;
 pub mod comment;
 pub mod sys_config;
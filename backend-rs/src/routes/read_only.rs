@@ -0,0 +1,67 @@
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    middleware::Next,
+    web, Error,
+};
+use sea_orm::DatabaseConnection;
+
+use crate::auth::OptionalAuthUser;
+use crate::error::AppError;
+use crate::sys_config as sys_config_store;
+
+/// State-changing endpoints rejected while `READ_ONLY_MODE` is on. Everything else (list,
+/// detail, statistics, rss, resource download, login/logout) keeps working.
+const WRITE_ROUTES: &[&str] = &[
+    "/api/memo/save",
+    "/api/memo/update",
+    "/api/memo/patch",
+    "/api/memo/toggleComment",
+    "/api/memo/remove",
+    "/api/memo/emptyTrash",
+    "/api/memo/batchRemove",
+    "/api/memo/importExternal",
+    "/api/memo/setPriority",
+    "/api/memo/relation",
+    "/api/comment/add",
+    "/api/comment/remove",
+    "/api/comment/singleApprove",
+    "/api/comment/memoApprove",
+    "/api/comment/like",
+    "/api/comment/unlike",
+    "/api/tag/save",
+    "/api/tag/remove",
+    "/api/resource/upload",
+    "/api/resource/update",
+    "/api/resource/remove",
+    "/api/user/register",
+    "/api/user/update",
+    "/api/user/notifications/read",
+    "/api/user/sessions/revoke",
+    "/api/user/sessions/revokeOthers",
+    "/api/token/reset",
+    "/api/token/enable",
+    "/api/token/disable",
+];
+
+/// Rejects writes for everyone but admins while the site is in read-only mode.
+pub async fn read_only_guard(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    if WRITE_ROUTES.contains(&req.path())
+        && let Some(db) = req.app_data::<web::Data<DatabaseConnection>>().cloned()
+    {
+        let read_only = sys_config_store::get_boolean(db.get_ref(), "READ_ONLY_MODE")
+            .await
+            .map_err(|_| AppError::system_exception())?;
+        if read_only {
+            let auth = req.extract::<OptionalAuthUser>().await?;
+            let is_admin = auth.0.is_some_and(|a| a.role.as_deref() == Some("ADMIN"));
+            if !is_admin {
+                return Err(AppError::fail("站点处于只读模式").into());
+            }
+        }
+    }
+    next.call(req).await
+}
@@ -1,29 +1,50 @@
-use actix_multipart::Multipart;
-use actix_web::{web, HttpResponse};
+use actix_multipart::{Field, Multipart, MultipartError};
+use actix_files::NamedFile;
+use actix_web::http::header::{self, EntityTag, HeaderValue};
+use actix_web::{error::PayloadError, mime, web, web::Bytes, HttpMessage, HttpRequest, HttpResponse};
 use aws_sdk_s3::config::Region;
 use aws_sdk_s3::primitives::ByteStream;
-use aws_sdk_s3::types::ObjectCannedAcl;
+use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart, ObjectCannedAcl};
 use aws_sdk_s3::Client as S3Client;
-use chrono::Utc;
-use futures_util::StreamExt;
+use chrono::{Duration, Utc};
+use futures_util::{Stream, StreamExt};
+use log::{error, info, warn};
+use base64::engine::general_purpose::URL_SAFE;
+use base64::Engine;
+use hmac::{Hmac, Mac};
 use md5::{Digest, Md5};
-use sea_orm::{ActiveModelTrait, DatabaseConnection, EntityTrait, Set};
-use serde::Serialize;
+use sha1::Sha1;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait, PaginatorTrait, QueryFilter,
+    QueryOrder, QuerySelect, Set, Statement,
+};
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::collections::HashMap;
 use std::fs;
 use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use crate::auth::AuthUser;
 use crate::config::AppConfig;
 use crate::entity::resource;
 use crate::error::AppError;
+use crate::request::Json;
 use crate::response::ResponseDto;
 use crate::sys_config as sys_config_store;
 
 pub fn config(cfg: &mut web::ServiceConfig) {
     cfg.service(web::resource("/upload").route(web::post().to(upload)))
-        .service(web::resource("/{public_id}").route(web::get().to(get_resource)));
+        .service(web::resource("/update").route(web::post().to(update)))
+        .service(web::resource("/remove").route(web::post().to(remove)))
+        .service(web::resource("/storageReport").route(web::post().to(storage_report)))
+        .service(web::resource("/migrate").route(web::post().to(migrate)))
+        .service(
+            web::resource("/{public_id}")
+                .route(web::get().to(get_resource))
+                .route(web::head().to(get_resource)),
+        );
 }
 
 #[derive(Serialize)]
@@ -37,18 +58,141 @@ struct UploadResourceResponse {
     file_name: String,
 }
 
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UpdateResourceRequest {
+    public_id: String,
+    #[serde(default, deserialize_with = "crate::request::trimmed")]
+    alt: Option<String>,
+}
+
+const RESOURCE_ALT_MAX_CHARS: usize = 500;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RemoveResourceQuery {
+    public_id: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct StorageReportItem {
+    storage_type: String,
+    count: i64,
+    total_size: i64,
+    oldest: Option<String>,
+    newest: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct MigrateRequest {
+    batch_size: Option<u64>,
+    #[serde(default)]
+    dry_run: bool,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct MigrateResponse {
+    dry_run: bool,
+    target: String,
+    /// Resources moved in this batch, or that would be moved on a dry run.
+    migrated: Vec<String>,
+    failed: Vec<String>,
+    bytes: i64,
+    /// LOCAL resources still left after this batch.
+    remaining: u64,
+}
+
+const MIGRATE_DEFAULT_BATCH: u64 = 20;
+const MIGRATE_MAX_BATCH: u64 = 100;
+
+struct StoredFile {
+    url: String,
+    suffix: String,
+    size: u64,
+    file_hash: String,
+    file_type: String,
+    internal_path: Option<String>,
+}
+
+static ACTIVE_UPLOADS: AtomicUsize = AtomicUsize::new(0);
+
+struct UploadSlot;
+
+impl UploadSlot {
+    fn acquire(max: usize) -> Option<Self> {
+        let prev = ACTIVE_UPLOADS.fetch_add(1, Ordering::AcqRel);
+        if max > 0 && prev >= max {
+            ACTIVE_UPLOADS.fetch_sub(1, Ordering::AcqRel);
+            return None;
+        }
+        Some(UploadSlot)
+    }
+}
+
+impl Drop for UploadSlot {
+    fn drop(&mut self) {
+        ACTIVE_UPLOADS.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Sets the alt text / caption of one of the caller's resources; a blank value clears it.
+async fn update(
+    db: web::Data<DatabaseConnection>,
+    auth: AuthUser,
+    payload: Json<UpdateResourceRequest>,
+) -> Result<HttpResponse, AppError> {
+    if payload.alt.as_ref().is_some_and(|a| a.chars().count() > RESOURCE_ALT_MAX_CHARS) {
+        return Err(AppError::param_error("alt"));
+    }
+    let exist = resource::Entity::find_by_id(payload.public_id.clone())
+        .one(db.get_ref())
+        .await
+        .map_err(|_| AppError::system_exception())?
+        .ok_or_else(|| AppError::fail("resource不存在"))?;
+    if exist.user_id != auth.user_id {
+        return Err(AppError::fail("不能操作其他人的资源"));
+    }
+
+    let active = resource::ActiveModel {
+        public_id: Set(exist.public_id),
+        alt: Set(payload.alt.clone()),
+        updated: Set(Some(Utc::now())),
+        ..Default::default()
+    };
+    active
+        .update(db.get_ref())
+        .await
+        .map_err(|_| AppError::system_exception())?;
+    Ok(HttpResponse::Ok().json(ResponseDto::<()>::success(None)))
+}
+
 async fn upload(
     db: web::Data<DatabaseConnection>,
     config: web::Data<AppConfig>,
     auth: AuthUser,
     mut payload: Multipart,
 ) -> Result<HttpResponse, AppError> {
-    let storage_type = sys_config_store::get_string(db.get_ref(), "STORAGE_TYPE")
+    let _slot = UploadSlot::acquire(config.max_concurrent_uploads)
+        .ok_or_else(|| AppError::fail("当前上传任务过多,请稍后再试"))?;
+    let files_allowed = check_upload_rate(db.get_ref(), &config, &auth).await?;
+    let max_bytes = max_upload_bytes(db.get_ref(), &config).await?;
+    let allowed_suffixes = load_allowed_suffixes(db.get_ref()).await?;
+    let dedup = sys_config_store::get_boolean(db.get_ref(), "DEDUP_UPLOADS")
+        .await
+        .map_err(|_| AppError::system_exception())?;
+
+    let mut storage_type = sys_config_store::get_string(db.get_ref(), "STORAGE_TYPE")
         .await
         .map_err(|_| AppError::system_exception())?
         .unwrap_or_else(|| "LOCAL".to_string());
 
     let mut responses = Vec::new();
+    let mut models = Vec::new();
+    // Non-file fields, e.g. an `alt` caption sent alongside the files.
+    let mut form: HashMap<String, String> = HashMap::new();
 
     loop {
         let item = payload.next().await;
@@ -58,13 +202,28 @@ async fn upload(
         };
         let mut field = match item {
             Ok(field) => field,
-            Err(_) => return Err(AppError::fail("上传文件异常")),
+            Err(e) => return Err(multipart_error(e)),
         };
-        let filename = field
-            .content_disposition()
-            .get_filename()
-            .map(|s| s.to_string())
-            .unwrap_or_else(|| "file".to_string());
+        let filename = match field.content_disposition().get_filename() {
+            Some(name) => name.to_string(),
+            None => {
+                let name = field.name().to_string();
+                let value = read_text_field(&mut field).await?;
+                // 只影响其后的文件,客户端需把storageType放在文件字段之前
+                if name == "storageType" {
+                    storage_type = check_storage_override(db.get_ref(), &auth, &value).await?;
+                }
+                form.insert(name, value);
+                continue;
+            }
+        };
+        if filename.trim().is_empty() {
+            drain_field(&mut field).await?;
+            continue;
+        }
+        if files_allowed.is_some_and(|allowed| models.len() as u64 >= allowed) {
+            return Err(AppError::fail("上传过于频繁"));
+        }
 
         let public_id = generate_public_id();
         let suffix = Path::new(&filename)
@@ -72,72 +231,47 @@ async fn upload(
             .and_then(|s| s.to_str())
             .unwrap_or("")
             .to_string();
+        // 先读出文件头校验类型,通过后再和剩余内容一起交给存储;
+        // fuse 防止小文件读完后再次 poll 已结束的 field
+        let mut field = field.fuse();
+        let head = read_head(&mut field).await?;
+        check_upload_type(&allowed_suffixes, &suffix, &head)?;
+        let mut field = futures_util::stream::iter([Ok(Bytes::from(head))]).chain(&mut field);
 
-        let parent_dir = Utc::now().format("%Y%m%d").to_string();
-        let file_name = if suffix.is_empty() {
-            public_id.clone()
-        } else {
-            format!("{}.{}", public_id, suffix)
-        };
-        let target_path = PathBuf::from(config.upload_storage_path())
-            .join(parent_dir)
-            .join(file_name);
-
-        if let Some(parent) = target_path.parent() {
-            fs::create_dir_all(parent).map_err(|_| AppError::fail("上传文件异常"))?;
-        }
-
-        let mut f = fs::File::create(&target_path).map_err(|_| AppError::fail("上传文件异常"))?;
-        let mut hasher = Md5::new();
-        let mut size: u64 = 0;
-
-        loop {
-            let chunk = field.next().await;
-            let chunk = match chunk {
-                Some(chunk) => chunk,
-                None => break,
-            };
-            let data = match chunk {
-                Ok(data) => data,
-                Err(_) => return Err(AppError::fail("上传文件异常")),
-            };
-            size += data.len() as u64;
-            hasher.update(&data);
-            f.write_all(&data).map_err(|_| AppError::fail("上传文件异常"))?;
-        }
-
-        let file_hash = format!("{:x}", hasher.finalize());
-        let file_type = detect_file_type(&target_path, &suffix);
-
-        let (url, storage, suffix_from_cfg) = match storage_type.as_str() {
-            "LOCAL" => (format!("/api/resource/{}", public_id), "LOCAL".to_string(), suffix.clone()),
+        let (stored, storage) = match storage_type.as_str() {
             "QINIU" => {
                 let qiniu_param = sys_config_store::get_string(db.get_ref(), "QINIU_PARAM")
                     .await
                     .map_err(|_| AppError::system_exception())?
                     .unwrap_or_default();
                 if qiniu_param.trim().is_empty() || qiniu_param.trim() == "{}" {
-                    let _ = fs::remove_file(&target_path);
                     return Err(AppError::fail("七牛云相关参数没有设置"));
                 }
-                let _ = fs::remove_file(&target_path);
-                return Err(AppError::fail("上传资源失败"));
+                let qiniu_param = QiniuParam::parse(&qiniu_param)?;
+                let stored =
+                    upload_qiniu(&qiniu_param, &mut field, &public_id, &suffix, max_bytes).await?;
+                (stored, "QINIU".to_string())
             }
             "AWSS3" => {
                 let s3_param = sys_config_store::get_string(db.get_ref(), "AWSS3_PARAM")
                     .await
                     .map_err(|_| AppError::system_exception())?
                     .unwrap_or_default();
-                let (url, suffix_cfg) = match upload_awss3(&s3_param, &target_path, &public_id).await {
-                    Ok(result) => result,
-                    Err(err) => {
-                        let _ = fs::remove_file(&target_path);
-                        return Err(err);
-                    }
-                };
-                (url, "AWSS3".to_string(), suffix_cfg)
+                let s3_param = S3Param::parse(&s3_param)?;
+                let stored =
+                    upload_awss3(&s3_param, &mut field, &public_id, &suffix, max_bytes).await?;
+                (stored, "AWSS3".to_string())
+            }
+            _ => {
+                let stored = save_local(&config, &mut field, &public_id, &suffix, max_bytes).await?;
+                (stored, "LOCAL".to_string())
             }
-            _ => (format!("/api/resource/{}", public_id), "LOCAL".to_string(), suffix.clone()),
+        };
+
+        let stored = if dedup {
+            dedup_stored(db.get_ref(), auth.user_id, &storage, &public_id, stored).await?
+        } else {
+            stored
         };
 
         let now = Utc::now();
@@ -145,43 +279,643 @@ async fn upload(
             public_id: Set(public_id.clone()),
             memo_id: Set(0),
             user_id: Set(auth.user_id),
-            file_type: Set(file_type.clone()),
+            file_type: Set(stored.file_type.clone()),
             file_name: Set(filename.clone()),
-            file_hash: Set(file_hash),
-            size: Set(size as i64),
-            internal_path: Set(Some(target_path.to_string_lossy().to_string())),
-            external_link: Set(Some(url.clone())),
+            file_hash: Set(stored.file_hash),
+            size: Set(stored.size as i64),
+            internal_path: Set(stored.internal_path),
+            external_link: Set(Some(stored.url.clone())),
             storage_type: Set(Some(storage.clone())),
             created: Set(Some(now)),
             updated: Set(Some(now)),
-            suffix: Set(Some(suffix_from_cfg.clone())),
+            suffix: Set(Some(stored.suffix.clone())),
+            alt: Set(None),
         };
+        models.push(resource_model);
 
-        resource_model
+        responses.push(UploadResourceResponse {
+            public_id,
+            url: stored.url,
+            suffix: stored.suffix,
+            storage_type: storage,
+            file_type: stored.file_type,
+            file_name: filename,
+        });
+    }
+
+    if models.is_empty() {
+        return Err(AppError::param_error("未找到上传文件"));
+    }
+
+    // Form fields may arrive after the files, so rows are only written once the body is consumed.
+    let alt = form
+        .get("alt")
+        .map(|a| a.trim().to_string())
+        .filter(|a| !a.is_empty());
+    if alt.as_ref().is_some_and(|a| a.chars().count() > RESOURCE_ALT_MAX_CHARS) {
+        return Err(AppError::param_error("alt"));
+    }
+    for mut model in models {
+        model.alt = Set(alt.clone());
+        model
             .insert(db.get_ref())
             .await
             .map_err(|_| AppError::system_exception())?;
+    }
 
-        if storage != "LOCAL" {
-            let _ = fs::remove_file(&target_path);
+    Ok(HttpResponse::Ok().json(ResponseDto::success(Some(responses))))
+}
+
+/// Deletes one of the caller's uploads (any upload for admins) and its stored file or object.
+/// Already removed resources count as success, and a failed file or object deletion is only
+/// logged since the row is gone either way.
+async fn remove(
+    db: web::Data<DatabaseConnection>,
+    auth: AuthUser,
+    query: web::Query<RemoveResourceQuery>,
+) -> Result<HttpResponse, AppError> {
+    let exist = resource::Entity::find_by_id(query.public_id.clone())
+        .one(db.get_ref())
+        .await
+        .map_err(|_| AppError::system_exception())?;
+    let Some(exist) = exist else {
+        return Ok(HttpResponse::Ok().json(ResponseDto::<()>::success(None)));
+    };
+    if exist.user_id != auth.user_id && auth.role.as_deref() != Some("ADMIN") {
+        return Err(AppError::fail("不能操作其他人的资源"));
+    }
+
+    resource::Entity::delete_by_id(exist.public_id.clone())
+        .exec(db.get_ref())
+        .await
+        .map_err(|_| AppError::system_exception())?;
+    delete_stored_file(db.get_ref(), &exist).await;
+    Ok(HttpResponse::Ok().json(ResponseDto::<()>::success(None)))
+}
+
+/// Removes what a deleted resource row pointed at, unless another row still shares the same
+/// local file or remote link.
+pub(crate) async fn delete_stored_file(db: &DatabaseConnection, r: &resource::Model) {
+    let storage_type = r.storage_type.as_deref().unwrap_or("LOCAL");
+    let shared = if storage_type == "LOCAL" {
+        resource::Column::InternalPath.eq(r.internal_path.clone())
+    } else {
+        resource::Column::ExternalLink.eq(r.external_link.clone())
+    };
+    match resource::Entity::find().filter(shared).count(db).await {
+        Ok(0) => {}
+        Ok(_) => return,
+        Err(e) => {
+            warn!("count references of resource {} failed: {}", r.public_id, e);
+            return;
+        }
+    }
+    remove_backing(
+        db,
+        storage_type,
+        &r.public_id,
+        r.internal_path.as_deref(),
+        r.external_link.as_deref().unwrap_or_default(),
+    )
+    .await;
+}
+
+/// Deletes a local file (with its thumbnail) or remote object without checking whether any row
+/// still uses it.
+async fn remove_backing(
+    db: &DatabaseConnection,
+    storage_type: &str,
+    public_id: &str,
+    internal_path: Option<&str>,
+    link: &str,
+) {
+    let result = match storage_type {
+        "LOCAL" => {
+            let Some(path) = internal_path.filter(|p| !p.is_empty()) else {
+                return;
+            };
+            let _ = fs::remove_file(thumbnail_path(Path::new(&path)));
+            match fs::remove_file(path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.to_string()),
+                _ => Ok(()),
+            }
         }
+        "AWSS3" => match load_param(db, "AWSS3_PARAM").await.and_then(|p| S3Param::parse(&p)) {
+            Ok(param) => {
+                let key = param.key_from_url(link).unwrap_or_else(|| param.object_key(public_id));
+                param
+                    .client()
+                    .delete_object()
+                    .bucket(&param.bucket)
+                    .key(key)
+                    .send()
+                    .await
+                    .map(|_| ())
+                    .map_err(|e| e.to_string())
+            }
+            Err(e) => Err(e.to_string()),
+        },
+        "QINIU" => match load_param(db, "QINIU_PARAM").await.and_then(|p| QiniuParam::parse(&p)) {
+            Ok(param) => {
+                let key = param.key_from_url(link).unwrap_or_else(|| param.object_key(public_id));
+                delete_qiniu(&param, &key).await
+            }
+            Err(e) => Err(e.to_string()),
+        },
+        _ => Ok(()),
+    };
+    if let Err(e) = result {
+        warn!("delete {} file of resource {} failed: {}", storage_type, public_id, e);
+    }
+}
 
-        responses.push(UploadResourceResponse {
-            public_id,
-            url,
-            suffix: suffix_from_cfg,
-            storage_type: storage,
-            file_type,
-            file_name: filename,
+/// `DEDUP_UPLOADS`: when the user already has a resource in the same storage with the same md5
+/// and size, the new row points at that file or object and the copy just written is deleted.
+/// The hash is only known once the whole file has been stored, so the copy is removed afterwards
+/// rather than never written.
+async fn dedup_stored(
+    db: &DatabaseConnection,
+    user_id: i32,
+    storage: &str,
+    public_id: &str,
+    stored: StoredFile,
+) -> Result<StoredFile, AppError> {
+    let existing = resource::Entity::find()
+        .filter(resource::Column::UserId.eq(user_id))
+        .filter(resource::Column::FileHash.eq(stored.file_hash.as_str()))
+        .filter(resource::Column::Size.eq(stored.size as i64))
+        .filter(resource::Column::StorageType.eq(storage))
+        .order_by_asc(resource::Column::Created)
+        .one(db)
+        .await
+        .map_err(|_| AppError::system_exception())?;
+    let Some(existing) = existing else {
+        return Ok(stored);
+    };
+    let reusable = if storage == "LOCAL" {
+        existing.internal_path.as_deref().is_some_and(|p| Path::new(p).is_file())
+    } else {
+        existing.external_link.as_deref().is_some_and(|l| !l.is_empty())
+    };
+    if !reusable {
+        return Ok(stored);
+    }
+
+    remove_backing(db, storage, public_id, stored.internal_path.as_deref(), &stored.url).await;
+    Ok(StoredFile {
+        // 本地文件的url是按public_id访问的接口地址,不随文件变化
+        url: if storage == "LOCAL" { stored.url } else { existing.external_link.unwrap_or_default() },
+        suffix: existing.suffix.unwrap_or(stored.suffix),
+        internal_path: existing.internal_path,
+        ..stored
+    })
+}
+
+async fn load_param(db: &DatabaseConnection, key: &str) -> Result<String, AppError> {
+    Ok(sys_config_store::get_string(db, key)
+        .await
+        .map_err(|_| AppError::system_exception())?
+        .unwrap_or_default())
+}
+
+/// Per storage type: how many resources, their summed `size` and the oldest/newest upload.
+async fn storage_report(
+    db: web::Data<DatabaseConnection>,
+    auth: AuthUser,
+) -> Result<HttpResponse, AppError> {
+    if auth.role.as_deref() != Some("ADMIN") {
+        return Err(AppError::need_login());
+    }
+    let stmt = Statement::from_string(
+        db.get_database_backend(),
+        "select coalesce(storage_type, 'LOCAL') as storage_type, count(1) as cnt, coalesce(sum(size), 0) as total_size, \
+         min(created) as oldest, max(created) as newest from t_resource group by coalesce(storage_type, 'LOCAL') \
+         order by storage_type",
+    );
+    let rows = db
+        .query_all(stmt)
+        .await
+        .map_err(|_| AppError::system_exception())?;
+    let items = rows
+        .into_iter()
+        .map(|row| StorageReportItem {
+            storage_type: row.try_get("", "storage_type").unwrap_or_default(),
+            count: row.try_get("", "cnt").unwrap_or(0),
+            total_size: row.try_get("", "total_size").unwrap_or(0),
+            oldest: row.try_get("", "oldest").ok(),
+            newest: row.try_get("", "newest").ok(),
+        })
+        .collect::<Vec<_>>();
+    Ok(HttpResponse::Ok().json(ResponseDto::success(Some(items))))
+}
+
+/// Moves one batch of LOCAL resources to the storage configured in `STORAGE_TYPE` (only AWSS3
+/// can be uploaded to here), oldest first. Each row is repointed once its object is stored and
+/// the local file is removed when no LOCAL row uses it any more; call repeatedly until
+/// `remaining` is 0. `dryRun` only lists the batch.
+async fn migrate(
+    db: web::Data<DatabaseConnection>,
+    auth: AuthUser,
+    payload: Option<Json<MigrateRequest>>,
+) -> Result<HttpResponse, AppError> {
+    if auth.role.as_deref() != Some("ADMIN") {
+        return Err(AppError::need_login());
+    }
+    let payload = payload.map(|p| p.into_inner()).unwrap_or_default();
+    let batch_size = payload
+        .batch_size
+        .unwrap_or(MIGRATE_DEFAULT_BATCH)
+        .clamp(1, MIGRATE_MAX_BATCH);
+
+    let target = sys_config_store::get_string(db.get_ref(), "STORAGE_TYPE")
+        .await
+        .map_err(|_| AppError::system_exception())?
+        .unwrap_or_default();
+    if target != "AWSS3" {
+        return Err(AppError::fail("STORAGE_TYPE需要设置为AWSS3才能迁移"));
+    }
+    let s3_param = sys_config_store::get_string(db.get_ref(), "AWSS3_PARAM")
+        .await
+        .map_err(|_| AppError::system_exception())?
+        .unwrap_or_default();
+    let s3_param = S3Param::parse(&s3_param)?;
+
+    let local = || {
+        resource::Entity::find()
+            .filter(
+                resource::Column::StorageType
+                    .eq("LOCAL")
+                    .or(resource::Column::StorageType.is_null()),
+            )
+            .filter(resource::Column::InternalPath.is_not_null())
+    };
+    let batch = local()
+        .order_by_asc(resource::Column::Created)
+        .limit(batch_size)
+        .all(db.get_ref())
+        .await
+        .map_err(|_| AppError::system_exception())?;
+
+    let mut migrated = Vec::new();
+    let mut failed = Vec::new();
+    let mut bytes = 0;
+    if payload.dry_run {
+        for r in batch {
+            bytes += r.size;
+            migrated.push(r.public_id);
+        }
+    } else {
+        let client = s3_param.client();
+        for r in batch {
+            let public_id = r.public_id.clone();
+            let size = r.size;
+            match migrate_one(db.get_ref(), &client, &s3_param, r).await {
+                Ok(()) => {
+                    bytes += size;
+                    migrated.push(public_id);
+                }
+                Err(err) => {
+                    warn!("migrate resource {} failed: {}", public_id, err);
+                    failed.push(public_id);
+                }
+            }
+        }
+        info!("migrated {} resources to {}, {} failed", migrated.len(), target, failed.len());
+    }
+
+    let remaining = local()
+        .count(db.get_ref())
+        .await
+        .map_err(|_| AppError::system_exception())?;
+    Ok(HttpResponse::Ok().json(ResponseDto::success(Some(MigrateResponse {
+        dry_run: payload.dry_run,
+        target,
+        migrated,
+        failed,
+        bytes,
+        remaining,
+    }))))
+}
+
+async fn migrate_one(
+    db: &DatabaseConnection,
+    client: &S3Client,
+    param: &S3Param,
+    r: resource::Model,
+) -> Result<(), AppError> {
+    let path = r.internal_path.clone().unwrap_or_default();
+    let body = ByteStream::from_path(&path)
+        .await
+        .map_err(|_| AppError::fail("获取resource异常"))?;
+    let key = param.object_key(&r.public_id);
+    client
+        .put_object()
+        .bucket(&param.bucket)
+        .key(&key)
+        .acl(ObjectCannedAcl::PublicRead)
+        .content_type(r.file_type.clone())
+        .body(body)
+        .send()
+        .await
+        .map_err(|_| AppError::fail("上传资源失败"))?;
+
+    let active = resource::ActiveModel {
+        public_id: Set(r.public_id.clone()),
+        storage_type: Set(Some("AWSS3".to_string())),
+        external_link: Set(Some(param.object_url(&key))),
+        internal_path: Set(None),
+        suffix: Set(Some(param.suffix.clone())),
+        updated: Set(Some(Utc::now())),
+        ..Default::default()
+    };
+    active
+        .update(db)
+        .await
+        .map_err(|_| AppError::system_exception())?;
+
+    // 同一个文件可能被多条resource引用,最后一条迁走后再删
+    let refs = resource::Entity::find()
+        .filter(resource::Column::InternalPath.eq(path.as_str()))
+        .count(db)
+        .await
+        .map_err(|_| AppError::system_exception())?;
+    if refs == 0 {
+        if let Err(e) = fs::remove_file(&path)
+            && e.kind() != std::io::ErrorKind::NotFound
+        {
+            warn!("remove migrated file {} failed: {}", path, e);
+        }
+        let _ = fs::remove_file(thumbnail_path(Path::new(&path)));
+    }
+    Ok(())
+}
+
+/// Enforces `UPLOAD_BYTES_PER_HOUR` and `UPLOAD_FILES_PER_MINUTE` before anything is streamed,
+/// returning how many more files this request may store (`None` = unlimited). Bytes are only
+/// checked up front, so a single request can overshoot the hourly budget once.
+async fn check_upload_rate(
+    db: &DatabaseConnection,
+    config: &AppConfig,
+    auth: &AuthUser,
+) -> Result<Option<u64>, AppError> {
+    if auth.role.as_deref() == Some("ADMIN") {
+        return Ok(None);
+    }
+    let now = Utc::now();
+    if config.upload_bytes_per_hour > 0 {
+        let bytes: Option<i64> = resource::Entity::find()
+            .select_only()
+            .column_as(resource::Column::Size.sum(), "bytes")
+            .filter(resource::Column::UserId.eq(auth.user_id))
+            .filter(resource::Column::Created.gte(now - Duration::hours(1)))
+            .into_tuple()
+            .one(db)
+            .await
+            .map_err(|_| AppError::system_exception())?
+            .flatten();
+        if bytes.unwrap_or(0) >= config.upload_bytes_per_hour {
+            return Err(AppError::fail("上传过于频繁"));
+        }
+    }
+    if config.upload_files_per_minute == 0 {
+        return Ok(None);
+    }
+    let files = resource::Entity::find()
+        .filter(resource::Column::UserId.eq(auth.user_id))
+        .filter(resource::Column::Created.gte(now - Duration::minutes(1)))
+        .count(db)
+        .await
+        .map_err(|_| AppError::system_exception())?;
+    if files >= config.upload_files_per_minute {
+        return Err(AppError::fail("上传过于频繁"));
+    }
+    Ok(Some(config.upload_files_per_minute - files))
+}
+
+const STORAGE_TYPES: [&str; 3] = ["LOCAL", "AWSS3", "QINIU"];
+
+/// Validates a per-request `storageType`, which only admins may send unless
+/// `ALLOW_STORAGE_OVERRIDE` is on. The chosen backend is recorded on each resource row.
+async fn check_storage_override(db: &DatabaseConnection, auth: &AuthUser, value: &str) -> Result<String, AppError> {
+    let value = value.trim().to_ascii_uppercase();
+    if !STORAGE_TYPES.contains(&value.as_str()) {
+        return Err(AppError::param_error("storageType"));
+    }
+    if auth.role.as_deref() != Some("ADMIN") {
+        let allowed = sys_config_store::get_boolean(db, "ALLOW_STORAGE_OVERRIDE")
+            .await
+            .map_err(|_| AppError::system_exception())?;
+        if !allowed {
+            return Err(AppError::fail("不允许指定存储方式"));
+        }
+    }
+    Ok(value)
+}
+
+const FORM_FIELD_MAX_BYTES: usize = 4096;
+
+async fn read_text_field(field: &mut Field) -> Result<String, AppError> {
+    let mut buf = Vec::new();
+    while let Some(chunk) = field.next().await {
+        let data = chunk.map_err(multipart_error)?;
+        if buf.len() + data.len() > FORM_FIELD_MAX_BYTES {
+            return Err(AppError::param_error("表单字段过长"));
+        }
+        buf.extend_from_slice(&data);
+    }
+    String::from_utf8(buf).map_err(|_| AppError::param_error("表单字段编码错误"))
+}
+
+/// Reads chunks until at least [`SNIFF_LEN`] bytes (or the whole field) are buffered.
+async fn read_head(
+    field: &mut (impl Stream<Item = Result<Bytes, MultipartError>> + Unpin),
+) -> Result<Vec<u8>, AppError> {
+    let mut head = Vec::new();
+    while head.len() < SNIFF_LEN
+        && let Some(chunk) = field.next().await
+    {
+        head.extend_from_slice(&chunk.map_err(multipart_error)?);
+    }
+    Ok(head)
+}
+
+/// `ALLOWED_UPLOAD_SUFFIXES`, lowercased with `jpeg` folded into `jpg`; empty allows everything.
+async fn load_allowed_suffixes(db: &DatabaseConnection) -> Result<Vec<String>, AppError> {
+    Ok(sys_config_store::get_string(db, "ALLOWED_UPLOAD_SUFFIXES")
+        .await
+        .map_err(|_| AppError::system_exception())?
+        .unwrap_or_default()
+        .split(',')
+        .map(|s| normalize_suffix(s.trim().trim_start_matches('.')))
+        .filter(|s| !s.is_empty())
+        .collect())
+}
+
+fn normalize_suffix(suffix: &str) -> String {
+    match suffix.to_ascii_lowercase().as_str() {
+        "jpeg" => "jpg".to_string(),
+        other => other.to_string(),
+    }
+}
+
+/// Both the file name's extension and the type sniffed from the content must be allowed, so a
+/// page renamed to `.png` or `.txt` is still rejected as html. Content `infer` cannot identify
+/// is judged by its extension alone.
+fn check_upload_type(allowed: &[String], suffix: &str, head: &[u8]) -> Result<(), AppError> {
+    if allowed.is_empty() {
+        return Ok(());
+    }
+    let sniffed = sniff_suffix(head);
+    let permitted = |s: &str| allowed.iter().any(|a| *a == normalize_suffix(s));
+    if !permitted(suffix) || sniffed.is_some_and(|s| !permitted(s)) {
+        return Err(AppError::fail("不支持上传该类型的文件"));
+    }
+    Ok(())
+}
+
+/// Extension matching the content, with svg recognised even without an xml prolog since
+/// `infer` only reports those as xml or not at all.
+fn sniff_suffix(head: &[u8]) -> Option<&'static str> {
+    let text = String::from_utf8_lossy(head).to_ascii_lowercase();
+    if text.contains("<svg") {
+        return Some("svg");
+    }
+    infer::get(head).map(|kind| kind.extension())
+}
+
+async fn drain_field(field: &mut Field) -> Result<(), AppError> {
+    while let Some(chunk) = field.next().await {
+        chunk.map_err(multipart_error)?;
+    }
+    Ok(())
+}
+
+/// Payload overflow means the request body itself was too large; anything else is a
+/// malformed multipart stream.
+fn multipart_error(err: MultipartError) -> AppError {
+    match err {
+        MultipartError::Payload(PayloadError::Overflow) => AppError::file_size_limit("上传文件过大"),
+        _ => AppError::fail("上传文件异常"),
+    }
+}
+
+fn io_error(err: std::io::Error) -> AppError {
+    error!("upload io failed: {}", err);
+    AppError::system_exception()
+}
+
+/// Per-file cap: the smaller of the `MAX_UPLOAD_FILE_BYTES` env setting and the `MAX_UPLOAD_SIZE`
+/// sys config, ignoring either when it is 0 (unlimited).
+async fn max_upload_bytes(db: &DatabaseConnection, config: &AppConfig) -> Result<u64, AppError> {
+    let configured = sys_config_store::get_i64(db, "MAX_UPLOAD_SIZE")
+        .await
+        .map_err(|_| AppError::system_exception())?
+        .unwrap_or(0)
+        .max(0) as u64;
+    Ok(match (configured, config.max_upload_file_bytes) {
+        (0, env) => env,
+        (configured, 0) => configured,
+        (configured, env) => configured.min(env),
+    })
+}
+
+fn check_file_size(size: u64, max_bytes: u64) -> Result<(), AppError> {
+    if max_bytes > 0 && size > max_bytes {
+        return Err(AppError::file_size_limit(format!("单个文件不能超过{}字节", max_bytes)));
+    }
+    Ok(())
+}
+
+/// Stops reading at the first chunk past `max_bytes` and removes the partial file.
+async fn save_local(
+    config: &AppConfig,
+    field: &mut (impl Stream<Item = Result<Bytes, MultipartError>> + Unpin),
+    public_id: &str,
+    suffix: &str,
+    max_bytes: u64,
+) -> Result<StoredFile, AppError> {
+    let parent_dir = Utc::now().format("%Y%m%d").to_string();
+    let file_name = if suffix.is_empty() {
+        public_id.to_string()
+    } else {
+        format!("{}.{}", public_id, suffix)
+    };
+    let target_path = PathBuf::from(config.upload_storage_path())
+        .join(parent_dir)
+        .join(file_name);
+
+    if let Some(parent) = target_path.parent() {
+        fs::create_dir_all(parent).map_err(io_error)?;
+    }
+
+    let mut f = fs::File::create(&target_path).map_err(io_error)?;
+    let mut hasher = Md5::new();
+    let mut head: Vec<u8> = Vec::new();
+    let mut size: u64 = 0;
+
+    while let Some(chunk) = field.next().await {
+        let written = chunk.map_err(multipart_error).and_then(|data| {
+            size += data.len() as u64;
+            check_file_size(size, max_bytes)?;
+            hasher.update(&data);
+            fill_head(&mut head, &data);
+            f.write_all(&data).map_err(io_error)
         });
+        if let Err(err) = written {
+            drop(f);
+            let _ = fs::remove_file(&target_path);
+            return Err(err);
+        }
     }
 
-    Ok(HttpResponse::Ok().json(ResponseDto::success(Some(responses))))
+    Ok(StoredFile {
+        url: format!("/api/resource/{}", public_id),
+        suffix: suffix.to_string(),
+        size,
+        file_hash: format!("{:x}", hasher.finalize()),
+        file_type: detect_file_type(&head, suffix),
+        internal_path: Some(target_path.to_string_lossy().to_string()),
+    })
 }
 
+/// Browsers may reuse a resource for this long before revalidating it against its `ETag`.
+const RESOURCE_CACHE_CONTROL: &str = "public, max-age=604800";
+/// Image types `?thumbnail=true` resizes; anything else (gif included, to keep animation) is
+/// served as is.
+const THUMBNAIL_TYPES: [&str; 3] = ["image/jpeg", "image/png", "image/webp"];
+
+/// Whether `?thumbnail=true` produces a resized image for this type.
+pub(crate) fn has_thumbnail(file_type: &str) -> bool {
+    THUMBNAIL_TYPES.contains(&file_type)
+}
+
+/// Types a browser would run scripts in when opened from our origin (SVG, HTML, XML, JS);
+/// they are only ever offered as downloads.
+fn is_active_content(file_type: &str) -> bool {
+    let essence = file_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+    matches!(essence.as_str(), "text/html" | "text/xml" | "application/xml")
+        || essence.ends_with("+xml")
+        || essence.contains("javascript")
+}
+
+#[derive(Deserialize)]
+struct GetResourceQuery {
+    thumbnail: Option<bool>,
+}
+
+/// Local files are streamed with `NamedFile`, which answers `Range` requests with 206 and
+/// `Content-Range` (first range only, open-ended `bytes=1000-` included) and 416 for ranges past
+/// the end, so video players can seek; `HEAD` lets them probe the size first. The `ETag` is the
+/// stored `file_hash`, so a matching `If-None-Match` gets a 304 without reading the file.
+/// `?thumbnail=true` serves an image scaled down to `THUMBNAIL_SIZE` instead, falling back to
+/// the original when it cannot be made. Every local file is sent with `nosniff` and a sandbox
+/// CSP, and SVG/HTML-like uploads as attachments, so they cannot run script on this origin.
+/// Other storage types redirect to the external link.
 async fn get_resource(
+    req: HttpRequest,
     db: web::Data<DatabaseConnection>,
     path: web::Path<String>,
+    query: web::Query<GetResourceQuery>,
 ) -> Result<HttpResponse, AppError> {
     let public_id = path.into_inner();
     let resource_item = resource::Entity::find_by_id(public_id.clone())
@@ -196,10 +930,60 @@ async fn get_resource(
 
     let storage_type = resource_item.storage_type.as_deref().unwrap_or("LOCAL");
     if storage_type == "LOCAL" {
-        let file_path = resource_item.internal_path.unwrap_or_default();
-        let data = fs::read(&file_path).map_err(|_| AppError::fail("获取resource异常"))?;
-        let file_type = resource_item.file_type;
-        Ok(HttpResponse::Ok().content_type(file_type).body(data))
+        let mut file_path = PathBuf::from(resource_item.internal_path.unwrap_or_default());
+        let mut etag = Some(resource_item.file_hash).filter(|hash| !hash.is_empty());
+        if query.thumbnail.unwrap_or(false)
+            && has_thumbnail(&resource_item.file_type)
+            && let Some(bounds) = load_thumbnail_bounds(db.get_ref()).await?
+            && let Some(thumb) = ensure_thumbnail(file_path.clone(), bounds).await
+        {
+            file_path = thumb;
+            etag = etag.map(|hash| format!("{}-thumb", hash));
+        }
+        let etag = etag.map(EntityTag::new_strong);
+        let cache_headers = |res: &mut HttpResponse| {
+            res.headers_mut()
+                .insert(header::CACHE_CONTROL, HeaderValue::from_static(RESOURCE_CACHE_CONTROL));
+            if let Some(etag) = &etag
+                && let Ok(value) = HeaderValue::from_str(&etag.to_string())
+            {
+                res.headers_mut().insert(header::ETAG, value);
+            }
+        };
+        if let Some(etag) = &etag {
+            let not_modified = match req.get_header::<header::IfNoneMatch>() {
+                Some(header::IfNoneMatch::Any) => true,
+                Some(header::IfNoneMatch::Items(items)) => items.iter().any(|item| item.weak_eq(etag)),
+                None => false,
+            };
+            if not_modified {
+                let mut res = HttpResponse::NotModified().finish();
+                cache_headers(&mut res);
+                return Ok(res);
+            }
+        }
+
+        let file = NamedFile::open_async(&file_path)
+            .await
+            .map_err(|_| AppError::fail("获取resource异常"))?;
+        let content_type = resource_item
+            .file_type
+            .parse::<mime::Mime>()
+            .unwrap_or(mime::APPLICATION_OCTET_STREAM);
+        let active = is_active_content(content_type.essence_str());
+        let mut res = file
+            .set_content_type(content_type)
+            .disable_content_disposition()
+            .use_etag(false)
+            .into_response(&req);
+        cache_headers(&mut res);
+        let headers = res.headers_mut();
+        headers.insert(header::X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+        headers.insert(header::CONTENT_SECURITY_POLICY, HeaderValue::from_static("sandbox"));
+        if active {
+            headers.insert(header::CONTENT_DISPOSITION, HeaderValue::from_static("attachment"));
+        }
+        Ok(res)
     } else {
         let url = resource_item.external_link.unwrap_or_default();
         Ok(HttpResponse::Found()
@@ -208,6 +992,72 @@ async fn get_resource(
     }
 }
 
+/// `THUMBNAIL_SIZE` is `width,height` (one number means a square); `None` when unset or invalid.
+async fn load_thumbnail_bounds(db: &DatabaseConnection) -> Result<Option<(u32, u32)>, AppError> {
+    let size = sys_config_store::get_string(db, "THUMBNAIL_SIZE")
+        .await
+        .map_err(|_| AppError::system_exception())?
+        .unwrap_or_default();
+    let mut dims = size.split(',').map(|d| d.trim().parse::<u32>().ok().filter(|d| *d > 0));
+    let width = dims.next().flatten();
+    let height = dims.next().flatten().or(width);
+    Ok(width.zip(height))
+}
+
+/// Thumbnail of `original` within `bounds`, generated off the async runtime; `None` (serve the
+/// original) when the image already fits or cannot be decoded.
+async fn ensure_thumbnail(original: PathBuf, bounds: (u32, u32)) -> Option<PathBuf> {
+    let display = original.display().to_string();
+    match web::block(move || make_thumbnail(&original, bounds)).await {
+        Ok(Ok(thumb)) => thumb,
+        Ok(Err(e)) => {
+            warn!("generate thumbnail for {} failed: {}", display, e);
+            None
+        }
+        Err(_) => None,
+    }
+}
+
+/// Reuses the cached thumbnail unless it was made for another `THUMBNAIL_SIZE`. The new file is
+/// written under a temporary name and renamed, so concurrent requests never read a partial one.
+fn make_thumbnail(original: &Path, (width, height): (u32, u32)) -> Result<Option<PathBuf>, image::ImageError> {
+    let thumb = thumbnail_path(original);
+    if let Ok((w, h)) = image_dimensions(&thumb)
+        && w <= width
+        && h <= height
+        && (w == width || h == height)
+    {
+        return Ok(Some(thumb));
+    }
+    let reader = image::ImageReader::open(original)?.with_guessed_format()?;
+    let Some(format) = reader.format() else {
+        return Ok(None);
+    };
+    let img = reader.decode()?;
+    if img.width() <= width && img.height() <= height {
+        return Ok(None);
+    }
+    let name = thumb.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+    let tmp = thumb.with_file_name(format!("{}.tmp", name));
+    img.thumbnail(width, height).save_with_format(&tmp, format)?;
+    fs::rename(&tmp, &thumb)?;
+    Ok(Some(thumb))
+}
+
+fn image_dimensions(path: &Path) -> Result<(u32, u32), image::ImageError> {
+    image::ImageReader::open(path)?.with_guessed_format()?.into_dimensions()
+}
+
+/// `<stem>_thumb.<ext>` next to a local resource file.
+pub(crate) fn thumbnail_path(original: &Path) -> PathBuf {
+    let stem = original.file_stem().and_then(|s| s.to_str()).unwrap_or_default();
+    let name = match original.extension().and_then(|s| s.to_str()) {
+        Some(ext) => format!("{}_thumb.{}", stem, ext),
+        None => format!("{}_thumb", stem),
+    };
+    original.with_file_name(name)
+}
+
 fn generate_public_id() -> String {
     let prefix = Utc::now().format("%Y%m%d%H%M%S").to_string();
     let rand: String = (0..20)
@@ -219,69 +1069,660 @@ fn generate_public_id() -> String {
     format!("{}{}", prefix, rand)
 }
 
-fn detect_file_type(path: &Path, suffix: &str) -> String {
-    if let Ok(kind) = infer::get_from_path(path) {
-        if let Some(kind) = kind {
-            return kind.mime_type().to_string();
+fn fill_head(head: &mut Vec<u8>, data: &[u8]) {
+    if head.len() < SNIFF_LEN {
+        let take = (SNIFF_LEN - head.len()).min(data.len());
+        head.extend_from_slice(&data[..take]);
+    }
+}
+
+fn detect_file_type(head: &[u8], suffix: &str) -> String {
+    if let Some(kind) = infer::get(head) {
+        return kind.mime_type().to_string();
+    }
+    mime_from_suffix(suffix).unwrap_or("application/octet-stream").to_string()
+}
+
+/// Extension fallback for files whose magic bytes `infer` doesn't recognise.
+fn mime_from_suffix(suffix: &str) -> Option<&'static str> {
+    let mime = match suffix.to_ascii_lowercase().as_str() {
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "bmp" => "image/bmp",
+        "svg" => "image/svg+xml",
+        "ico" => "image/x-icon",
+        "tif" | "tiff" => "image/tiff",
+        "heic" => "image/heic",
+        "avif" => "image/avif",
+        "mp4" | "m4v" => "video/mp4",
+        "mov" => "video/quicktime",
+        "webm" => "video/webm",
+        "mkv" => "video/x-matroska",
+        "avi" => "video/x-msvideo",
+        "mp3" => "audio/mpeg",
+        "m4a" => "audio/mp4",
+        "aac" => "audio/aac",
+        "wav" => "audio/wav",
+        "ogg" => "audio/ogg",
+        "flac" => "audio/flac",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "txt" => "text/plain",
+        "md" => "text/markdown",
+        "csv" => "text/csv",
+        "json" => "application/json",
+        "doc" => "application/msword",
+        "docx" => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        "xls" => "application/vnd.ms-excel",
+        "xlsx" => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        "ppt" => "application/vnd.ms-powerpoint",
+        "pptx" => "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+        _ => return None,
+    };
+    Some(mime)
+}
+
+const SNIFF_LEN: usize = 8192;
+const S3_PART_SIZE: usize = 5 * 1024 * 1024;
+
+struct S3Param {
+    access_key: String,
+    secret_key: String,
+    bucket: String,
+    domain: String,
+    prefix: String,
+    suffix: String,
+    region: String,
+}
+
+impl S3Param {
+    fn parse(param: &str) -> Result<Self, AppError> {
+        let json: Value = serde_json::from_str(param).map_err(|_| AppError::fail("上传资源失败"))?;
+        let get = |key: &str| json.get(key).and_then(|v| v.as_str()).unwrap_or("").to_string();
+        let param = Self {
+            access_key: get("accessKey"),
+            secret_key: get("secretKey"),
+            bucket: get("bucket"),
+            domain: get("domain"),
+            prefix: get("prefix"),
+            suffix: get("suffix"),
+            region: get("region"),
+        };
+        if param.access_key.is_empty()
+            || param.secret_key.is_empty()
+            || param.bucket.is_empty()
+            || param.region.is_empty()
+        {
+            return Err(AppError::fail("上传资源失败"));
+        }
+        Ok(param)
+    }
+
+    fn client(&self) -> S3Client {
+        let creds = aws_sdk_s3::config::Credentials::new(
+            self.access_key.clone(),
+            self.secret_key.clone(),
+            None,
+            None,
+            "static",
+        );
+        let s3_config = aws_sdk_s3::config::Builder::new()
+            .behavior_version(aws_sdk_s3::config::BehaviorVersion::latest())
+            .region(Region::new(self.region.clone()))
+            .credentials_provider(creds)
+            .build();
+        S3Client::from_conf(s3_config)
+    }
+
+    fn object_key(&self, public_id: &str) -> String {
+        if self.prefix.is_empty() {
+            public_id.to_string()
+        } else {
+            format!("{}/{}", self.prefix, public_id)
         }
     }
-    if !suffix.is_empty() {
-        return format!("image/{}", suffix);
+
+    fn object_url(&self, key: &str) -> String {
+        if !self.domain.is_empty() {
+            format!("{}/{}", self.domain.trim_end_matches('/'), key)
+        } else {
+            format!("https://s3.{}.amazonaws.com/{}/{}", self.region, self.bucket, key)
+        }
+    }
+
+    /// Inverse of [`Self::object_url`]; `None` when the domain has changed since the upload.
+    fn key_from_url(&self, url: &str) -> Option<String> {
+        url.strip_prefix(&self.object_url("")).filter(|k| !k.is_empty()).map(|k| k.to_string())
     }
-    "application/octet-stream".to_string()
 }
 
-async fn upload_awss3(param: &str, file_path: &Path, public_id: &str) -> Result<(String, String), AppError> {
-    let json: Value = serde_json::from_str(param).map_err(|_| AppError::fail("上传资源失败"))?;
-    let access_key = json.get("accessKey").and_then(|v| v.as_str()).unwrap_or("").to_string();
-    let secret_key = json.get("secretKey").and_then(|v| v.as_str()).unwrap_or("").to_string();
-    let bucket = json.get("bucket").and_then(|v| v.as_str()).unwrap_or("").to_string();
-    let domain = json.get("domain").and_then(|v| v.as_str()).unwrap_or("").to_string();
-    let prefix = json.get("prefix").and_then(|v| v.as_str()).unwrap_or("").to_string();
-    let suffix = json.get("suffix").and_then(|v| v.as_str()).unwrap_or("").to_string();
-    let region = json.get("region").and_then(|v| v.as_str()).unwrap_or("").to_string();
+/// Streams the field straight to S3: small files go up with one `PutObject`,
+/// larger ones through a multipart upload so nothing touches local disk.
+async fn upload_awss3(
+    param: &S3Param,
+    field: &mut (impl Stream<Item = Result<Bytes, MultipartError>> + Unpin),
+    public_id: &str,
+    suffix: &str,
+    max_bytes: u64,
+) -> Result<StoredFile, AppError> {
+    let client = param.client();
+    let key = param.object_key(public_id);
+    let mut hasher = Md5::new();
+    let mut head: Vec<u8> = Vec::new();
+    let mut buf: Vec<u8> = Vec::new();
+    let mut size: u64 = 0;
+    let mut upload_id: Option<String> = None;
+    let mut parts: Vec<CompletedPart> = Vec::new();
 
-    if access_key.is_empty() || secret_key.is_empty() || bucket.is_empty() || region.is_empty() {
-        return Err(AppError::fail("上传资源失败"));
+    let result: Result<(), AppError> = async {
+        while let Some(chunk) = field.next().await {
+            let data = chunk.map_err(multipart_error)?;
+            size += data.len() as u64;
+            check_file_size(size, max_bytes)?;
+            hasher.update(&data);
+            fill_head(&mut head, &data);
+            buf.extend_from_slice(&data);
+            if buf.len() >= S3_PART_SIZE {
+                if upload_id.is_none() {
+                    let created = client
+                        .create_multipart_upload()
+                        .bucket(&param.bucket)
+                        .key(&key)
+                        .acl(ObjectCannedAcl::PublicRead)
+                        .content_type(detect_file_type(&head, suffix))
+                        .send()
+                        .await
+                        .map_err(|_| AppError::fail("上传资源失败"))?;
+                    upload_id = created.upload_id().map(|s| s.to_string());
+                }
+                let part_number = parts.len() as i32 + 1;
+                let data = std::mem::take(&mut buf);
+                let part = upload_part(&client, param, &key, upload_id.as_deref(), part_number, data).await?;
+                parts.push(part);
+            }
+        }
+
+        match upload_id.as_deref() {
+            None => {
+                client
+                    .put_object()
+                    .bucket(&param.bucket)
+                    .key(&key)
+                    .acl(ObjectCannedAcl::PublicRead)
+                    .content_type(detect_file_type(&head, suffix))
+                    .body(ByteStream::from(std::mem::take(&mut buf)))
+                    .send()
+                    .await
+                    .map_err(|_| AppError::fail("上传资源失败"))?;
+            }
+            Some(id) => {
+                if !buf.is_empty() {
+                    let part_number = parts.len() as i32 + 1;
+                    let data = std::mem::take(&mut buf);
+                    let part = upload_part(&client, param, &key, Some(id), part_number, data).await?;
+                    parts.push(part);
+                }
+                client
+                    .complete_multipart_upload()
+                    .bucket(&param.bucket)
+                    .key(&key)
+                    .upload_id(id)
+                    .multipart_upload(
+                        CompletedMultipartUpload::builder()
+                            .set_parts(Some(std::mem::take(&mut parts)))
+                            .build(),
+                    )
+                    .send()
+                    .await
+                    .map_err(|_| AppError::fail("上传资源失败"))?;
+            }
+        }
+        Ok(())
     }
+    .await;
 
-    let key = if prefix.is_empty() {
-        public_id.to_string()
-    } else {
-        format!("{}/{}", prefix, public_id)
-    };
+    if let Err(err) = result {
+        if let Some(id) = upload_id {
+            let _ = client
+                .abort_multipart_upload()
+                .bucket(&param.bucket)
+                .key(&key)
+                .upload_id(id)
+                .send()
+                .await;
+        }
+        return Err(err);
+    }
 
-    let creds = aws_sdk_s3::config::Credentials::new(
-        access_key,
-        secret_key,
-        None,
-        None,
-        "static",
-    );
-    let s3_config = aws_sdk_s3::config::Builder::new()
-        .region(Region::new(region.clone()))
-        .credentials_provider(creds)
-        .build();
-    let client = S3Client::from_conf(s3_config);
-    let body = ByteStream::from_path(file_path)
+    Ok(StoredFile {
+        url: param.object_url(&key),
+        suffix: param.suffix.clone(),
+        size,
+        file_hash: format!("{:x}", hasher.finalize()),
+        file_type: detect_file_type(&head, suffix),
+        internal_path: None,
+    })
+}
+
+async fn upload_part(
+    client: &S3Client,
+    param: &S3Param,
+    key: &str,
+    upload_id: Option<&str>,
+    part_number: i32,
+    data: Vec<u8>,
+) -> Result<CompletedPart, AppError> {
+    let uploaded = client
+        .upload_part()
+        .bucket(&param.bucket)
+        .key(key)
+        .set_upload_id(upload_id.map(|s| s.to_string()))
+        .part_number(part_number)
+        .body(ByteStream::from(data))
+        .send()
         .await
         .map_err(|_| AppError::fail("上传资源失败"))?;
+    Ok(CompletedPart::builder()
+        .set_e_tag(uploaded.e_tag().map(|s| s.to_string()))
+        .part_number(part_number)
+        .build())
+}
 
-    client
-        .put_object()
-        .bucket(&bucket)
-        .key(&key)
-        .acl(ObjectCannedAcl::PublicRead)
-        .body(body)
+const QINIU_UPLOAD_URL: &str = "https://upload.qiniup.com";
+const QINIU_RS_URL: &str = "https://rs.qiniuapi.com";
+const QINIU_TOKEN_TTL_SECS: i64 = 3600;
+
+/// `QINIU_PARAM`: `accessKey`, `secretKey`, `bucket` and `domain` (the bucket's public domain)
+/// are required; `prefix`/`suffix` work as for S3. `uploadUrl` picks the region's upload host
+/// for buckets outside East China, and `rsUrl` overrides the management host used for deletes.
+struct QiniuParam {
+    access_key: String,
+    secret_key: String,
+    bucket: String,
+    domain: String,
+    prefix: String,
+    suffix: String,
+    upload_url: String,
+    rs_url: String,
+}
+
+impl QiniuParam {
+    fn parse(param: &str) -> Result<Self, AppError> {
+        let json: Value = serde_json::from_str(param).map_err(|_| AppError::fail("上传资源失败"))?;
+        let get = |key: &str| json.get(key).and_then(|v| v.as_str()).unwrap_or("").trim().to_string();
+        let upload_url = get("uploadUrl");
+        let rs_url = get("rsUrl");
+        let param = Self {
+            access_key: get("accessKey"),
+            secret_key: get("secretKey"),
+            bucket: get("bucket"),
+            domain: get("domain"),
+            prefix: get("prefix"),
+            suffix: get("suffix"),
+            upload_url: if upload_url.is_empty() { QINIU_UPLOAD_URL.to_string() } else { upload_url },
+            rs_url: if rs_url.is_empty() { QINIU_RS_URL.to_string() } else { rs_url },
+        };
+        if param.access_key.is_empty()
+            || param.secret_key.is_empty()
+            || param.bucket.is_empty()
+            || param.domain.is_empty()
+        {
+            return Err(AppError::fail("七牛云相关参数没有设置"));
+        }
+        Ok(param)
+    }
+
+    fn object_key(&self, public_id: &str) -> String {
+        if self.prefix.is_empty() {
+            public_id.to_string()
+        } else {
+            format!("{}/{}", self.prefix, public_id)
+        }
+    }
+
+    /// Upload token for exactly `key`: `accessKey:urlsafe_b64(hmac_sha1(policy)):policy` where
+    /// policy is the url-safe base64 of the put policy JSON.
+    fn upload_token(&self, key: &str) -> String {
+        let policy = serde_json::json!({
+            "scope": format!("{}:{}", self.bucket, key),
+            "deadline": Utc::now().timestamp() + QINIU_TOKEN_TTL_SECS,
+        });
+        let encoded_policy = URL_SAFE.encode(policy.to_string());
+        let mut mac = Hmac::<Sha1>::new_from_slice(self.secret_key.as_bytes()).expect("hmac accepts any key length");
+        hmac::Mac::update(&mut mac, encoded_policy.as_bytes());
+        let sign = URL_SAFE.encode(mac.finalize().into_bytes());
+        format!("{}:{}:{}", self.access_key, sign, encoded_policy)
+    }
+
+    fn object_url(&self, key: &str) -> String {
+        let domain = self.domain.trim_end_matches('/');
+        if domain.starts_with("http://") || domain.starts_with("https://") {
+            format!("{}/{}", domain, key)
+        } else {
+            format!("https://{}/{}", domain, key)
+        }
+    }
+
+    /// Inverse of [`Self::object_url`]; `None` when the domain has changed since the upload.
+    fn key_from_url(&self, url: &str) -> Option<String> {
+        url.strip_prefix(&self.object_url("")).filter(|k| !k.is_empty()).map(|k| k.to_string())
+    }
+
+    /// `QBox` management credential: the access key and the signed request path.
+    fn management_token(&self, path: &str) -> String {
+        let mut mac = Hmac::<Sha1>::new_from_slice(self.secret_key.as_bytes()).expect("hmac accepts any key length");
+        hmac::Mac::update(&mut mac, format!("{}\n", path).as_bytes());
+        format!("QBox {}:{}", self.access_key, URL_SAFE.encode(mac.finalize().into_bytes()))
+    }
+}
+
+/// Deletes `key` through Qiniu's management API; a missing object (612) counts as deleted.
+async fn delete_qiniu(param: &QiniuParam, key: &str) -> Result<(), String> {
+    let path = format!("/delete/{}", URL_SAFE.encode(format!("{}:{}", param.bucket, key)));
+    let resp = reqwest::Client::new()
+        .post(format!("{}{}", param.rs_url.trim_end_matches('/'), path))
+        .header("Authorization", param.management_token(&path))
+        .header("Content-Type", "application/x-www-form-urlencoded")
         .send()
         .await
+        .map_err(|e| e.to_string())?;
+    let status = resp.status();
+    if status.is_success() || status.as_u16() == 612 {
+        return Ok(());
+    }
+    Err(format!("{} {}", status, resp.text().await.unwrap_or_default()))
+}
+
+/// A spooled upload under the system temp dir, removed when dropped (also on early return or a
+/// cancelled request).
+struct SpoolFile(PathBuf);
+
+impl Drop for SpoolFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/// Spools the field to a temp file (bounded by [`max_upload_bytes`]) and streams it to Qiniu's
+/// form upload with a known length, so memory use stays flat whatever the file size.
+async fn upload_qiniu(
+    param: &QiniuParam,
+    field: &mut (impl Stream<Item = Result<Bytes, MultipartError>> + Unpin),
+    public_id: &str,
+    suffix: &str,
+    max_bytes: u64,
+) -> Result<StoredFile, AppError> {
+    let key = param.object_key(public_id);
+    let spool = SpoolFile(std::env::temp_dir().join(format!("mblog-qiniu-{}.part", public_id)));
+    let mut f = fs::File::create(&spool.0).map_err(io_error)?;
+    let mut hasher = Md5::new();
+    let mut head: Vec<u8> = Vec::new();
+    let mut size: u64 = 0;
+    while let Some(chunk) = field.next().await {
+        let data = chunk.map_err(multipart_error)?;
+        size += data.len() as u64;
+        check_file_size(size, max_bytes)?;
+        hasher.update(&data);
+        fill_head(&mut head, &data);
+        f.write_all(&data).map_err(io_error)?;
+    }
+    drop(f);
+    let file_type = detect_file_type(&head, suffix);
+    let body = tokio::fs::File::open(&spool.0).await.map_err(io_error)?;
+
+    let file_name = if suffix.is_empty() { public_id.to_string() } else { format!("{}.{}", public_id, suffix) };
+    let file_part = reqwest::multipart::Part::stream_with_length(body, size)
+        .file_name(file_name)
+        .mime_str(&file_type)
         .map_err(|_| AppError::fail("上传资源失败"))?;
+    let form = reqwest::multipart::Form::new()
+        .text("token", param.upload_token(&key))
+        .text("key", key.clone())
+        .part("file", file_part);
+    let resp = reqwest::Client::new()
+        .post(&param.upload_url)
+        .multipart(form)
+        .send()
+        .await
+        .map_err(|e| {
+            error!("qiniu upload failed: {}", e);
+            AppError::fail("上传资源失败")
+        })?;
+    if !resp.status().is_success() {
+        let status = resp.status();
+        let body = resp.text().await.unwrap_or_default();
+        error!("qiniu upload failed: {} {}", status, body);
+        return Err(AppError::fail("上传资源失败"));
+    }
 
-    let url = if !domain.is_empty() {
-        format!("{}/{}", domain.trim_end_matches('/'), key)
-    } else {
-        format!("https://s3.{}.amazonaws.com/{}/{}", region, bucket, key)
-    };
+    Ok(StoredFile {
+        url: param.object_url(&key),
+        suffix: param.suffix.clone(),
+        size,
+        file_hash: format!("{:x}", hasher.finalize()),
+        file_type,
+        internal_path: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{test as web_test, App};
+
+    use super::*;
+    use crate::test_util::{TestEnv, ADMIN_ID};
+
+    fn resource_app(
+        app_config: AppConfig,
+        db: DatabaseConnection,
+    ) -> App<
+        impl actix_web::dev::ServiceFactory<
+            actix_web::dev::ServiceRequest,
+            Config = (),
+            Response = actix_web::dev::ServiceResponse,
+            Error = actix_web::Error,
+            InitError = (),
+        >,
+    > {
+        App::new()
+            .app_data(web::Data::new(app_config))
+            .app_data(web::Data::new(db))
+            .service(web::scope("/api/resource").configure(config))
+    }
+
+    /// Stores `bytes` as a LOCAL resource of the admin and returns its public id.
+    async fn insert_local(env: &TestEnv, bytes: &[u8], file_type: &str, suffix: &str) -> String {
+        let dir = PathBuf::from(env.config.upload_storage_path());
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(format!("sample.{}", suffix));
+        std::fs::write(&path, bytes).unwrap();
+        let public_id = format!("test{}", suffix);
+        resource::ActiveModel {
+            public_id: Set(public_id.clone()),
+            memo_id: Set(0),
+            user_id: Set(ADMIN_ID),
+            file_type: Set(file_type.to_string()),
+            file_name: Set(format!("sample.{}", suffix)),
+            file_hash: Set("abc123".to_string()),
+            size: Set(bytes.len() as i64),
+            internal_path: Set(Some(path.display().to_string())),
+            storage_type: Set(Some("LOCAL".to_string())),
+            suffix: Set(Some(suffix.to_string())),
+            ..Default::default()
+        }
+        .insert(&env.db)
+        .await
+        .unwrap();
+        public_id
+    }
+
+    #[actix_web::test]
+    async fn range_request_returns_partial_content() {
+        let env = TestEnv::new().await;
+        let bytes: Vec<u8> = (0..4096u32).map(|i| i as u8).collect();
+        let public_id = insert_local(&env, &bytes, "video/mp4", "mp4").await;
+        let app = web_test::init_service(resource_app(env.config.clone(), env.db.clone())).await;
+
+        let req = web_test::TestRequest::get()
+            .uri(&format!("/api/resource/{}", public_id))
+            .insert_header((header::RANGE, "bytes=0-1023"))
+            .to_request();
+        let res = web_test::call_service(&app, req).await;
+        assert_eq!(res.status(), actix_web::http::StatusCode::PARTIAL_CONTENT);
+        assert_eq!(res.headers().get(header::CONTENT_RANGE).unwrap(), "bytes 0-1023/4096");
+        let body = web_test::read_body(res).await;
+        assert_eq!(body.len(), 1024);
+        assert_eq!(&body[..], &bytes[..1024]);
+    }
+
+    /// Files left under the upload directory, searched recursively.
+    fn stored_files(dir: &Path) -> Vec<PathBuf> {
+        let Ok(entries) = fs::read_dir(dir) else {
+            return Vec::new();
+        };
+        entries
+            .flatten()
+            .flat_map(|e| if e.path().is_dir() { stored_files(&e.path()) } else { vec![e.path()] })
+            .collect()
+    }
 
-    Ok((url, suffix))
+    #[actix_web::test]
+    async fn oversized_upload_is_rejected_without_leftovers() {
+        let env = TestEnv::new().await;
+        env.set_config("MAX_UPLOAD_SIZE", "1024").await;
+        let token = env.token(ADMIN_ID).await;
+        let app = web_test::init_service(resource_app(env.config.clone(), env.db.clone())).await;
+
+        let boundary = "mblogtestboundary";
+        let mut body = format!(
+            "--{b}\r\nContent-Disposition: form-data; name=\"files\"; filename=\"big.txt\"\r\n\
+             Content-Type: text/plain\r\n\r\n",
+            b = boundary
+        )
+        .into_bytes();
+        body.extend(std::iter::repeat_n(b'a', 64 * 1024));
+        body.extend(format!("\r\n--{}--\r\n", boundary).into_bytes());
+        let upload = || {
+            web_test::TestRequest::post()
+                .uri("/api/resource/upload")
+                .insert_header(("token", token.clone()))
+                .insert_header((header::CONTENT_TYPE, format!("multipart/form-data; boundary={}", boundary)))
+                .set_payload(body.clone())
+                .to_request()
+        };
+        let upload_dir = PathBuf::from(env.config.upload_storage_path());
+
+        let res: Value = web_test::call_and_read_body_json(&app, upload()).await;
+        assert_eq!(res["code"], 4);
+        assert!(stored_files(&upload_dir).is_empty());
+        assert!(resource::Entity::find().all(&env.db).await.unwrap().is_empty());
+
+        env.set_config("MAX_UPLOAD_SIZE", "0").await;
+        let res: Value = web_test::call_and_read_body_json(&app, upload()).await;
+        assert_eq!(res["code"], 0);
+        assert_eq!(stored_files(&upload_dir).len(), 1);
+    }
+
+    #[actix_web::test]
+    async fn qiniu_upload_streams_spooled_file_and_cleans_up() {
+        use actix_web::HttpServer;
+        use std::sync::Mutex;
+
+        let received = web::Data::new(Mutex::new(0usize));
+        let sink = received.clone();
+        let server = HttpServer::new(move || {
+            App::new().app_data(sink.clone()).default_service(web::to(
+                |body: Bytes, received: web::Data<Mutex<usize>>| async move {
+                    *received.lock().unwrap() = body.len();
+                    HttpResponse::Ok().finish()
+                },
+            ))
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let addr = server.addrs()[0];
+        actix_web::rt::spawn(server.run());
+
+        let param = QiniuParam::parse(
+            &serde_json::json!({
+                "accessKey": "ak", "secretKey": "sk", "bucket": "b", "domain": "https://cdn.example.com",
+                "uploadUrl": format!("http://{}/", addr),
+            })
+            .to_string(),
+        )
+        .unwrap();
+        let chunks = || futures_util::stream::iter((0..16).map(|_| Ok(Bytes::from(vec![b'a'; 4096]))));
+        let spool_path = |public_id: &str| std::env::temp_dir().join(format!("mblog-qiniu-{}.part", public_id));
+
+        let public_id = generate_public_id();
+        let stored = upload_qiniu(&param, &mut chunks(), &public_id, "txt", 0).await.unwrap();
+        assert_eq!(stored.size, 64 * 1024);
+        assert!(*received.lock().unwrap() > 64 * 1024);
+        assert!(!spool_path(&public_id).exists());
+
+        let public_id = generate_public_id();
+        let err = upload_qiniu(&param, &mut chunks(), &public_id, "txt", 1024).await.err().unwrap();
+        assert!(matches!(err, AppError::Biz { code: 4, .. }));
+        assert!(!spool_path(&public_id).exists());
+    }
+
+    #[actix_web::test]
+    async fn thumbnail_query_serves_resized_image() {
+        let env = TestEnv::new().await;
+        env.set_config("THUMBNAIL_SIZE", "100,100").await;
+        let mut png = Vec::new();
+        image::DynamicImage::new_rgb8(400, 200)
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        let public_id = insert_local(&env, &png, "image/png", "png").await;
+        let app = web_test::init_service(resource_app(env.config.clone(), env.db.clone())).await;
+
+        let req = web_test::TestRequest::get()
+            .uri(&format!("/api/resource/{}?thumbnail=true", public_id))
+            .to_request();
+        let body = web_test::call_and_read_body(&app, req).await;
+        let thumb = image::load_from_memory(&body).unwrap();
+        assert_eq!((thumb.width(), thumb.height()), (100, 50));
+    }
+
+    #[actix_web::test]
+    async fn matching_etag_returns_not_modified() {
+        let env = TestEnv::new().await;
+        let public_id = insert_local(&env, b"hello", "text/plain", "txt").await;
+        let app = web_test::init_service(resource_app(env.config.clone(), env.db.clone())).await;
+
+        let req = web_test::TestRequest::get()
+            .uri(&format!("/api/resource/{}", public_id))
+            .insert_header((header::IF_NONE_MATCH, "\"abc123\""))
+            .to_request();
+        let res = web_test::call_service(&app, req).await;
+        assert_eq!(res.status(), actix_web::http::StatusCode::NOT_MODIFIED);
+    }
+
+    #[test]
+    fn mime_from_suffix_maps_known_extensions() {
+        assert_eq!(mime_from_suffix("pdf"), Some("application/pdf"));
+        assert_eq!(mime_from_suffix("mp4"), Some("video/mp4"));
+        assert_eq!(mime_from_suffix("MP4"), Some("video/mp4"));
+        assert_eq!(mime_from_suffix("svg"), Some("image/svg+xml"));
+    }
+
+    #[test]
+    fn mime_from_suffix_rejects_unknown_extensions() {
+        assert_eq!(mime_from_suffix("xyz"), None);
+        assert_eq!(mime_from_suffix(""), None);
+        assert_eq!(detect_file_type(b"plain bytes", "xyz"), "application/octet-stream");
+    }
+
+    #[test]
+    fn active_content_is_detected() {
+        assert!(is_active_content("image/svg+xml"));
+        assert!(is_active_content("text/html; charset=utf-8"));
+        assert!(is_active_content("application/javascript"));
+        assert!(!is_active_content("image/png"));
+        assert!(!is_active_content("application/pdf"));
+    }
 }
@@ -1,56 +1,153 @@
-use actix_web::{web, HttpResponse};
+use actix_web::{http::header, web, HttpRequest, HttpResponse};
+use chrono::{DateTime, SecondsFormat, Utc};
 use rss::{ChannelBuilder, GuidBuilder, ItemBuilder};
-use sea_orm::{ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait, QueryFilter, Statement};
+use sea_orm::{ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder, Statement};
+use serde::Serialize;
 
+use crate::auth;
 use crate::config::AppConfig;
-use crate::entity::user;
+use crate::entity::{resource, user};
 use crate::error::AppError;
+use crate::origin::RequestOrigin;
+use crate::render;
 use crate::sys_config as sys_config_store;
 
 pub fn config(cfg: &mut web::ServiceConfig) {
-    cfg.service(web::resource("").route(web::get().to(get_rss)));
+    cfg.service(web::resource("").route(web::get().to(get_rss)))
+        .service(web::resource("/atom").route(web::get().to(get_atom)))
+        .service(web::resource("/json").route(web::get().to(get_json)));
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum FeedFormat {
+    Rss,
+    Atom,
+    Json,
+}
+
+/// Format-independent feed data; each renderer below turns it into one wire format.
+struct Feed {
+    title: String,
+    link: String,
+    description: String,
+    /// Item content is sanitized HTML (`RSS_DESCRIPTION_FORMAT=html`) rather than text.
+    html: bool,
+    items: Vec<FeedItem>,
+}
+
+struct FeedItem {
+    id: i32,
+    title: String,
+    link: String,
+    content: String,
+    author: String,
+    created: DateTime<Utc>,
+    tags: Vec<String>,
 }
 
 async fn get_rss(
+    req: HttpRequest,
     db: web::Data<DatabaseConnection>,
     _config: web::Data<AppConfig>,
+    origin: RequestOrigin,
+) -> Result<HttpResponse, AppError> {
+    let format = negotiate(&req);
+    let mut res = serve(db.get_ref(), &origin, format).await?;
+    // 同一地址按 Accept 返回不同格式,缓存需要区分
+    res.headers_mut()
+        .insert(header::VARY, header::HeaderValue::from_static("Accept"));
+    Ok(res)
+}
+
+async fn get_atom(
+    db: web::Data<DatabaseConnection>,
+    origin: RequestOrigin,
 ) -> Result<HttpResponse, AppError> {
-    let admin = user::Entity::find()
-        .filter(user::Column::Role.eq("ADMIN"))
-        .one(db.get_ref())
+    serve(db.get_ref(), &origin, FeedFormat::Atom).await
+}
+
+async fn get_json(
+    db: web::Data<DatabaseConnection>,
+    origin: RequestOrigin,
+) -> Result<HttpResponse, AppError> {
+    serve(db.get_ref(), &origin, FeedFormat::Json).await
+}
+
+/// Picks the first feed type named in `Accept`, defaulting to RSS.
+fn negotiate(req: &HttpRequest) -> FeedFormat {
+    let accept = req
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    for media in accept.split(',') {
+        let media = media.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+        match media.as_str() {
+            "application/rss+xml" => return FeedFormat::Rss,
+            "application/atom+xml" => return FeedFormat::Atom,
+            "application/feed+json" | "application/json" => return FeedFormat::Json,
+            _ => {}
+        }
+    }
+    FeedFormat::Rss
+}
+
+async fn serve(db: &DatabaseConnection, origin: &RequestOrigin, format: FeedFormat) -> Result<HttpResponse, AppError> {
+    // RSS_ENABLED=false hides every feed variant before any memo is read; unset means enabled
+    let enabled = sys_config_store::get_string(db, "RSS_ENABLED")
         .await
-        .ok()
-        .flatten();
+        .map_err(|_| AppError::system_exception())?
+        .is_none_or(|v| !v.trim().eq_ignore_ascii_case("false"));
+    if !enabled {
+        return Ok(HttpResponse::NotFound().body("RSS未启用"));
+    }
+    let feed = load_feed(db, origin).await;
+    let (content_type, body) = match format {
+        FeedFormat::Rss => ("application/rss+xml; charset=utf-8", render_rss(feed)),
+        FeedFormat::Atom => ("application/atom+xml; charset=utf-8", render_atom(feed)),
+        FeedFormat::Json => ("application/feed+json; charset=utf-8", render_json(feed)?),
+    };
+    Ok(HttpResponse::Ok().content_type(content_type).body(body))
+}
+
+async fn load_feed(db: &DatabaseConnection, origin: &RequestOrigin) -> Feed {
+    let admin = auth::primary_admin(db).await.ok();
 
-    let title = sys_config_store::get_string(db.get_ref(), "WEBSITE_TITLE")
+    let title = sys_config_store::get_string(db, "WEBSITE_TITLE")
         .await
         .ok()
         .flatten()
         .unwrap_or_default();
-    let domain = sys_config_store::get_string(db.get_ref(), "DOMAIN")
+    let domain = sys_config_store::get_string(db, "DOMAIN")
         .await
         .ok()
         .flatten()
         .unwrap_or_default();
+    let domain = origin.resolve(&domain);
 
-    let items = query_latest_memos(db.get_ref()).await.unwrap_or_default();
+    let format = sys_config_store::get_string(db, "RSS_DESCRIPTION_FORMAT")
+        .await
+        .ok()
+        .flatten()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
 
-    let channel = ChannelBuilder::default()
-        .title(title)
-        .link(domain.clone())
-        .description(admin.and_then(|u| u.bio).unwrap_or_default())
-        .items(items)
-        .build();
+    let items = query_latest_memos(db, &domain, &format).await.unwrap_or_default();
 
-    Ok(HttpResponse::Ok()
-        .content_type("application/rss+xml; charset=utf-8")
-        .body(channel.to_string()))
+    Feed {
+        title,
+        link: domain,
+        description: admin.and_then(|u| u.bio).unwrap_or_default(),
+        html: format == "html",
+        items,
+    }
 }
 
-async fn query_latest_memos(db: &DatabaseConnection) -> Result<Vec<rss::Item>, AppError> {
+async fn query_latest_memos(db: &DatabaseConnection, domain: &str, format: &str) -> Result<Vec<FeedItem>, AppError> {
     let stmt = Statement::from_string(
         db.get_database_backend(),
-        "select id,content,created,updated,user_id,tags from t_memo where `status` = 'NORMAL' and `visibility` = 'PUBLIC' order by priority desc, created desc limit 20",
+        "select id,title,content,content_format,created,updated,user_id,tags from t_memo where `status` = 'NORMAL' and `visibility` = 'PUBLIC' order by priority desc, created desc limit 20",
     );
     let rows = db
         .query_all(stmt)
@@ -60,15 +157,14 @@ async fn query_latest_memos(db: &DatabaseConnection) -> Result<Vec<rss::Item>, A
     let mut items = Vec::new();
     for row in rows {
         let id: i32 = row.try_get::<i32>("", "id").unwrap_or(0);
-        let content: String = row.try_get::<String>("", "content").unwrap_or_default();
+        let mut content: String = row.try_get::<String>("", "content").unwrap_or_default();
         let created: chrono::NaiveDateTime = row
             .try_get::<chrono::NaiveDateTime>("", "created")
             .unwrap_or_else(|_| chrono::Utc::now().naive_utc());
-        let _updated: chrono::NaiveDateTime = row
-            .try_get::<chrono::NaiveDateTime>("", "updated")
-            .unwrap_or(created);
         let user_id: i32 = row.try_get::<i32>("", "user_id").unwrap_or(0);
         let tags: String = row.try_get::<String>("", "tags").unwrap_or_default();
+        let title = row.try_get::<String>("", "title").ok().filter(|t| !t.is_empty());
+        let content_format = row.try_get::<Option<String>>("", "content_format").ok().flatten();
 
         let author = user::Entity::find_by_id(user_id)
             .one(db)
@@ -77,34 +173,179 @@ async fn query_latest_memos(db: &DatabaseConnection) -> Result<Vec<rss::Item>, A
             .and_then(|u| u.display_name)
             .unwrap_or_default();
 
-        let link = sys_config_store::get_string(db, "DOMAIN")
-            .await
-            .map_err(|_| AppError::system_exception())?
-            .unwrap_or_default();
-        let link = format!("{}/memo/{}", link, id);
+        let link = format!("{}/memo/{}", domain, id);
 
-        let guid = GuidBuilder::default().value(link.clone()).permalink(true).build();
+        if content.trim().is_empty() {
+            let first = resource::Entity::find()
+                .filter(resource::Column::MemoId.eq(id))
+                .order_by_asc(resource::Column::Created)
+                .one(db)
+                .await
+                .map_err(|_| AppError::system_exception())?;
+            if let Some(r) = first {
+                content = render::resource_placeholder(&r.file_type, &r.file_name);
+            }
+        }
 
-        let categories = tags
-            .split(',')
-            .filter(|s: &&str| !s.is_empty())
-            .map(|t: &str| rss::CategoryBuilder::default().name(t.to_string()).build())
-            .collect::<Vec<_>>();
+        items.push(FeedItem {
+            id,
+            title: title.unwrap_or_else(|| truncate(&content, 20)),
+            link,
+            content: format_description(&content, format, content_format.as_deref()),
+            author,
+            created: DateTime::<Utc>::from_naive_utc_and_offset(created, Utc),
+            tags: tags
+                .split(',')
+                .filter(|s| !s.is_empty())
+                .map(str::to_string)
+                .collect(),
+        });
+    }
 
-        let mut builder = ItemBuilder::default();
-        builder.title(Some(truncate(&content, 20)));
-        builder.link(Some(link));
-        builder.guid(Some(guid));
-        builder.description(Some(content.clone()));
-        builder.author(Some(author));
-        builder.pub_date(Some(to_rfc2822(created)));
-        builder.categories(categories);
-        let item = builder.build();
+    Ok(items)
+}
 
-        items.push(item);
+/// Applies `RSS_DESCRIPTION_FORMAT`: `plaintext` strips markdown and tag/mention markers,
+/// `html` renders and sanitizes, anything else keeps the raw markdown. The memo's own
+/// `content_format` decides how its content is parsed.
+fn format_description(content: &str, format: &str, content_format: Option<&str>) -> String {
+    match format {
+        "plaintext" => render::strip_markers(&render::to_plain_text_as(content, content_format)),
+        "html" => render::to_safe_html_as(content, content_format),
+        _ => content.to_string(),
     }
+}
 
-    Ok(items)
+fn render_rss(feed: Feed) -> String {
+    let items = feed
+        .items
+        .into_iter()
+        .map(|item| {
+            let guid = GuidBuilder::default().value(item.link.clone()).permalink(true).build();
+            let categories = item
+                .tags
+                .iter()
+                .map(|t| rss::CategoryBuilder::default().name(t.clone()).build())
+                .collect::<Vec<_>>();
+            let mut builder = ItemBuilder::default();
+            builder.title(Some(item.title));
+            builder.link(Some(item.link));
+            builder.guid(Some(guid));
+            builder.description(Some(item.content));
+            builder.author(Some(item.author));
+            builder.pub_date(Some(item.created.to_rfc2822()));
+            builder.categories(categories);
+            builder.build()
+        })
+        .collect::<Vec<_>>();
+
+    ChannelBuilder::default()
+        .title(feed.title)
+        .link(feed.link)
+        .description(feed.description)
+        .items(items)
+        .build()
+        .to_string()
+}
+
+fn render_atom(feed: Feed) -> String {
+    let updated = feed
+        .items
+        .iter()
+        .map(|i| i.created)
+        .max()
+        .unwrap_or_else(Utc::now);
+    let content_type = if feed.html { "html" } else { "text" };
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+    xml.push_str(&format!("<title>{}</title>\n", escape_xml(&feed.title)));
+    xml.push_str(&format!("<subtitle>{}</subtitle>\n", escape_xml(&feed.description)));
+    xml.push_str(&format!("<id>{}/</id>\n", escape_xml(&feed.link)));
+    xml.push_str(&format!("<link href=\"{}\"/>\n", escape_xml(&feed.link)));
+    xml.push_str(&format!("<updated>{}</updated>\n", to_rfc3339(updated)));
+    for item in feed.items {
+        xml.push_str("<entry>\n");
+        xml.push_str(&format!("<title>{}</title>\n", escape_xml(&item.title)));
+        xml.push_str(&format!("<id>{}</id>\n", escape_xml(&item.link)));
+        xml.push_str(&format!("<link href=\"{}\"/>\n", escape_xml(&item.link)));
+        xml.push_str(&format!("<published>{}</published>\n", to_rfc3339(item.created)));
+        xml.push_str(&format!("<updated>{}</updated>\n", to_rfc3339(item.created)));
+        xml.push_str(&format!("<author><name>{}</name></author>\n", escape_xml(&item.author)));
+        for tag in &item.tags {
+            xml.push_str(&format!("<category term=\"{}\"/>\n", escape_xml(tag)));
+        }
+        xml.push_str(&format!(
+            "<content type=\"{}\">{}</content>\n",
+            content_type,
+            escape_xml(&item.content)
+        ));
+        xml.push_str("</entry>\n");
+    }
+    xml.push_str("</feed>\n");
+    xml
+}
+
+fn render_json(feed: Feed) -> Result<String, AppError> {
+    #[derive(Serialize)]
+    struct JsonFeed {
+        version: &'static str,
+        title: String,
+        home_page_url: String,
+        feed_url: String,
+        description: String,
+        items: Vec<JsonFeedItem>,
+    }
+
+    #[derive(Serialize)]
+    struct JsonFeedItem {
+        id: String,
+        url: String,
+        title: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        content_text: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        content_html: Option<String>,
+        date_published: String,
+        authors: Vec<JsonFeedAuthor>,
+        tags: Vec<String>,
+    }
+
+    #[derive(Serialize)]
+    struct JsonFeedAuthor {
+        name: String,
+    }
+
+    let html = feed.html;
+    let json = JsonFeed {
+        version: "https://jsonfeed.org/version/1.1",
+        feed_url: format!("{}/rss/json", feed.link),
+        title: feed.title,
+        home_page_url: feed.link,
+        description: feed.description,
+        items: feed
+            .items
+            .into_iter()
+            .map(|item| JsonFeedItem {
+                id: item.id.to_string(),
+                url: item.link,
+                title: item.title,
+                content_text: (!html).then(|| item.content.clone()),
+                content_html: html.then_some(item.content),
+                date_published: to_rfc3339(item.created),
+                authors: vec![JsonFeedAuthor { name: item.author }],
+                tags: item.tags,
+            })
+            .collect(),
+    };
+    serde_json::to_string(&json).map_err(|_| AppError::system_exception())
+}
+
+fn escape_xml(input: &str) -> String {
+    input
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
 }
 
 fn truncate(input: &str, max: usize) -> String {
@@ -115,6 +356,115 @@ fn truncate(input: &str, max: usize) -> String {
     }
 }
 
-fn to_rfc2822(dt: chrono::NaiveDateTime) -> String {
-    chrono::DateTime::<chrono::Utc>::from_naive_utc_and_offset(dt, chrono::Utc).to_rfc2822()
+fn to_rfc3339(dt: DateTime<Utc>) -> String {
+    dt.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{test as web_test, App};
+
+    use super::*;
+    use crate::test_util::TestEnv;
+
+    fn sample_feed(html: bool) -> Feed {
+        Feed {
+            title: "Jerry's <memos>".to_string(),
+            link: "https://example.com".to_string(),
+            description: "bio".to_string(),
+            html,
+            items: vec![FeedItem {
+                id: 7,
+                title: "hello".to_string(),
+                link: "https://example.com/memo/7".to_string(),
+                content: "a & b".to_string(),
+                author: "jerry".to_string(),
+                created: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+                tags: vec!["rust".to_string()],
+            }],
+        }
+    }
+
+    #[test]
+    fn render_rss_writes_channel_and_items() {
+        let xml = render_rss(sample_feed(false));
+        assert!(xml.contains("<title>Jerry&apos;s &lt;memos&gt;</title>"));
+        assert!(xml.contains("<link>https://example.com/memo/7</link>"));
+        assert!(xml.contains("<guid>https://example.com/memo/7</guid>"));
+        assert!(xml.contains("<description><![CDATA[a & b]]></description>"));
+        assert!(xml.contains("<category>rust</category>"));
+        assert!(xml.contains("<pubDate>Tue, 14 Nov 2023 22:13:20 +0000</pubDate>"));
+    }
+
+    #[test]
+    fn render_atom_escapes_and_types_content() {
+        let xml = render_atom(sample_feed(true));
+        assert!(xml.contains("<title>Jerry&apos;s &lt;memos&gt;</title>"));
+        assert!(xml.contains("<updated>2023-11-14T22:13:20Z</updated>"));
+        assert!(xml.contains("<category term=\"rust\"/>"));
+        assert!(xml.contains("<content type=\"html\">a &amp; b</content>"));
+        assert!(render_atom(sample_feed(false)).contains("<content type=\"text\">"));
+    }
+
+    #[test]
+    fn render_json_picks_content_field_by_format() {
+        let json: serde_json::Value = serde_json::from_str(&render_json(sample_feed(false)).unwrap()).unwrap();
+        assert_eq!(json["version"], "https://jsonfeed.org/version/1.1");
+        assert_eq!(json["feed_url"], "https://example.com/rss/json");
+        assert_eq!(json["items"][0]["id"], "7");
+        assert_eq!(json["items"][0]["content_text"], "a & b");
+        assert!(json["items"][0].get("content_html").is_none());
+        assert_eq!(json["items"][0]["date_published"], "2023-11-14T22:13:20Z");
+
+        let json: serde_json::Value = serde_json::from_str(&render_json(sample_feed(true)).unwrap()).unwrap();
+        assert_eq!(json["items"][0]["content_html"], "a & b");
+        assert!(json["items"][0].get("content_text").is_none());
+    }
+
+    const SAMPLE_MEMO: &str = "**bold** note #rust for @bob <script>alert(1)</script>";
+
+    #[test]
+    fn format_description_keeps_raw_markdown() {
+        assert_eq!(format_description(SAMPLE_MEMO, "raw", None), SAMPLE_MEMO);
+    }
+
+    #[test]
+    fn format_description_plaintext_strips_markdown_and_markers() {
+        assert_eq!(format_description(SAMPLE_MEMO, "plaintext", None), "bold note rust for bob alert(1)");
+    }
+
+    #[test]
+    fn format_description_html_renders_and_sanitizes() {
+        assert_eq!(
+            format_description(SAMPLE_MEMO, "html", None),
+            "<p><strong>bold</strong> note #rust for @bob </p>\n"
+        );
+    }
+
+    #[actix_web::test]
+    async fn negotiated_feed_varies_on_accept() {
+        let env = TestEnv::new().await;
+        let app = web_test::init_service(
+            App::new()
+                .app_data(web::Data::new(env.config.clone()))
+                .app_data(web::Data::new(env.db.clone()))
+                .service(web::scope("/rss").configure(config)),
+        )
+        .await;
+
+        let req = web_test::TestRequest::get()
+            .uri("/rss")
+            .insert_header((header::ACCEPT, "application/atom+xml"))
+            .to_request();
+        let res = web_test::call_service(&app, req).await;
+        assert_eq!(res.headers().get(header::VARY).unwrap(), "Accept");
+        assert_eq!(
+            res.headers().get(header::CONTENT_TYPE).unwrap(),
+            "application/atom+xml; charset=utf-8"
+        );
+
+        let req = web_test::TestRequest::get().uri("/rss/json").to_request();
+        let res = web_test::call_service(&app, req).await;
+        assert!(res.headers().get(header::VARY).is_none());
+    }
 }
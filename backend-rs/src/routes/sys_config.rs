@@ -1,18 +1,29 @@
 use actix_web::{web, HttpResponse};
 use base64::{engine::general_purpose::STANDARD, Engine as _};
+use chrono::Utc;
+use log::{error, info, warn};
 use rand::RngCore;
-use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, Set};
+use sea_orm::{ColumnTrait, ConnectionTrait, DatabaseConnection, DbBackend, EntityTrait, QueryFilter, Set, Statement};
+use std::sync::atomic::{AtomicBool, Ordering};
 use serde::{Deserialize, Serialize};
 
-use crate::auth::AuthUser;
+use crate::auth::{self, AuthUser};
+use crate::config::AppConfig;
 use crate::entity::sys_config;
 use crate::error::AppError;
+use crate::request::Json;
+use crate::routes::cors::CorsOrigins;
 use crate::response::ResponseDto;
 use crate::sys_config as sys_config_store;
+use crate::webhook;
 
 pub fn config(cfg: &mut web::ServiceConfig) {
     cfg.service(web::resource("/save").route(web::post().to(save)))
         .service(web::resource("/get").route(web::get().to(get_all)))
+        .service(web::resource("/previewSquarePush").route(web::post().to(preview_square_push)))
+        .service(web::resource("/rotateWebhookToken").route(web::post().to(rotate_webhook_token)))
+        .service(web::resource("/optimize").route(web::post().to(optimize)))
+        .service(web::resource("/testWebhook").route(web::post().to(test_webhook)))
         .service(web::resource("/").route(web::get().to(get_front_config)));
 }
 
@@ -33,29 +44,271 @@ pub async fn init_defaults(db: &DatabaseConnection) {
         .ok()
         .flatten();
     if token.is_none() || token.as_deref() == Some("") {
-        let mut bytes = [0u8; 32];
-        rand::thread_rng().fill_bytes(&mut bytes);
-        let encoded = STANDARD.encode(bytes);
-        let _ = upsert_config(db, WEB_HOOK_TOKEN, Some(encoded)).await;
+        let _ = upsert_config(db, WEB_HOOK_TOKEN, Some(generate_webhook_token())).await;
     }
 }
 
+fn generate_webhook_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    STANDARD.encode(bytes)
+}
+
+/// Replaces `WEB_HOOK_TOKEN` with a fresh random value and returns it; this is the only time the
+/// new token is shown. The square is re-notified when pushing is enabled.
+async fn rotate_webhook_token(
+    db: web::Data<DatabaseConnection>,
+    config: web::Data<AppConfig>,
+    auth: AuthUser,
+) -> Result<HttpResponse, AppError> {
+    require_admin(&auth)?;
+    let token = generate_webhook_token();
+    upsert_config(db.get_ref(), WEB_HOOK_TOKEN, Some(token.clone())).await?;
+    info!("webhook token rotated by user {}", auth.user_id);
+
+    let push_square = sys_config_store::get_boolean(db.get_ref(), PUSH_OFFICIAL_SQUARE)
+        .await
+        .map_err(|_| AppError::system_exception())?;
+    if push_square && !config.disable_official_square {
+        push_official_square_async(db.get_ref().clone(), config.get_ref().clone());
+    }
+    Ok(HttpResponse::Ok().json(ResponseDto::success(Some(token))))
+}
+
+/// Sends a sample payload to the configured webhook so admins can check URL and token.
+async fn test_webhook(
+    db: web::Data<DatabaseConnection>,
+    auth: AuthUser,
+) -> Result<HttpResponse, AppError> {
+    require_admin(&auth)?;
+    let outcome = webhook::send_test(db.get_ref()).await?;
+    info!("webhook test to {} finished: {:?}", outcome.url, outcome.status);
+    Ok(HttpResponse::Ok().json(ResponseDto::success(Some(outcome))))
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct OptimizeSummary {
+    size_before: i64,
+    size_after: i64,
+    freed_bytes: i64,
+    duration_ms: i64,
+}
+
+static OPTIMIZE_RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Holds [`OPTIMIZE_RUNNING`] until dropped, so the flag is cleared even when the request is
+/// cancelled mid-VACUUM.
+struct OptimizeLock;
+
+impl OptimizeLock {
+    fn acquire() -> Option<Self> {
+        (!OPTIMIZE_RUNNING.swap(true, Ordering::AcqRel)).then_some(OptimizeLock)
+    }
+}
+
+impl Drop for OptimizeLock {
+    fn drop(&mut self) {
+        OPTIMIZE_RUNNING.store(false, Ordering::Release);
+    }
+}
+
+/// Runs `VACUUM` + `ANALYZE` to reclaim free pages after bulk deletes. VACUUM rewrites the whole
+/// file and blocks writers until it finishes, so only one run is allowed at a time.
+async fn optimize(
+    db: web::Data<DatabaseConnection>,
+    auth: AuthUser,
+) -> Result<HttpResponse, AppError> {
+    require_admin(&auth)?;
+    if db.get_database_backend() != DbBackend::Sqlite {
+        return Err(AppError::fail("当前数据库不支持优化"));
+    }
+    let lock = OptimizeLock::acquire().ok_or_else(|| AppError::fail("数据库优化正在进行中"))?;
+    warn!("database optimize started by user {}, writes are blocked until it finishes", auth.user_id);
+    let started = Utc::now();
+    let result = run_optimize(db.get_ref()).await;
+    drop(lock);
+    let (size_before, size_after) = result?;
+
+    let summary = OptimizeSummary {
+        size_before,
+        size_after,
+        freed_bytes: size_before - size_after,
+        duration_ms: (Utc::now() - started).num_milliseconds(),
+    };
+    info!("database optimize freed {} bytes", summary.freed_bytes);
+    Ok(HttpResponse::Ok().json(ResponseDto::success(Some(summary))))
+}
+
+async fn run_optimize(db: &DatabaseConnection) -> Result<(i64, i64), AppError> {
+    let before = sqlite_size(db).await?;
+    for sql in ["VACUUM", "ANALYZE"] {
+        db.execute(Statement::from_string(DbBackend::Sqlite, sql))
+            .await
+            .map_err(|e| {
+                error!("{} failed: {}", sql, e);
+                AppError::system_exception()
+            })?;
+    }
+    Ok((before, sqlite_size(db).await?))
+}
+
+async fn sqlite_size(db: &DatabaseConnection) -> Result<i64, AppError> {
+    let stmt = Statement::from_string(
+        DbBackend::Sqlite,
+        "select page_count * page_size as size from pragma_page_count(), pragma_page_size()",
+    );
+    Ok(db
+        .query_one(stmt)
+        .await
+        .map_err(|_| AppError::system_exception())?
+        .and_then(|row| row.try_get::<i64>("", "size").ok())
+        .unwrap_or(0))
+}
+
 async fn save(
     db: web::Data<DatabaseConnection>,
+    config: web::Data<AppConfig>,
+    cors: web::Data<CorsOrigins>,
     auth: AuthUser,
-    payload: web::Json<SaveSysConfigRequest>,
+    payload: Json<SaveSysConfigRequest>,
 ) -> Result<HttpResponse, AppError> {
     require_admin(&auth)?;
 
     let items = payload.items.clone().ok_or_else(|| AppError::param_error("items must not be null"))?;
 
+    let push_square = items
+        .iter()
+        .any(|item| item.key == PUSH_OFFICIAL_SQUARE && item.value.as_deref() == Some("true"));
+    let cors_changed = items.iter().any(|item| item.key == CORS_DOMAIN_LIST);
+
     for item in items {
         upsert_config(db.get_ref(), &item.key, item.value).await?;
     }
 
+    if cors_changed {
+        cors.reload(db.get_ref()).await;
+    }
+    if push_square && !config.disable_official_square {
+        push_official_square_async(db.get_ref().clone(), config.get_ref().clone());
+    }
+
     Ok(HttpResponse::Ok().json(ResponseDto::<()>::success(None)))
 }
 
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SquarePushPreview {
+    url: String,
+    payload: SquareTokenPayload,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SquareTokenPayload {
+    token: String,
+    author: Option<String>,
+    avatar_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    website: Option<String>,
+}
+
+async fn preview_square_push(
+    db: web::Data<DatabaseConnection>,
+    config: web::Data<AppConfig>,
+    auth: AuthUser,
+) -> Result<HttpResponse, AppError> {
+    require_admin(&auth)?;
+    let url = square_token_url(&config)?;
+    let mut payload = build_square_payload(db.get_ref(), &config).await?;
+    if !payload.token.is_empty() {
+        payload.token = "******".to_string();
+    }
+    let preview = SquarePushPreview {
+        url: url.to_string(),
+        payload,
+    };
+    Ok(HttpResponse::Ok().json(ResponseDto::success(Some(preview))))
+}
+
+/// Builds the square token endpoint, refusing to hand the webhook token to anything but an
+/// allowlisted https host.
+fn square_token_url(config: &AppConfig) -> Result<reqwest::Url, AppError> {
+    if config.disable_official_square {
+        return Err(AppError::fail("官方广场已禁用"));
+    }
+    let raw = format!("{}/api/token", config.official_square_url.trim_end_matches('/'));
+    let url = reqwest::Url::parse(&raw).map_err(|_| AppError::fail("OFFICIAL_SQUARE_URL格式错误"))?;
+    if url.scheme() != "https" {
+        return Err(AppError::fail("OFFICIAL_SQUARE_URL必须使用https"));
+    }
+    let host = url.host_str().unwrap_or_default().to_ascii_lowercase();
+    if !config.official_square_allowed_hosts.is_empty()
+        && !config.official_square_allowed_hosts.contains(&host)
+    {
+        return Err(AppError::fail(format!("官方广场地址 {} 不在允许列表中", host)));
+    }
+    Ok(url)
+}
+
+async fn build_square_payload(
+    db: &DatabaseConnection,
+    config: &AppConfig,
+) -> Result<SquareTokenPayload, AppError> {
+    let token = sys_config_store::get_string(db, WEB_HOOK_TOKEN)
+        .await
+        .map_err(|_| AppError::system_exception())?
+        .unwrap_or_default();
+    let admin = auth::primary_admin(db).await?;
+
+    let website = if config.embed {
+        sys_config_store::get_string(db, DOMAIN)
+            .await
+            .map_err(|_| AppError::system_exception())?
+    } else {
+        sys_config_store::get_cors_domain_list(db)
+            .await
+            .map_err(|_| AppError::system_exception())?
+            .and_then(|list| list.split(',').next().map(|s| s.trim().to_string()))
+    }
+    .filter(|s| !s.is_empty());
+
+    Ok(SquareTokenPayload {
+        token,
+        author: admin.display_name,
+        avatar_url: admin.avatar_url,
+        website,
+    })
+}
+
+/// Registers this instance with the official square in the background; the outcome is
+/// stored under `PUSH_OFFICIAL_SQUARE_RESULT` instead of failing the config save.
+fn push_official_square_async(db: DatabaseConnection, config: AppConfig) {
+    actix_web::rt::spawn(async move {
+        let result = match push_official_square(&db, &config).await {
+            Ok(status) => format!("{} {}", Utc::now().to_rfc3339(), status),
+            Err(err) => {
+                error!("push official square failed: {}", err);
+                format!("{} 失败: {}", Utc::now().to_rfc3339(), err)
+            }
+        };
+        let _ = upsert_config(&db, PUSH_OFFICIAL_SQUARE_RESULT, Some(result)).await;
+    });
+}
+
+async fn push_official_square(db: &DatabaseConnection, config: &AppConfig) -> Result<String, AppError> {
+    let url = square_token_url(config)?;
+    let payload = build_square_payload(db, config).await?;
+    info!("注册token {} (host: {})", url, url.host_str().unwrap_or_default());
+    let resp = reqwest::Client::new()
+        .post(url)
+        .json(&payload)
+        .send()
+        .await
+        .map_err(|e| AppError::fail(format!("连接广场异常: {}", e)))?;
+    Ok(resp.status().to_string())
+}
+
 async fn get_all(
     db: web::Data<DatabaseConnection>,
     auth: AuthUser,
@@ -74,10 +327,14 @@ async fn get_front_config(
 ) -> Result<HttpResponse, AppError> {
     let keys = vec![
         OPEN_REGISTER,
+        REGISTER_MODE,
         WEBSITE_TITLE,
         OPEN_COMMENT,
         OPEN_LIKE,
+        ANONYMOUS_LIKE,
         MEMO_MAX_LENGTH,
+        MEMO_MIN_LENGTH,
+        MEMO_MAX_CONTENT_LENGTH,
         INDEX_WIDTH,
         USER_MODEL,
         CUSTOM_CSS,
@@ -85,6 +342,8 @@ async fn get_front_config(
         THUMBNAIL_SIZE,
         ANONYMOUS_COMMENT,
         COMMENT_APPROVED,
+        MEMO_FIRST_LINE_MODE,
+        MAX_UPLOAD_SIZE,
     ];
 
     let list: Vec<sys_config::Model> = sys_config::Entity::find()
@@ -136,10 +395,14 @@ fn require_admin(auth: &AuthUser) -> Result<(), AppError> {
 }
 
 const OPEN_REGISTER: &str = "OPEN_REGISTER";
+const REGISTER_MODE: &str = "REGISTER_MODE";
 const WEBSITE_TITLE: &str = "WEBSITE_TITLE";
 const OPEN_COMMENT: &str = "OPEN_COMMENT";
 const OPEN_LIKE: &str = "OPEN_LIKE";
+const ANONYMOUS_LIKE: &str = "ANONYMOUS_LIKE";
 const MEMO_MAX_LENGTH: &str = "MEMO_MAX_LENGTH";
+const MEMO_MIN_LENGTH: &str = "MEMO_MIN_LENGTH";
+const MEMO_MAX_CONTENT_LENGTH: &str = "MEMO_MAX_CONTENT_LENGTH";
 const INDEX_WIDTH: &str = "INDEX_WIDTH";
 const USER_MODEL: &str = "USER_MODEL";
 const CUSTOM_CSS: &str = "CUSTOM_CSS";
@@ -147,5 +410,11 @@ const CUSTOM_JAVASCRIPT: &str = "CUSTOM_JAVASCRIPT";
 const THUMBNAIL_SIZE: &str = "THUMBNAIL_SIZE";
 const ANONYMOUS_COMMENT: &str = "ANONYMOUS_COMMENT";
 const COMMENT_APPROVED: &str = "COMMENT_APPROVED";
+const MEMO_FIRST_LINE_MODE: &str = "MEMO_FIRST_LINE_MODE";
+const MAX_UPLOAD_SIZE: &str = "MAX_UPLOAD_SIZE";
 
 const WEB_HOOK_TOKEN: &str = "WEB_HOOK_TOKEN";
+const DOMAIN: &str = "DOMAIN";
+const CORS_DOMAIN_LIST: &str = "CORS_DOMAIN_LIST";
+const PUSH_OFFICIAL_SQUARE: &str = "PUSH_OFFICIAL_SQUARE";
+const PUSH_OFFICIAL_SQUARE_RESULT: &str = "PUSH_OFFICIAL_SQUARE_RESULT";
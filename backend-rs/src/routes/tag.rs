@@ -1,16 +1,21 @@
+use std::collections::HashMap;
+
 use actix_web::{web, HttpResponse};
 use chrono::Utc;
-use sea_orm::{ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder, QuerySelect, Set, Statement, TransactionError, TransactionTrait};
+use sea_orm::{ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait, PaginatorTrait, QueryFilter, QueryOrder, QuerySelect, Set, Statement, TransactionError, TransactionTrait};
 use serde::{Deserialize, Serialize};
 
-use crate::auth::{AuthUser, OptionalAuthUser};
-use crate::entity::{memo, tag, user};
+use crate::auth::{self, AuthUser, OptionalAuthUser};
+use crate::entity::{memo, tag};
 use crate::error::AppError;
+use crate::request::Json;
 use crate::response::ResponseDto;
 
 pub fn config(cfg: &mut web::ServiceConfig) {
     cfg.service(web::resource("/list").route(web::post().to(list)))
         .service(web::resource("/top10").route(web::post().to(top10)))
+        .service(web::resource("/cloud").route(web::post().to(cloud)))
+        .service(web::resource("/byUser").route(web::post().to(by_user)))
         .service(web::resource("/remove").route(web::post().to(remove)))
         .service(web::resource("/save").route(web::post().to(save)));
 }
@@ -23,6 +28,39 @@ struct TagDto {
     count: i32,
 }
 
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TagCloudDto {
+    id: i32,
+    name: String,
+    count: i32,
+    weight: f64,
+}
+
+const TAG_CLOUD_LIMIT: u64 = 200;
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct ListTagRequest {
+    page: Option<u64>,
+    size: Option<u64>,
+    search: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ListTagResponse {
+    items: Vec<TagDto>,
+    total: u64,
+    total_page: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TagsByUserRequest {
+    user_id: i32,
+}
+
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct SaveTagRequest {
@@ -44,14 +82,44 @@ struct RemoveQuery {
 async fn list(
     db: web::Data<DatabaseConnection>,
     auth: AuthUser,
+    payload: Option<Json<ListTagRequest>>,
 ) -> Result<HttpResponse, AppError> {
-    let rows = tag::Entity::find()
+    let payload = payload.map(|p| p.into_inner()).unwrap_or_default();
+    let mut query = tag::Entity::find()
         .filter(tag::Column::UserId.eq(auth.user_id))
-        .all(db.get_ref())
+        .order_by_desc(tag::Column::MemoCount)
+        .order_by_asc(tag::Column::Name);
+    if let Some(search) = payload.search.as_deref().map(str::trim).filter(|s| !s.is_empty()) {
+        query = query.filter(tag::Column::Name.starts_with(search));
+    }
+
+    // 未传分页参数时保持旧行为,返回全部标签
+    if payload.page.is_none() && payload.size.is_none() {
+        let rows = query
+            .all(db.get_ref())
+            .await
+            .map_err(|_| AppError::system_exception())?;
+        let list = rows.into_iter().map(to_dto).collect::<Vec<_>>();
+        return Ok(HttpResponse::Ok().json(ResponseDto::success(Some(list))));
+    }
+
+    let page = payload.page.unwrap_or(1).max(1);
+    let size = payload.size.unwrap_or(20).clamp(1, 200);
+    let paginator = query.paginate(db.get_ref(), size);
+    let counts = paginator
+        .num_items_and_pages()
         .await
         .map_err(|_| AppError::system_exception())?;
-    let list = rows.into_iter().map(to_dto).collect::<Vec<_>>();
-    Ok(HttpResponse::Ok().json(ResponseDto::success(Some(list))))
+    let rows = paginator
+        .fetch_page(page - 1)
+        .await
+        .map_err(|_| AppError::system_exception())?;
+    let response = ListTagResponse {
+        items: rows.into_iter().map(to_dto).collect(),
+        total: counts.number_of_items,
+        total_page: counts.number_of_pages,
+    };
+    Ok(HttpResponse::Ok().json(ResponseDto::success(Some(response))))
 }
 
 async fn top10(
@@ -61,13 +129,7 @@ async fn top10(
     let user_id = if let Some(auth) = auth.0 {
         auth.user_id
     } else {
-        let admin = user::Entity::find()
-            .filter(user::Column::Role.eq("ADMIN"))
-            .one(db.get_ref())
-            .await
-            .map_err(|_| AppError::system_exception())?
-            .ok_or_else(|| AppError::fail("管理员不存在"))?;
-        admin.id
+        auth::primary_admin_id(db.get_ref()).await?
     };
 
     let rows = tag::Entity::find()
@@ -82,6 +144,90 @@ async fn top10(
     Ok(HttpResponse::Ok().json(ResponseDto::success(Some(list))))
 }
 
+async fn cloud(
+    db: web::Data<DatabaseConnection>,
+    auth: OptionalAuthUser,
+) -> Result<HttpResponse, AppError> {
+    let user_id = if let Some(auth) = auth.0 {
+        auth.user_id
+    } else {
+        auth::primary_admin_id(db.get_ref()).await?
+    };
+
+    let rows = tag::Entity::find()
+        .filter(tag::Column::UserId.eq(user_id))
+        .filter(tag::Column::MemoCount.gt(0))
+        .order_by_desc(tag::Column::MemoCount)
+        .order_by_asc(tag::Column::Name)
+        .limit(TAG_CLOUD_LIMIT)
+        .all(db.get_ref())
+        .await
+        .map_err(|_| AppError::system_exception())?;
+
+    let counts = rows.iter().map(|r| r.memo_count.unwrap_or(0));
+    let max = counts.clone().max().unwrap_or(0);
+    let min = counts.min().unwrap_or(0);
+    let list = rows
+        .into_iter()
+        .map(|r| {
+            let count = r.memo_count.unwrap_or(0);
+            // 所有标签数量相同时统一按最大权重展示
+            let weight = if max == min {
+                1.0
+            } else {
+                (count - min) as f64 / (max - min) as f64
+            };
+            TagCloudDto { id: r.id, name: r.name, count, weight }
+        })
+        .collect::<Vec<_>>();
+    Ok(HttpResponse::Ok().json(ResponseDto::success(Some(list))))
+}
+
+/// Lists another user's tags with counts recomputed over the memos the caller can see, so
+/// tags that only appear on private memos stay hidden.
+async fn by_user(
+    db: web::Data<DatabaseConnection>,
+    auth: OptionalAuthUser,
+    payload: Json<TagsByUserRequest>,
+) -> Result<HttpResponse, AppError> {
+    let mut values: Vec<sea_orm::Value> = vec![payload.user_id.into()];
+    let visibility_sql = if let Some(auth) = auth.0.as_ref() {
+        values.push(auth.user_id.into());
+        super::memo::logged_in_visibility_sql(db.get_ref()).await?
+    } else {
+        "t.visibility = 'PUBLIC'"
+    };
+    let sql = format!(
+        "select t.tags from t_memo t where t.status = 'NORMAL' and t.user_id = ? and {} and t.tags is not null and t.tags != ''",
+        visibility_sql
+    );
+    let rows = query_all(db.get_ref(), &sql, values).await?;
+
+    let mut counts: HashMap<String, i32> = HashMap::new();
+    for row in rows {
+        let tags: String = row.try_get("", "tags").unwrap_or_default();
+        for name in tags.split(',').filter(|t| !t.is_empty()) {
+            *counts.entry(name.to_string()).or_insert(0) += 1;
+        }
+    }
+
+    let tags = tag::Entity::find()
+        .filter(tag::Column::UserId.eq(payload.user_id))
+        .all(db.get_ref())
+        .await
+        .map_err(|_| AppError::system_exception())?;
+    let mut list = tags
+        .into_iter()
+        .filter_map(|mut t| {
+            let count = counts.get(&t.name).copied()?;
+            t.memo_count = Some(count);
+            Some(to_dto(t))
+        })
+        .collect::<Vec<_>>();
+    list.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)));
+    Ok(HttpResponse::Ok().json(ResponseDto::success(Some(list))))
+}
+
 async fn remove(
     db: web::Data<DatabaseConnection>,
     auth: AuthUser,
@@ -99,10 +245,11 @@ async fn remove(
 
 async fn save(
     db: web::Data<DatabaseConnection>,
-    _auth: AuthUser,
-    payload: web::Json<SaveTagRequest>,
+    auth: AuthUser,
+    payload: Json<SaveTagRequest>,
 ) -> Result<HttpResponse, AppError> {
     let items = payload.list.clone().ok_or_else(|| AppError::param_error("items"))?;
+    let (user_id, is_admin) = (auth.user_id, auth.role.as_deref() == Some("ADMIN"));
 
     db.transaction::<_, (), AppError>(|txn| {
         let items = items.clone();
@@ -113,6 +260,9 @@ async fn save(
                     .await
                     .map_err(|_| AppError::system_exception())?
                     .ok_or_else(|| AppError::fail("tag不存在"))?;
+                if !is_admin && old.user_id != user_id {
+                    return Err(AppError::fail("不能操作其他人的记录"));
+                }
 
                 let active = tag::ActiveModel {
                     id: Set(item.id),
@@ -126,18 +276,30 @@ async fn save(
 
                 let memos = query_all(
                     txn,
-                    "select id,tags from t_memo where tags like ?",
-                    vec![format!("%{},", old.name).into()],
+                    "select id,tags,content from t_memo where user_id = ? and tags like ?",
+                    vec![old.user_id.into(), format!("%{},%", old.name).into()],
                 )
                 .await?;
 
                 for row in memos {
                     let memo_id: i32 = row.try_get("", "id").unwrap_or(0);
                     let tags: String = row.try_get("", "tags").unwrap_or_default();
-                    let new_tags = tags.replacen(&format!("{},", old.name), &format!("{},", item.name), 1);
+                    let content: String = row.try_get("", "content").unwrap_or_default();
+                    let new_tags = tags
+                        .split(',')
+                        .filter(|t| !t.is_empty())
+                        .map(|t| if t == old.name { item.name.as_str() } else { t })
+                        .map(|t| format!("{},", t))
+                        .collect::<String>();
+                    if new_tags == tags {
+                        continue;
+                    }
+                    // 正文里的旧标签也要改名,否则下次编辑时会被重新解析出来
+                    let new_content = super::memo::rename_tag_in_content(&content, &old.name, &item.name);
                     let memo_active = memo::ActiveModel {
                         id: Set(memo_id),
                         tags: Set(Some(new_tags)),
+                        content: Set(Some(new_content)),
                         updated: Set(Some(Utc::now())),
                         ..Default::default()
                     };
@@ -182,3 +344,49 @@ fn map_tx_error(err: TransactionError<AppError>) -> AppError {
         TransactionError::Transaction(app) => app,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{auth_user, TestEnv, ADMIN_ID};
+
+    #[actix_web::test]
+    async fn save_only_renames_own_tags_unless_admin() {
+        let env = TestEnv::new().await;
+        let user_id = ADMIN_ID + 1;
+        env.exec(
+            "insert into t_user (id, username, password_hash, role) values (?, 'bob', 'x', 'USER'), \
+             (?, 'eve', 'x', 'USER')",
+            vec![user_id.into(), (user_id + 1).into()],
+        )
+        .await;
+        env.exec(
+            "insert into t_tag (id, name, user_id, memo_count) values (1, '#rust', ?, 1)",
+            vec![user_id.into()],
+        )
+        .await;
+        env.exec(
+            "insert into t_memo (id, user_id, content, tags) values (1, ?, '#rust notes', '#rust,')",
+            vec![user_id.into()],
+        )
+        .await;
+        let db = web::Data::new(env.db.clone());
+        let rename = |auth: AuthUser, name: &str| {
+            let payload = SaveTagRequest { list: Some(vec![TagUpdateDto { id: 1, name: name.to_string() }]) };
+            save(db.clone(), auth, Json(payload))
+        };
+        let tag_name = || async { tag::Entity::find_by_id(1).one(&env.db).await.unwrap().unwrap().name };
+
+        let err = rename(auth_user(user_id + 1, "USER"), "#stolen").await.unwrap_err();
+        assert_eq!(err.msg(), "不能操作其他人的记录");
+        assert_eq!(tag_name().await, "#rust");
+
+        rename(auth_user(user_id, "USER"), "#rs").await.unwrap();
+        assert_eq!(tag_name().await, "#rs");
+
+        rename(auth_user(ADMIN_ID, "ADMIN"), "#rustlang").await.unwrap();
+        assert_eq!(tag_name().await, "#rustlang");
+        let memo = memo::Entity::find_by_id(1).one(&env.db).await.unwrap().unwrap();
+        assert_eq!(memo.tags.as_deref(), Some("#rustlang,"));
+    }
+}
@@ -1,10 +1,9 @@
 use actix_web::{web, HttpResponse};
-use chrono::{Duration, Utc};
-use jsonwebtoken::{encode, EncodingKey, Header};
-use sea_orm::{ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, Set};
+use chrono::{DateTime, SecondsFormat, Utc};
+use sea_orm::{ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QuerySelect, Set};
 use serde::Serialize;
 
-use crate::auth::AuthUser;
+use crate::auth::{self, AuthUser};
 use crate::config::AppConfig;
 use crate::entity::dev_token;
 use crate::error::AppError;
@@ -19,31 +18,55 @@ pub fn config(cfg: &mut web::ServiceConfig) {
 }
 
 #[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
 struct TokenDto {
     id: i32,
     name: String,
     token: String,
+    last_used: Option<String>,
 }
 
 #[derive(Serialize)]
 struct EmptyResponse {}
 
+/// `last_used` is read on its own and treated as unknown on error, so the token stays visible
+/// on a database that is missing the column.
+async fn last_used(db: &DatabaseConnection, id: i32) -> Option<String> {
+    dev_token::Entity::find_by_id(id)
+        .select_only()
+        .column(dev_token::Column::LastUsed)
+        .into_tuple::<Option<DateTime<Utc>>>()
+        .one(db)
+        .await
+        .ok()
+        .flatten()
+        .flatten()
+        .map(|t| t.to_rfc3339_opts(SecondsFormat::Millis, false))
+}
+
 async fn get_token(
     db: web::Data<DatabaseConnection>,
     auth: AuthUser,
 ) -> Result<HttpResponse, AppError> {
     let token = dev_token::Entity::find()
+        .select_only()
+        .columns([dev_token::Column::Id, dev_token::Column::Name, dev_token::Column::Token])
         .filter(dev_token::Column::Name.eq("default"))
         .filter(dev_token::Column::UserId.eq(auth.user_id))
+        .into_tuple::<(i32, String, String)>()
         .one(db.get_ref())
         .await
         .map_err(|_| AppError::system_exception())?;
 
-    let dto = token.map(|t| TokenDto {
-        id: t.id,
-        name: t.name,
-        token: t.token,
-    });
+    let dto = match token {
+        Some((id, name, token)) => Some(TokenDto {
+            id,
+            name,
+            token,
+            last_used: last_used(db.get_ref(), id).await,
+        }),
+        None => None,
+    };
 
     Ok(HttpResponse::Ok().json(ResponseDto::success(dto)))
 }
@@ -137,16 +160,11 @@ struct TokenClaims {
 }
 
 fn generate_token(config: &AppConfig, user_id: i32, device: &str) -> Result<String, AppError> {
-    let exp = (Utc::now() + Duration::days(365 * 100)).timestamp() as usize;
+    let exp = auth::token_exp(config);
     let claims = TokenClaims {
         login_id: user_id,
         device: device.to_string(),
         exp,
     };
-    encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(config.jwt_secret.as_bytes()),
-    )
-    .map_err(|_| AppError::system_exception())
+    auth::encode_jwt(config, &claims)
 }
@@ -1,16 +1,18 @@
 use actix_web::{web, HttpResponse};
-use bcrypt::{hash, verify};
 use chrono::{Duration, SecondsFormat, Utc};
-use jsonwebtoken::{encode, EncodingKey, Header};
-use log::error;
-use sea_orm::{ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait, QueryFilter, Set};
+use log::{error, info};
+use sea_orm::sea_query::Expr;
+use sea_orm::{ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder, Set, TransactionError, TransactionTrait};
 use serde::{Deserialize, Serialize};
 
-use crate::auth::{AuthUser, OptionalAuthUser};
+use crate::auth::{self, AuthUser, OptionalAuthUser};
 use crate::config::AppConfig;
-use crate::entity::user;
+use crate::entity::{invite_code, user};
 use crate::error::AppError;
+use crate::password;
+use crate::request::Json;
 use crate::response::ResponseDto;
+use crate::session;
 use crate::sys_config;
 
 pub fn config(cfg: &mut web::ServiceConfig) {
@@ -20,31 +22,67 @@ pub fn config(cfg: &mut web::ServiceConfig) {
     .service(web::resource("/update").route(web::post().to(update_user)))
     .service(web::resource("/current").route(web::post().to(current_user)))
     .service(web::resource("/{id:\\d+}").route(web::post().to(get_user)))
+    .service(web::resource("/profile/{id:\\d+}").route(web::get().to(public_profile)))
     .service(web::resource("/list").route(web::post().to(list_users)))
     .service(web::resource("/login").route(web::post().to(login)))
     .service(web::resource("/logout").route(web::post().to(logout)))
     .service(web::resource("/listNames").route(web::post().to(list_names)))
-    .service(web::resource("/statistics").route(web::post().to(statistics)));
+    .service(web::resource("/statistics").route(web::post().to(statistics)))
+    .service(web::resource("/notifications").route(web::post().to(notifications)))
+    .service(web::resource("/notifications/read").route(web::post().to(mark_notifications_read)))
+    .service(web::resource("/adminCreate").route(web::post().to(admin_create_user)))
+    .service(web::resource("/inviteCode").route(web::post().to(generate_invite_codes)))
+    .service(web::resource("/sessions").route(web::post().to(list_sessions)))
+    .service(web::resource("/sessions/revoke").route(web::post().to(revoke_session)))
+    .service(web::resource("/sessions/revokeOthers").route(web::post().to(revoke_other_sessions)));
 }
 
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct RegisterUserRequest {
+    #[serde(default, deserialize_with = "crate::request::trimmed")]
     username: Option<String>,
     password: Option<String>,
+    #[serde(default, deserialize_with = "crate::request::trimmed")]
     display_name: Option<String>,
+    #[serde(default, deserialize_with = "crate::request::trimmed")]
     email: Option<String>,
     bio: Option<String>,
+    invite_code: Option<String>,
 }
 
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AdminCreateUserRequest {
+    #[serde(default, deserialize_with = "crate::request::trimmed")]
+    username: Option<String>,
+    password: Option<String>,
+    #[serde(default, deserialize_with = "crate::request::trimmed")]
+    display_name: Option<String>,
+    #[serde(default, deserialize_with = "crate::request::trimmed")]
+    email: Option<String>,
+    #[serde(default, deserialize_with = "crate::request::trimmed")]
+    role: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+struct GenerateInviteCodeRequest {
+    count: Option<u32>,
+}
+
+const MAX_INVITE_CODES_PER_REQUEST: u32 = 50;
+
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct UpdateUserRequest {
+    #[serde(default, deserialize_with = "crate::request::trimmed_or_clear")]
     display_name: Option<String>,
+    #[serde(default, deserialize_with = "crate::request::trimmed_or_clear")]
     email: Option<String>,
     bio: Option<String>,
     avatar_url: Option<String>,
     password: Option<String>,
+    #[serde(default, deserialize_with = "crate::request::trimmed")]
     default_visibility: Option<String>,
     default_enable_comment: Option<String>,
 }
@@ -52,6 +90,7 @@ struct UpdateUserRequest {
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct LoginRequest {
+    #[serde(default, deserialize_with = "crate::request::trimmed")]
     username: Option<String>,
     password: Option<String>,
 }
@@ -83,6 +122,27 @@ struct UserDto {
     default_enable_comment: Option<String>,
 }
 
+/// Public author header; only counts public memos and never exposes email or settings.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PublicProfileDto {
+    id: i32,
+    display_name: Option<String>,
+    bio: Option<String>,
+    avatar_url: Option<String>,
+    created: Option<String>,
+    public_memo_count: i64,
+    tag_count: i64,
+}
+
+/// Mention candidate; the id disambiguates users sharing a display name.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct UserNameDto {
+    id: i32,
+    display_name: String,
+}
+
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
 struct MemoStatisticsDto {
@@ -93,6 +153,22 @@ struct MemoStatisticsDto {
     unread_mentioned: i64,
 }
 
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct NotificationSummaryDto {
+    unread_mentioned: i64,
+    unread_comments: i64,
+    pending_approvals: i64,
+    total: i64,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct MarkNotificationsReadRequest {
+    /// `mention` / `comment`; 为空时全部标记已读
+    category: Option<String>,
+}
+
 #[derive(Serialize)]
 struct EmptyResponse {}
 
@@ -101,40 +177,65 @@ struct Claims {
     #[serde(rename = "loginId")]
     login_id: i32,
     device: String,
+    jti: String,
     exp: usize,
 }
 
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SessionDto {
+    id: i32,
+    device: String,
+    created: Option<String>,
+    last_seen: Option<String>,
+    current: bool,
+}
+
+#[derive(Deserialize)]
+struct RevokeSessionRequest {
+    id: i32,
+}
+
 async fn register_user(
     db: web::Data<DatabaseConnection>,
-    payload: web::Json<RegisterUserRequest>,
+    config: web::Data<AppConfig>,
+    payload: Json<RegisterUserRequest>,
 ) -> Result<HttpResponse, AppError> {
     let username = payload.username.clone().unwrap_or_default();
     let password = payload.password.clone().unwrap_or_default();
-    if username.trim().is_empty() {
+    if username.is_empty() {
         return Err(AppError::param_error("username cannot be null"));
     }
     if password.trim().is_empty() {
         return Err(AppError::param_error("password cannot be null"));
     }
+    if is_reserved_username(db.get_ref(), &username).await? {
+        return Err(AppError::fail("该用户名已被保留"));
+    }
 
-    let open_register = sys_config::get_boolean(db.get_ref(), "OPEN_REGISTER")
-        .await
-        .map_err(|_| AppError::system_exception())?;
-    if !open_register {
+    let mode = register_mode(db.get_ref()).await?;
+    if mode == "closed" {
         return Err(AppError::fail("当前不允许注册"));
     }
+    let invite_code = payload
+        .invite_code
+        .as_deref()
+        .map(str::trim)
+        .unwrap_or_default()
+        .to_string();
+    if mode == "invite" && invite_code.is_empty() {
+        return Err(AppError::param_error("inviteCode"));
+    }
+    // 全局邀请码可重复使用,否则需消耗一个t_invite_code中的一次性邀请码
+    let shared_code = sys_config::get_string(db.get_ref(), "INVITE_CODE")
+        .await
+        .map_err(|_| AppError::system_exception())?
+        .unwrap_or_default();
+    let consume_code = mode == "invite" && (shared_code.is_empty() || shared_code != invite_code);
 
-    let display_name = if let Some(name) = &payload.display_name {
-        if !name.trim().is_empty() {
-            Some(name.clone())
-        } else {
-            Some(username.clone())
-        }
-    } else {
-        Some(username.clone())
-    };
+    let display_name = Some(payload.display_name.clone().unwrap_or_else(|| username.clone()));
 
-    let password_hash = hash(password, 10).map_err(|_| AppError::system_exception())?;
+    let password_hash = password::hash_password(&config, &password)?;
     let now = Utc::now();
 
     let user_model = user::ActiveModel {
@@ -148,21 +249,164 @@ async fn register_user(
         ..Default::default()
     };
 
-    if let Err(err) = user_model.insert(db.get_ref()).await {
+    db.transaction::<_, (), AppError>(|txn| {
+        Box::pin(async move {
+            let created = match user_model.insert(txn).await {
+                Ok(created) => created,
+                Err(err) => {
+                    let msg = err.to_string();
+                    if msg.contains("Duplicate") || msg.contains("UNIQUE") {
+                        return Err(AppError::fail("用户名或昵称已存在"));
+                    }
+                    return Err(AppError::system_exception());
+                }
+            };
+            if consume_code {
+                let consumed = invite_code::Entity::update_many()
+                    .col_expr(invite_code::Column::UsedBy, Expr::value(created.id))
+                    .col_expr(invite_code::Column::UsedAt, Expr::value(now))
+                    .filter(invite_code::Column::Code.eq(invite_code))
+                    .filter(invite_code::Column::UsedBy.is_null())
+                    .exec(txn)
+                    .await
+                    .map_err(|_| AppError::system_exception())?;
+                if consumed.rows_affected != 1 {
+                    return Err(AppError::fail("邀请码无效或已被使用"));
+                }
+            }
+            Ok(())
+        })
+    })
+    .await
+    .map_err(|err| match err {
+        TransactionError::Connection(_) => AppError::system_exception(),
+        TransactionError::Transaction(app) => app,
+    })?;
+
+    Ok(HttpResponse::Ok().json(ResponseDto::<EmptyResponse>::success(None)))
+}
+
+/// Resolves `REGISTER_MODE` (`open`/`invite`/`closed`), falling back to `OPEN_REGISTER` when unset.
+async fn register_mode(db: &DatabaseConnection) -> Result<String, AppError> {
+    let mode = sys_config::get_string(db, "REGISTER_MODE")
+        .await
+        .map_err(|_| AppError::system_exception())?
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    match mode.as_str() {
+        "open" | "invite" | "closed" => Ok(mode),
+        _ => {
+            let open_register = sys_config::get_boolean(db, "OPEN_REGISTER")
+                .await
+                .map_err(|_| AppError::system_exception())?;
+            Ok(if open_register { "open" } else { "closed" }.to_string())
+        }
+    }
+}
+
+/// Provisions an account regardless of `REGISTER_MODE`; reserved usernames are allowed here.
+async fn admin_create_user(
+    db: web::Data<DatabaseConnection>,
+    config: web::Data<AppConfig>,
+    auth: AuthUser,
+    payload: Json<AdminCreateUserRequest>,
+) -> Result<HttpResponse, AppError> {
+    if auth.role.as_deref() != Some("ADMIN") {
+        return Err(AppError::need_login());
+    }
+    let username = payload.username.clone().unwrap_or_default();
+    let password = payload.password.clone().unwrap_or_default();
+    if username.is_empty() {
+        return Err(AppError::param_error("username cannot be null"));
+    }
+    password::check_strength(&password)?;
+    let role = match payload.role.as_deref().map(str::to_ascii_uppercase) {
+        None => "USER".to_string(),
+        Some(role) if role == "USER" || role == "ADMIN" => role,
+        Some(_) => return Err(AppError::param_error("role")),
+    };
+
+    let now = Utc::now();
+    let user_model = user::ActiveModel {
+        display_name: Set(Some(payload.display_name.clone().unwrap_or_else(|| username.clone()))),
+        username: Set(username),
+        password_hash: Set(password::hash_password(&config, &password)?),
+        email: Set(payload.email.clone()),
+        role: Set(Some(role)),
+        created: Set(Some(now)),
+        updated: Set(Some(now)),
+        ..Default::default()
+    };
+    let created = user_model.insert(db.get_ref()).await.map_err(|err| {
         let msg = err.to_string();
         if msg.contains("Duplicate") || msg.contains("UNIQUE") {
-            return Err(AppError::fail("用户名或昵称已存在"));
+            AppError::fail("用户名或昵称已存在")
+        } else {
+            AppError::system_exception()
         }
-        return Err(AppError::system_exception());
+    })?;
+
+    Ok(HttpResponse::Ok().json(ResponseDto::success(Some(to_user_dto(created)))))
+}
+
+/// Built-in list used when `RESERVED_USERNAMES` is missing from t_sys_config.
+const DEFAULT_RESERVED_USERNAMES: &str = "admin,administrator,root,system,support,api,mblog,official,moderator";
+
+/// Case-insensitive match against the comma-separated `RESERVED_USERNAMES` config.
+async fn is_reserved_username(db: &DatabaseConnection, username: &str) -> Result<bool, AppError> {
+    let reserved = sys_config::get_string(db, "RESERVED_USERNAMES")
+        .await
+        .map_err(|_| AppError::system_exception())?
+        .unwrap_or_else(|| DEFAULT_RESERVED_USERNAMES.to_string());
+    Ok(reserved
+        .split(',')
+        .map(str::trim)
+        .any(|name| !name.is_empty() && name.eq_ignore_ascii_case(username)))
+}
+
+async fn generate_invite_codes(
+    db: web::Data<DatabaseConnection>,
+    auth: AuthUser,
+    payload: Option<Json<GenerateInviteCodeRequest>>,
+) -> Result<HttpResponse, AppError> {
+    if auth.role.as_deref() != Some("ADMIN") {
+        return Err(AppError::need_login());
     }
+    let count = payload
+        .and_then(|p| p.count)
+        .unwrap_or(1)
+        .clamp(1, MAX_INVITE_CODES_PER_REQUEST);
 
-    Ok(HttpResponse::Ok().json(ResponseDto::<EmptyResponse>::success(None)))
+    let now = Utc::now();
+    let mut codes = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let code = (0..12)
+            .map(|_| {
+                let idx = rand::random::<u8>() % 26;
+                (b'a' + idx) as char
+            })
+            .collect::<String>();
+        let active = invite_code::ActiveModel {
+            code: Set(code.clone()),
+            created_by: Set(auth.user_id),
+            created: Set(Some(now)),
+            ..Default::default()
+        };
+        active
+            .insert(db.get_ref())
+            .await
+            .map_err(|_| AppError::system_exception())?;
+        codes.push(code);
+    }
+    Ok(HttpResponse::Ok().json(ResponseDto::success(Some(codes))))
 }
 
 async fn update_user(
     db: web::Data<DatabaseConnection>,
+    config: web::Data<AppConfig>,
     auth: AuthUser,
-    payload: web::Json<UpdateUserRequest>,
+    payload: Json<UpdateUserRequest>,
 ) -> Result<HttpResponse, AppError> {
     let mut active = user::ActiveModel {
         id: Set(auth.user_id),
@@ -171,11 +415,12 @@ async fn update_user(
 
     active.updated = Set(Some(Utc::now()));
 
+    // 空白表示清空,存为NULL以免与display_name唯一索引冲突
     if let Some(v) = payload.display_name.clone() {
-        active.display_name = Set(Some(v));
+        active.display_name = Set(Some(v).filter(|v| !v.is_empty()));
     }
     if let Some(v) = payload.email.clone() {
-        active.email = Set(Some(v));
+        active.email = Set(Some(v).filter(|v| !v.is_empty()));
     }
     if let Some(v) = payload.bio.clone() {
         active.bio = Set(Some(v));
@@ -190,11 +435,11 @@ async fn update_user(
         active.default_enable_comment = Set(Some(v));
     }
 
-    if let Some(password) = payload.password.clone() {
-        if !password.trim().is_empty() {
-            let hashed = hash(password, 10).map_err(|_| AppError::system_exception())?;
-            active.password_hash = Set(hashed);
-        }
+    if let Some(password) = payload.password.clone()
+        && !password.trim().is_empty()
+    {
+        let hashed = password::hash_password(&config, &password)?;
+        active.password_hash = Set(hashed);
     }
 
     user::Entity::update(active)
@@ -219,6 +464,52 @@ async fn get_user(
     Ok(HttpResponse::Ok().json(ResponseDto::success(dto)))
 }
 
+async fn public_profile(
+    db: web::Data<DatabaseConnection>,
+    path: web::Path<i32>,
+) -> Result<HttpResponse, AppError> {
+    let Some(user) = user::Entity::find_by_id(*path)
+        .one(db.get_ref())
+        .await
+        .map_err(|_| AppError::system_exception())?
+    else {
+        return Ok(HttpResponse::Ok().json(ResponseDto::<PublicProfileDto>::success(None)));
+    };
+
+    let public_memo_count = count_by_sql(
+        db.get_ref(),
+        "SELECT COUNT(*) as cnt FROM t_memo WHERE user_id = ? and status = 'NORMAL' and visibility = 'PUBLIC'",
+        vec![user.id.into()],
+    )
+    .await?;
+
+    let stmt = sea_orm::Statement::from_sql_and_values(
+        db.get_database_backend(),
+        "SELECT tags FROM t_memo WHERE user_id = ? and status = 'NORMAL' and visibility = 'PUBLIC' and tags is not null and tags != ''",
+        vec![user.id.into()],
+    );
+    let rows = db
+        .query_all(stmt)
+        .await
+        .map_err(|_| AppError::system_exception())?;
+    let mut tags = std::collections::HashSet::new();
+    for row in rows {
+        let value: String = row.try_get("", "tags").unwrap_or_default();
+        tags.extend(value.split(',').filter(|t| !t.is_empty()).map(str::to_string));
+    }
+
+    let dto = PublicProfileDto {
+        id: user.id,
+        display_name: user.display_name,
+        bio: user.bio,
+        avatar_url: user.avatar_url,
+        created: user.created.map(to_rfc3339),
+        public_memo_count,
+        tag_count: tags.len() as i64,
+    };
+    Ok(HttpResponse::Ok().json(ResponseDto::success(Some(dto))))
+}
+
 async fn current_user(
     db: web::Data<DatabaseConnection>,
     auth: OptionalAuthUser,
@@ -232,14 +523,7 @@ async fn current_user(
                 AppError::system_exception()
             })?
     } else {
-        user::Entity::find()
-            .filter(user::Column::Role.eq("ADMIN"))
-            .one(db.get_ref())
-            .await
-            .map_err(|e| {
-                error!("current_user find admin failed: {}", e);
-                AppError::system_exception()
-            })?
+        auth::find_primary_admin(db.get_ref()).await?
     };
 
     let dto = user.map(to_user_dto);
@@ -265,11 +549,11 @@ async fn list_users(
 async fn login(
     db: web::Data<DatabaseConnection>,
     config: web::Data<AppConfig>,
-    payload: web::Json<LoginRequest>,
+    payload: Json<LoginRequest>,
 ) -> Result<HttpResponse, AppError> {
     let username = payload.username.clone().unwrap_or_default();
     let password = payload.password.clone().unwrap_or_default();
-    if username.trim().is_empty() {
+    if username.is_empty() {
         return Err(AppError::param_error("username cannot be null"));
     }
     if password.trim().is_empty() {
@@ -287,23 +571,36 @@ async fn login(
         None => return Err(AppError::fail("用户不存在")),
     };
 
-    let ok = verify(password, &user.password_hash).map_err(|_| AppError::system_exception())?;
+    let ok = password::verify_password(&password, &user.password_hash)?;
     if !ok {
         return Err(AppError::fail("密码不正确"));
     }
+    if password::needs_rehash(&config, &user.password_hash) {
+        // 登录成功时顺带把旧算法的哈希升级为当前配置的算法,失败不影响登录
+        match password::hash_password(&config, &password) {
+            Ok(hashed) => {
+                let active = user::ActiveModel {
+                    id: Set(user.id),
+                    password_hash: Set(hashed),
+                    ..Default::default()
+                };
+                if let Err(e) = active.update(db.get_ref()).await {
+                    error!("rehash password for user {} failed: {}", user.id, e);
+                }
+            }
+            Err(e) => error!("rehash password for user {} failed: {}", user.id, e),
+        }
+    }
 
-    let exp = (Utc::now() + Duration::days(365 * 100)).timestamp() as usize;
+    let exp = auth::token_exp(&config);
+    let jti = session::create(db.get_ref(), user.id, "WEB").await?;
     let claims = Claims {
         login_id: user.id,
         device: "WEB".to_string(),
+        jti,
         exp,
     };
-    let token = encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(config.jwt_secret.as_bytes()),
-    )
-    .map_err(|_| AppError::system_exception())?;
+    let token = auth::encode_jwt(&config, &claims)?;
 
     let response = LoginResponse {
         token,
@@ -317,7 +614,52 @@ async fn login(
     Ok(HttpResponse::Ok().json(ResponseDto::success(Some(response))))
 }
 
-async fn logout(_auth: AuthUser) -> Result<HttpResponse, AppError> {
+async fn list_sessions(
+    db: web::Data<DatabaseConnection>,
+    auth: AuthUser,
+) -> Result<HttpResponse, AppError> {
+    let rows = session::list_active(db.get_ref(), auth.user_id).await?;
+    let list = rows
+        .into_iter()
+        .map(|s| SessionDto {
+            id: s.id,
+            device: s.device,
+            created: s.created.map(|t| t.to_rfc3339_opts(SecondsFormat::Millis, false)),
+            last_seen: s.last_seen.map(|t| t.to_rfc3339_opts(SecondsFormat::Millis, false)),
+            current: auth.session_id == Some(s.id),
+        })
+        .collect::<Vec<_>>();
+    Ok(HttpResponse::Ok().json(ResponseDto::success(Some(list))))
+}
+
+async fn revoke_session(
+    db: web::Data<DatabaseConnection>,
+    auth: AuthUser,
+    payload: Json<RevokeSessionRequest>,
+) -> Result<HttpResponse, AppError> {
+    session::revoke_many(db.get_ref(), auth.user_id, vec![payload.id]).await?;
+    Ok(HttpResponse::Ok().json(ResponseDto::<EmptyResponse>::success(None)))
+}
+
+/// Signs out every other session of the caller, keeping the one making the request.
+async fn revoke_other_sessions(
+    db: web::Data<DatabaseConnection>,
+    auth: AuthUser,
+) -> Result<HttpResponse, AppError> {
+    let count = session::revoke_all(db.get_ref(), auth.user_id, auth.session_id).await?;
+    info!("user {} revoked {} other sessions", auth.user_id, count);
+    Ok(HttpResponse::Ok().json(ResponseDto::<EmptyResponse>::success(None)))
+}
+
+/// Revokes the session behind the caller's token so it stops working immediately. Tokens
+/// signed before sessions existed carry no `jti` and can only expire.
+async fn logout(
+    db: web::Data<DatabaseConnection>,
+    auth: AuthUser,
+) -> Result<HttpResponse, AppError> {
+    if let Some(id) = auth.session_id {
+        session::revoke_many(db.get_ref(), auth.user_id, vec![id]).await?;
+    }
     Ok(HttpResponse::Ok().json(ResponseDto::<EmptyResponse>::success(None)))
 }
 
@@ -326,12 +668,19 @@ async fn list_names(
     _auth: AuthUser,
 ) -> Result<HttpResponse, AppError> {
     let users = user::Entity::find()
+        .filter(user::Column::DisplayName.is_not_null())
+        .order_by_asc(user::Column::DisplayName)
+        .order_by_asc(user::Column::Id)
         .all(db.get_ref())
         .await
         .map_err(|_| AppError::system_exception())?;
-    let names: Vec<String> = users
+    let names: Vec<UserNameDto> = users
         .into_iter()
-        .filter_map(|u| u.display_name)
+        .filter_map(|u| {
+            u.display_name
+                .filter(|name| !name.is_empty())
+                .map(|display_name| UserNameDto { id: u.id, display_name })
+        })
         .collect();
     Ok(HttpResponse::Ok().json(ResponseDto::success(Some(names))))
 }
@@ -356,6 +705,50 @@ async fn statistics(
     Ok(HttpResponse::Ok().json(ResponseDto::success(Some(dto))))
 }
 
+async fn notifications(
+    db: web::Data<DatabaseConnection>,
+    auth: AuthUser,
+) -> Result<HttpResponse, AppError> {
+    let unread_mentioned = count_unread_mentioned(db.get_ref(), auth.user_id).await?;
+    let unread_comments = count_unread_comments(db.get_ref(), auth.user_id).await?;
+    let pending_approvals = count_pending_approvals(db.get_ref(), &auth).await?;
+
+    let dto = NotificationSummaryDto {
+        unread_mentioned,
+        unread_comments,
+        pending_approvals,
+        total: unread_mentioned + unread_comments + pending_approvals,
+    };
+    Ok(HttpResponse::Ok().json(ResponseDto::success(Some(dto))))
+}
+
+async fn mark_notifications_read(
+    db: web::Data<DatabaseConnection>,
+    auth: AuthUser,
+    payload: Option<Json<MarkNotificationsReadRequest>>,
+) -> Result<HttpResponse, AppError> {
+    let payload = payload.map(|p| p.into_inner()).unwrap_or_default();
+    let now = Utc::now();
+    let mut active = user::ActiveModel {
+        id: Set(auth.user_id),
+        ..Default::default()
+    };
+    match payload.category.as_deref().unwrap_or("") {
+        "" => {
+            active.last_clicked_mentioned = Set(Some(now));
+            active.last_checked_comments = Set(Some(now));
+        }
+        "mention" => active.last_clicked_mentioned = Set(Some(now)),
+        "comment" => active.last_checked_comments = Set(Some(now)),
+        _ => return Err(AppError::param_error("category")),
+    }
+    active
+        .update(db.get_ref())
+        .await
+        .map_err(|_| AppError::system_exception())?;
+    Ok(HttpResponse::Ok().json(ResponseDto::<()>::success(None)))
+}
+
 fn to_user_dto(model: user::Model) -> UserDto {
     UserDto {
         id: model.id,
@@ -403,10 +796,25 @@ async fn count_commented(db: &DatabaseConnection, user_id: i32) -> Result<i64, A
     .await
 }
 
+/// Same rows as the mentions list: memos the user may read that mention them in the body or
+/// in a comment.
 async fn count_mentioned(db: &DatabaseConnection, user_id: i32) -> Result<i64, AppError> {
     let pattern = format!("%#{},%", user_id);
-    let sql = "SELECT COUNT(1) as cnt FROM (SELECT DISTINCT memo_id FROM t_comment WHERE mentioned_user_id LIKE ?) x";
-    count_by_sql(db, sql, vec![sea_orm::Value::String(Some(Box::new(pattern)))]).await
+    let sql = format!(
+        "SELECT COUNT(1) as cnt FROM t_memo t WHERE t.status = 'NORMAL' AND {} \
+        AND (t.mentioned_user_id LIKE ? OR t.id IN (SELECT memo_id FROM t_comment WHERE mentioned_user_id LIKE ?))",
+        super::memo::logged_in_visibility_sql(db).await?
+    );
+    count_by_sql(
+        db,
+        &sql,
+        vec![
+            sea_orm::Value::Int(Some(user_id)),
+            sea_orm::Value::String(Some(Box::new(pattern.clone()))),
+            sea_orm::Value::String(Some(Box::new(pattern))),
+        ],
+    )
+    .await
 }
 
 async fn count_unread_mentioned(db: &DatabaseConnection, user_id: i32) -> Result<i64, AppError> {
@@ -419,11 +827,22 @@ async fn count_unread_mentioned(db: &DatabaseConnection, user_id: i32) -> Result
         .unwrap_or_else(|| Utc::now() - Duration::days(365 * 100));
 
     let pattern = format!("%#{},%", user_id);
-    let sql = "SELECT COUNT(*) as cnt FROM t_comment WHERE mentioned_user_id LIKE ? AND created >= ?";
+    // 评论和memo正文中的提及一起计入未读数,可见范围与提及列表一致
+    let visibility = super::memo::logged_in_visibility_sql(db).await?;
+    let sql = format!(
+        "SELECT (SELECT COUNT(*) FROM t_comment c JOIN t_memo t ON c.memo_id = t.id \
+        WHERE t.status = 'NORMAL' AND {0} AND c.mentioned_user_id LIKE ? AND c.created >= ?) \
+        + (SELECT COUNT(*) FROM t_memo t WHERE t.status = 'NORMAL' AND {0} AND t.mentioned_user_id LIKE ? AND t.created >= ?) as cnt",
+        visibility
+    );
     count_by_sql(
         db,
-        sql,
+        &sql,
         vec![
+            sea_orm::Value::Int(Some(user_id)),
+            sea_orm::Value::String(Some(Box::new(pattern.clone()))),
+            sea_orm::Value::ChronoDateTimeUtc(Some(Box::new(last_clicked))),
+            sea_orm::Value::Int(Some(user_id)),
             sea_orm::Value::String(Some(Box::new(pattern))),
             sea_orm::Value::ChronoDateTimeUtc(Some(Box::new(last_clicked))),
         ],
@@ -431,6 +850,47 @@ async fn count_unread_mentioned(db: &DatabaseConnection, user_id: i32) -> Result
     .await
 }
 
+async fn count_unread_comments(db: &DatabaseConnection, user_id: i32) -> Result<i64, AppError> {
+    let user = user::Entity::find_by_id(user_id)
+        .one(db)
+        .await
+        .map_err(|_| AppError::system_exception())?;
+    let last_checked = user
+        .and_then(|u| u.last_checked_comments)
+        .unwrap_or_else(|| Utc::now() - Duration::days(365 * 100));
+
+    let sql = "SELECT COUNT(*) as cnt FROM t_comment c JOIN t_memo m ON c.memo_id = m.id \
+               WHERE m.user_id = ? AND c.user_id <> ? AND c.created >= ?";
+    count_by_sql(
+        db,
+        sql,
+        vec![
+            sea_orm::Value::Int(Some(user_id)),
+            sea_orm::Value::Int(Some(user_id)),
+            sea_orm::Value::ChronoDateTimeUtc(Some(Box::new(last_checked))),
+        ],
+    )
+    .await
+}
+
+async fn count_pending_approvals(db: &DatabaseConnection, auth: &AuthUser) -> Result<i64, AppError> {
+    if auth.role.as_deref() == Some("ADMIN") {
+        return count_by_sql(
+            db,
+            "SELECT COUNT(*) as cnt FROM t_comment WHERE user_id < 0 AND approved = 0",
+            vec![],
+        )
+        .await;
+    }
+    count_by_sql(
+        db,
+        "SELECT COUNT(*) as cnt FROM t_comment c JOIN t_memo m ON c.memo_id = m.id \
+         WHERE m.user_id = ? AND c.user_id < 0 AND c.approved = 0",
+        vec![sea_orm::Value::Int(Some(auth.user_id))],
+    )
+    .await
+}
+
 async fn count_by_sql(
     db: &DatabaseConnection,
     sql: &str,
@@ -447,3 +907,105 @@ async fn count_by_sql(
     let cnt: i64 = row.try_get("", "cnt").unwrap_or(0);
     Ok(cnt)
 }
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{test as web_test, App};
+
+    use super::*;
+    use crate::entity::memo;
+    use crate::test_util::{TestEnv, ADMIN_ID};
+
+    #[test]
+    fn register_blank_fields_are_none() {
+        let req: RegisterUserRequest =
+            serde_json::from_str(r#"{"username":" bob ","displayName":"  ","email":"  "}"#).unwrap();
+        assert_eq!(req.username.as_deref(), Some("bob"));
+        assert_eq!(req.display_name, None);
+        assert_eq!(req.email, None);
+    }
+
+    #[test]
+    fn update_blank_fields_clear() {
+        let req: UpdateUserRequest = serde_json::from_str(r#"{"displayName":"  ","email":""}"#).unwrap();
+        assert_eq!(req.display_name.as_deref(), Some(""));
+        assert_eq!(req.email.as_deref(), Some(""));
+
+        let req: UpdateUserRequest = serde_json::from_str(r#"{"displayName":" Jerry "}"#).unwrap();
+        assert_eq!(req.display_name.as_deref(), Some("Jerry"));
+        assert_eq!(req.email, None);
+    }
+
+    #[actix_web::test]
+    async fn mention_counts_follow_protect_scope() {
+        let env = TestEnv::new().await;
+        let mentioned = ADMIN_ID + 1;
+        let tag = format!("#{},", mentioned);
+        for visibility in ["PUBLIC", "PROTECT", "PRIVATE"] {
+            env.exec(
+                "insert into t_memo (user_id, content, visibility, mentioned_user_id) values (?, 'hi', ?, ?)",
+                vec![ADMIN_ID.into(), visibility.into(), tag.clone().into()],
+            )
+            .await;
+        }
+
+        assert_eq!(count_mentioned(&env.db, mentioned).await.unwrap(), 2);
+        assert_eq!(count_unread_mentioned(&env.db, mentioned).await.unwrap(), 2);
+
+        env.set_config("PROTECT_SCOPE", "author_only").await;
+        assert_eq!(count_mentioned(&env.db, mentioned).await.unwrap(), 1);
+        assert_eq!(count_unread_mentioned(&env.db, mentioned).await.unwrap(), 1);
+    }
+
+    #[actix_web::test]
+    async fn memo_body_mention_reaches_the_mentioned_user() {
+        let env = TestEnv::new().await;
+        let bob = ADMIN_ID + 1;
+        env.exec(
+            "insert into t_user (id, username, password_hash, display_name, role) values (?, 'bob', 'x', 'bob', 'USER')",
+            vec![bob.into()],
+        )
+        .await;
+        let app = web_test::init_service(
+            App::new()
+                .app_data(web::Data::new(env.config.clone()))
+                .app_data(web::Data::new(env.db.clone()))
+                .service(web::scope("/api/memo").configure(crate::routes::memo::config)),
+        )
+        .await;
+
+        let req = web_test::TestRequest::post()
+            .uri("/api/memo/save")
+            .insert_header(("token", env.token(ADMIN_ID).await))
+            .set_json(serde_json::json!({"content": "lunch with @bob today", "visibility": "PUBLIC"}))
+            .to_request();
+        let res: serde_json::Value = web_test::call_and_read_body_json(&app, req).await;
+        let memo_id = res["data"].as_i64().unwrap() as i32;
+        let saved = memo::Entity::find_by_id(memo_id).one(&env.db).await.unwrap().unwrap();
+        assert_eq!(saved.mentioned_user_id, Some(format!("#{},", bob)));
+
+        assert_eq!(count_unread_mentioned(&env.db, bob).await.unwrap(), 1);
+
+        let req = web_test::TestRequest::post()
+            .uri("/api/memo/mentions")
+            .insert_header(("token", env.token(bob).await))
+            .to_request();
+        let res: serde_json::Value = web_test::call_and_read_body_json(&app, req).await;
+        assert_eq!(res["data"]["total"], 1);
+        assert_eq!(res["data"]["items"][0]["id"], memo_id);
+    }
+
+    #[actix_web::test]
+    async fn current_user_reports_database_errors() {
+        let env = TestEnv::new().await;
+        let db = web::Data::new(env.db.clone());
+        let res = current_user(db.clone(), OptionalAuthUser(None)).await.unwrap();
+        let body = actix_web::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["data"]["id"], ADMIN_ID);
+
+        env.exec("drop table t_user", vec![]).await;
+        let err = current_user(db, OptionalAuthUser(None)).await.unwrap_err();
+        assert_eq!(err.code(), 99);
+    }
+}
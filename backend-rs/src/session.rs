@@ -0,0 +1,112 @@
+use chrono::{Duration, Utc};
+use log::info;
+use sea_orm::sea_query::Expr;
+use sea_orm::{ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder, Set};
+
+use crate::entity::session;
+use crate::error::AppError;
+use crate::sys_config as sys_config_store;
+
+/// `last_seen` is only rewritten when older than this, so authenticated requests don't all
+/// turn into writes.
+const LAST_SEEN_RESOLUTION_SECS: i64 = 60;
+
+/// Records a new login session and returns its `jti`. When the user already holds
+/// `MAX_SESSIONS_PER_USER` active sessions (0 = unlimited) the oldest ones are revoked first.
+pub async fn create(db: &DatabaseConnection, user_id: i32, device: &str) -> Result<String, AppError> {
+    let max = sys_config_store::get_i64(db, "MAX_SESSIONS_PER_USER")
+        .await
+        .map_err(|_| AppError::system_exception())?
+        .unwrap_or(0);
+    if max > 0 {
+        let active = session::Entity::find()
+            .filter(session::Column::UserId.eq(user_id))
+            .filter(session::Column::Revoked.eq(0))
+            .order_by_asc(session::Column::Created)
+            .order_by_asc(session::Column::Id)
+            .all(db)
+            .await
+            .map_err(|_| AppError::system_exception())?;
+        let excess = (active.len() as i64 - max + 1).max(0) as usize;
+        let ids = active.iter().take(excess).map(|s| s.id).collect::<Vec<_>>();
+        if !ids.is_empty() {
+            revoke_many(db, user_id, ids.clone()).await?;
+            info!("revoked sessions {:?} of user {} over MAX_SESSIONS_PER_USER", ids, user_id);
+        }
+    }
+
+    let jti = (0..32)
+        .map(|_| format!("{:x}", rand::random::<u8>() % 16))
+        .collect::<String>();
+    let now = Utc::now();
+    let row = session::ActiveModel {
+        jti: Set(jti.clone()),
+        user_id: Set(user_id),
+        device: Set(device.to_string()),
+        revoked: Set(0),
+        created: Set(Some(now)),
+        last_seen: Set(Some(now)),
+        ..Default::default()
+    };
+    row.insert(db).await.map_err(|_| AppError::system_exception())?;
+    Ok(jti)
+}
+
+/// Returns the session id for an active `jti`, or `None` if it is unknown or revoked.
+pub async fn validate(db: &DatabaseConnection, user_id: i32, jti: &str) -> Result<Option<i32>, AppError> {
+    let row = session::Entity::find()
+        .filter(session::Column::Jti.eq(jti))
+        .filter(session::Column::UserId.eq(user_id))
+        .filter(session::Column::Revoked.eq(0))
+        .one(db)
+        .await
+        .map_err(|_| AppError::system_exception())?;
+    let Some(row) = row else {
+        return Ok(None);
+    };
+    let now = Utc::now();
+    if row.last_seen.is_none_or(|t| now - t > Duration::seconds(LAST_SEEN_RESOLUTION_SECS)) {
+        let active = session::ActiveModel {
+            id: Set(row.id),
+            last_seen: Set(Some(now)),
+            ..Default::default()
+        };
+        active.update(db).await.map_err(|_| AppError::system_exception())?;
+    }
+    Ok(Some(row.id))
+}
+
+pub async fn list_active(db: &DatabaseConnection, user_id: i32) -> Result<Vec<session::Model>, AppError> {
+    session::Entity::find()
+        .filter(session::Column::UserId.eq(user_id))
+        .filter(session::Column::Revoked.eq(0))
+        .order_by_desc(session::Column::LastSeen)
+        .all(db)
+        .await
+        .map_err(|_| AppError::system_exception())
+}
+
+/// Revokes the given sessions, limited to those owned by `user_id`.
+pub async fn revoke_many(db: &DatabaseConnection, user_id: i32, ids: Vec<i32>) -> Result<u64, AppError> {
+    let result = session::Entity::update_many()
+        .col_expr(session::Column::Revoked, Expr::value(1))
+        .filter(session::Column::UserId.eq(user_id))
+        .filter(session::Column::Id.is_in(ids))
+        .exec(db)
+        .await
+        .map_err(|_| AppError::system_exception())?;
+    Ok(result.rows_affected)
+}
+
+/// Revokes every active session of `user_id` except `keep`, signing the user out everywhere else.
+pub async fn revoke_all(db: &DatabaseConnection, user_id: i32, keep: Option<i32>) -> Result<u64, AppError> {
+    let mut update = session::Entity::update_many()
+        .col_expr(session::Column::Revoked, Expr::value(1))
+        .filter(session::Column::UserId.eq(user_id))
+        .filter(session::Column::Revoked.eq(0));
+    if let Some(keep) = keep {
+        update = update.filter(session::Column::Id.ne(keep));
+    }
+    let result = update.exec(db).await.map_err(|_| AppError::system_exception())?;
+    Ok(result.rows_affected)
+}
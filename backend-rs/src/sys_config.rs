@@ -7,10 +7,10 @@ pub async fn get_string(db: &DatabaseConnection, key: &str) -> Result<Option<Str
         .one(db)
         .await?;
     Ok(config.map(|c| {
-        if let Some(value) = c.value {
-            if !value.is_empty() {
-                return value;
-            }
+        if let Some(value) = c.value
+            && !value.is_empty()
+        {
+            return value;
         }
         c.default_value.unwrap_or_default()
     }))
@@ -21,18 +21,37 @@ pub async fn get_boolean(db: &DatabaseConnection, key: &str) -> Result<bool, sea
     Ok(value.unwrap_or_default().to_lowercase() == "true")
 }
 
-#[allow(dead_code)]
+/// Reads a numeric config, returning `None` when it is unset or not a number.
+pub async fn get_i64(db: &DatabaseConnection, key: &str) -> Result<Option<i64>, sea_orm::DbErr> {
+    let value = get_string(db, key).await?;
+    Ok(value.and_then(|v| v.trim().parse::<i64>().ok()))
+}
+
 pub async fn get_cors_domain_list(db: &DatabaseConnection) -> Result<Option<String>, sea_orm::DbErr> {
     let config = sys_config::Entity::find()
         .filter(sys_config::Column::Key.eq("CORS_DOMAIN_LIST"))
         .one(db)
         .await?;
     Ok(config.map(|c| {
-        if let Some(value) = c.value {
-            if !value.is_empty() {
-                return value;
-            }
+        if let Some(value) = c.value
+            && !value.is_empty()
+        {
+            return value;
         }
         c.default_value.unwrap_or_default()
     }))
 }
+
+/// `PROTECT_SCOPE`: `logged_in` (default) lets every logged-in user read PROTECT memos;
+/// `author_only` limits them to their author. `followers` is accepted but, with no follow
+/// relation to check yet, behaves like `author_only`.
+pub async fn protect_readable_by_logged_in(db: &DatabaseConnection) -> Result<bool, sea_orm::DbErr> {
+    let scope = get_string(db, "PROTECT_SCOPE").await?.unwrap_or_default();
+    Ok(!matches!(scope.trim(), "author_only" | "followers"))
+}
+
+/// `MAX_PAGE_OFFSET`: pages starting past this many rows come back empty instead of running a
+/// deep `OFFSET` scan. Defaults to 10000 when unset; 0 or less disables the cap.
+pub async fn get_max_page_offset(db: &DatabaseConnection) -> Result<i64, sea_orm::DbErr> {
+    Ok(get_i64(db, "MAX_PAGE_OFFSET").await?.unwrap_or(10000))
+}
@@ -0,0 +1,84 @@
+//! Helpers for handler tests: a throwaway SQLite database with the full changelog applied and
+//! login tokens for its seeded users.
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use sea_orm::{ConnectionTrait, DatabaseConnection, Statement};
+
+use crate::auth::{self, AuthUser};
+use crate::config::AppConfig;
+use crate::db::connect_db;
+use crate::session;
+
+/// User seeded by the changelog (`admin` / `ADMIN`).
+pub const ADMIN_ID: i32 = 1;
+
+/// An authenticated WEB user, as the extractor would produce for a token without a session.
+pub fn auth_user(user_id: i32, role: &str) -> AuthUser {
+    AuthUser {
+        user_id,
+        role: Some(role.to_string()),
+        device: "WEB".to_string(),
+        session_id: None,
+        dev_token_id: None,
+    }
+}
+
+static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+/// A fresh database and upload directory under the system temp dir, removed on drop.
+pub struct TestEnv {
+    pub db: DatabaseConnection,
+    pub config: AppConfig,
+    pub dir: PathBuf,
+}
+
+impl Drop for TestEnv {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+impl TestEnv {
+    pub async fn new() -> Self {
+        let dir = std::env::temp_dir().join(format!(
+            "mblog-test-{}-{}",
+            std::process::id(),
+            NEXT_DIR.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut config = AppConfig::from_env();
+        config.database_url = None;
+        config.sqlite_path = dir.join("data.sqlite").display().to_string();
+        config.upload_storage_path = dir.join("upload").display().to_string();
+        let db = connect_db(&config).await;
+        TestEnv { db, config, dir }
+    }
+
+    /// A WEB login token for `user_id`, backed by a session row like a real login.
+    pub async fn token(&self, user_id: i32) -> String {
+        let jti = session::create(&self.db, user_id, "WEB").await.unwrap();
+        let claims = serde_json::json!({
+            "loginId": user_id,
+            "device": "WEB",
+            "jti": jti,
+            "exp": auth::token_exp(&self.config),
+        });
+        auth::encode_jwt(&self.config, &claims).unwrap()
+    }
+
+    pub async fn set_config(&self, key: &str, value: &str) {
+        self.exec(
+            "update t_sys_config set value = ? where `key` = ?",
+            vec![value.into(), key.into()],
+        )
+        .await;
+    }
+
+    pub async fn exec(&self, sql: &str, values: Vec<sea_orm::Value>) {
+        let stmt = Statement::from_sql_and_values(self.db.get_database_backend(), sql, values);
+        self.db.execute(stmt).await.unwrap();
+    }
+}
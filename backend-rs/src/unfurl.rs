@@ -0,0 +1,236 @@
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::time::Duration;
+
+use log::{debug, warn};
+use reqwest::Url;
+use reqwest::header::{CONTENT_TYPE, LOCATION};
+use reqwest::redirect::Policy;
+use sea_orm::{ConnectionTrait, DatabaseConnection, Statement};
+
+use crate::error::AppError;
+use crate::sys_config as sys_config_store;
+
+const FETCH_TIMEOUT_SECS: u64 = 5;
+/// Only the head of the page is needed for `<title>` and `og:image`.
+const MAX_BODY_BYTES: usize = 512 * 1024;
+const MAX_REDIRECTS: usize = 3;
+const TITLE_MAX_CHARS: usize = 200;
+
+/// Fetches the title and `og:image` of a memo's `source` URL in the background when
+/// `UNFURL_SOURCE` is on, storing them as `source_title` / `source_image`. Failures are only
+/// logged; the save that triggered it never waits on this.
+pub fn spawn(db: DatabaseConnection, memo_id: i32, source: String) {
+    actix_web::rt::spawn(async move {
+        if let Err(err) = unfurl(&db, memo_id, &source).await {
+            warn!("unfurl source for memo {} failed: {}", memo_id, err);
+        }
+    });
+}
+
+async fn unfurl(db: &DatabaseConnection, memo_id: i32, source: &str) -> Result<(), AppError> {
+    let enabled = sys_config_store::get_boolean(db, "UNFURL_SOURCE")
+        .await
+        .map_err(|_| AppError::system_exception())?;
+    let Ok(url) = Url::parse(source.trim()) else {
+        return Ok(());
+    };
+    if !enabled || !matches!(url.scheme(), "http" | "https") {
+        return Ok(());
+    }
+    let hosts = HostRules {
+        allowed: load_host_list(db, "UNFURL_ALLOWED_HOSTS").await?,
+        denied: load_host_list(db, "UNFURL_DENIED_HOSTS").await?,
+    };
+
+    let html = fetch_html(&hosts, url.clone()).await?;
+    let title = extract_title(&html);
+    let image = extract_og_image(&html).and_then(|img| url.join(&img).ok()).and_then(|img| {
+        matches!(img.scheme(), "http" | "https").then(|| img.to_string())
+    });
+    debug!("unfurled memo {} source: title={:?} image={:?}", memo_id, title, image);
+
+    // source 在抓取期间被改掉时不写入,避免旧结果覆盖
+    let stmt = Statement::from_sql_and_values(
+        db.get_database_backend(),
+        "update t_memo set source_title = ?, source_image = ? where id = ? and source = ?",
+        vec![title.into(), image.into(), memo_id.into(), source.into()],
+    );
+    db.execute(stmt).await.map_err(|_| AppError::system_exception())?;
+    Ok(())
+}
+
+async fn load_host_list(db: &DatabaseConnection, key: &str) -> Result<Vec<String>, AppError> {
+    Ok(sys_config_store::get_string(db, key)
+        .await
+        .map_err(|_| AppError::system_exception())?
+        .unwrap_or_default()
+        .split(',')
+        .map(|h| h.trim().trim_start_matches('.').to_ascii_lowercase())
+        .filter(|h| !h.is_empty())
+        .collect())
+}
+
+/// Comma separated host lists; an entry also matches its subdomains. An empty allowlist
+/// permits every host that is not denied.
+struct HostRules {
+    allowed: Vec<String>,
+    denied: Vec<String>,
+}
+
+impl HostRules {
+    fn permits(&self, host: &str) -> bool {
+        let matches = |rule: &String| host == rule || host.ends_with(&format!(".{}", rule));
+        host != "localhost"
+            && !self.denied.iter().any(matches)
+            && (self.allowed.is_empty() || self.allowed.iter().any(matches))
+    }
+}
+
+/// Follows up to [`MAX_REDIRECTS`] redirects by hand so every hop goes through the host rules,
+/// and pins each request to the address that was checked so DNS cannot swap in an internal
+/// one between the check and the connect.
+async fn fetch_html(hosts: &HostRules, mut url: Url) -> Result<String, AppError> {
+    for _ in 0..=MAX_REDIRECTS {
+        let host = url.host_str().unwrap_or_default().to_ascii_lowercase();
+        if !matches!(url.scheme(), "http" | "https") || !hosts.permits(&host) {
+            return Err(AppError::fail(format!("不允许抓取的地址 {}", host)));
+        }
+        let addr = resolve_public(&url).await?;
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(FETCH_TIMEOUT_SECS))
+            .redirect(Policy::none())
+            .resolve(&host, addr)
+            .build()
+            .map_err(|_| AppError::system_exception())?;
+        let mut resp = client
+            .get(url.clone())
+            .send()
+            .await
+            .map_err(|e| AppError::fail(format!("抓取失败: {}", e)))?;
+
+        if resp.status().is_redirection() {
+            let location = resp
+                .headers()
+                .get(LOCATION)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| url.join(v).ok())
+                .ok_or_else(|| AppError::fail("重定向地址无效"))?;
+            url = location;
+            continue;
+        }
+        if !resp.status().is_success() {
+            return Err(AppError::fail(format!("抓取返回状态码 {}", resp.status())));
+        }
+        let is_html = resp
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.to_ascii_lowercase().contains("html"));
+        if !is_html {
+            return Err(AppError::fail("不是html页面"));
+        }
+
+        let mut body = Vec::new();
+        while let Some(chunk) = resp
+            .chunk()
+            .await
+            .map_err(|e| AppError::fail(format!("抓取失败: {}", e)))?
+        {
+            body.extend_from_slice(&chunk);
+            if body.len() >= MAX_BODY_BYTES {
+                body.truncate(MAX_BODY_BYTES);
+                break;
+            }
+        }
+        return Ok(String::from_utf8_lossy(&body).into_owned());
+    }
+    Err(AppError::fail("重定向次数过多"))
+}
+
+/// Resolves the URL's host and rejects it unless every address is publicly routable.
+async fn resolve_public(url: &Url) -> Result<SocketAddr, AppError> {
+    let host = url.host_str().unwrap_or_default().to_string();
+    let port = url.port_or_known_default().unwrap_or(80);
+    let addrs = actix_web::web::block(move || {
+        (host.trim_start_matches('[').trim_end_matches(']'), port)
+            .to_socket_addrs()
+            .map(|addrs| addrs.collect::<Vec<_>>())
+    })
+    .await
+    .map_err(|_| AppError::system_exception())?
+    .map_err(|e| AppError::fail(format!("域名解析失败: {}", e)))?;
+    if addrs.is_empty() || addrs.iter().any(|a| !is_public_ip(a.ip())) {
+        return Err(AppError::fail("不允许抓取内网地址"));
+    }
+    Ok(addrs[0])
+}
+
+fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => {
+            let [a, b, ..] = v4.octets();
+            !(v4.is_private()
+                || v4.is_loopback()
+                || v4.is_link_local()
+                || v4.is_unspecified()
+                || v4.is_broadcast()
+                || v4.is_documentation()
+                || a == 0
+                || (a == 100 && (64..128).contains(&b)))
+        }
+        IpAddr::V6(v6) => {
+            if let Some(v4) = v6.to_ipv4_mapped() {
+                return is_public_ip(IpAddr::V4(v4));
+            }
+            let first = v6.segments()[0];
+            !(v6.is_loopback()
+                || v6.is_unspecified()
+                || (first & 0xfe00) == 0xfc00
+                || (first & 0xffc0) == 0xfe80)
+        }
+    }
+}
+
+fn extract_title(html: &str) -> Option<String> {
+    let lower = html.to_ascii_lowercase();
+    let open = lower.find("<title")?;
+    let start = open + lower[open..].find('>')? + 1;
+    let end = start + lower[start..].find("</title")?;
+    let title = decode_entities(html[start..end].split_whitespace().collect::<Vec<_>>().join(" ").as_str());
+    let title = title.chars().take(TITLE_MAX_CHARS).collect::<String>();
+    (!title.is_empty()).then_some(title)
+}
+
+/// `content` of the first `<meta property="og:image">` (or `name="og:image"`).
+fn extract_og_image(html: &str) -> Option<String> {
+    let lower = html.to_ascii_lowercase();
+    let mut pos = 0;
+    while let Some(found) = lower[pos..].find("<meta") {
+        let start = pos + found;
+        let end = start + lower[start..].find('>').unwrap_or(lower.len() - start);
+        let tag = &lower[start..end];
+        if tag.contains("\"og:image\"") || tag.contains("'og:image'") {
+            let value = attr_value(&html[start..end], tag, "content")?;
+            return Some(decode_entities(value.trim())).filter(|v| !v.is_empty());
+        }
+        pos = end;
+    }
+    None
+}
+
+/// Quoted value of `name=` in `tag`; `lower` is the ASCII-lowercased tag used for the lookup.
+fn attr_value<'a>(tag: &'a str, lower: &str, name: &str) -> Option<&'a str> {
+    let at = lower.find(&format!("{}=", name))? + name.len() + 1;
+    let quote = tag[at..].chars().next().filter(|c| *c == '"' || *c == '\'')?;
+    let end = at + 1 + tag[at + 1..].find(quote)?;
+    Some(&tag[at + 1..end])
+}
+
+fn decode_entities(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#x27;", "'")
+        .replace("&amp;", "&")
+}
@@ -0,0 +1,105 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use log::{error, info};
+use md5::{Digest, Md5};
+use sea_orm::{ConnectionTrait, DatabaseConnection, Statement, TransactionTrait};
+
+use crate::error::AppError;
+use crate::sys_config as sys_config_store;
+
+const AGGREGATE_INTERVAL_SECS: u64 = 60;
+/// Repeat views of a memo by the same viewer within this window are not recorded again, so
+/// refreshes count once.
+const VIEW_DEDUPE_SECS: i64 = 30 * 60;
+/// Highest `t_memo_view.id` already folded into `t_memo.view_count`.
+const CURSOR_KEY: &str = "VIEW_EVENT_CURSOR";
+
+/// `VIEW_COUNT_MODE` is `inline` (default, bump `view_count` on each view) or `event`
+/// (append to `t_memo_view` and let the worker aggregate).
+pub async fn event_mode(db: &DatabaseConnection) -> Result<bool, AppError> {
+    let mode = sys_config_store::get_string(db, "VIEW_COUNT_MODE")
+        .await
+        .map_err(|_| AppError::system_exception())?
+        .unwrap_or_default();
+    Ok(mode.trim().eq_ignore_ascii_case("event"))
+}
+
+/// Hashes whatever identifies the viewer so raw IPs are never stored.
+pub fn viewer_hash(identity: &str) -> String {
+    let mut hasher = Md5::new();
+    hasher.update(identity.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+/// Appends a view event unless the same viewer already has one for this memo within
+/// [`VIEW_DEDUPE_SECS`].
+pub async fn record(db: &DatabaseConnection, memo_id: i32, viewer_hash: String) -> Result<(), AppError> {
+    let now = Utc::now();
+    let since = now - chrono::Duration::seconds(VIEW_DEDUPE_SECS);
+    let stmt = Statement::from_sql_and_values(
+        db.get_database_backend(),
+        "insert into t_memo_view (memo_id, viewer_hash, created) select ?, ?, ? \
+         where not exists (select 1 from t_memo_view where memo_id = ? and viewer_hash = ? and created > ?)",
+        vec![
+            memo_id.into(),
+            viewer_hash.clone().into(),
+            now.into(),
+            memo_id.into(),
+            viewer_hash.into(),
+            since.into(),
+        ],
+    );
+    db.execute(stmt).await.map_err(|e| {
+        error!("memo view record failed: {}", e);
+        AppError::system_exception()
+    })?;
+    Ok(())
+}
+
+pub fn spawn_worker(db: DatabaseConnection) {
+    actix_web::rt::spawn(async move {
+        let mut interval = actix_web::rt::time::interval(Duration::from_secs(AGGREGATE_INTERVAL_SECS));
+        loop {
+            interval.tick().await;
+            if let Err(e) = aggregate(&db).await {
+                error!("memo view aggregation failed: {}", e);
+            }
+        }
+    });
+}
+
+/// Adds the events recorded since the last run to `t_memo.view_count` and advances the cursor
+/// in the same transaction, so every event is counted exactly once.
+async fn aggregate(db: &DatabaseConnection) -> Result<(), sea_orm::DbErr> {
+    let backend = db.get_database_backend();
+    let cursor = sys_config_store::get_i64(db, CURSOR_KEY).await?.unwrap_or(0);
+    let row = db
+        .query_one(Statement::from_string(backend, "select max(id) as max_id from t_memo_view"))
+        .await?;
+    let max_id = row
+        .and_then(|r| r.try_get::<Option<i64>>("", "max_id").ok().flatten())
+        .unwrap_or(0);
+    if max_id <= cursor {
+        return Ok(());
+    }
+
+    let txn = db.begin().await?;
+    txn.execute(Statement::from_sql_and_values(
+        backend,
+        "update t_memo set view_count = coalesce(view_count, 0) + \
+         (select count(1) from t_memo_view v where v.memo_id = t_memo.id and v.id > ? and v.id <= ?) \
+         where id in (select memo_id from t_memo_view where id > ? and id <= ?)",
+        vec![cursor.into(), max_id.into(), cursor.into(), max_id.into()],
+    ))
+    .await?;
+    txn.execute(Statement::from_sql_and_values(
+        backend,
+        "update t_sys_config set value = ? where `key` = ?",
+        vec![max_id.to_string().into(), CURSOR_KEY.into()],
+    ))
+    .await?;
+    txn.commit().await?;
+    info!("memo views aggregated up to event {}", max_id);
+    Ok(())
+}
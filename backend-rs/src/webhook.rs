@@ -0,0 +1,351 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use log::{error, info, warn};
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait, QueryFilter,
+    QueryOrder, QuerySelect, Set,
+};
+use serde::Serialize;
+
+use crate::config::AppConfig;
+use crate::entity::{memo, resource, user, webhook_outbox};
+use crate::error::AppError;
+use crate::render;
+use crate::sys_config as sys_config_store;
+
+const STATUS_PENDING: &str = "PENDING";
+const STATUS_DELIVERED: &str = "DELIVERED";
+const STATUS_FAILED: &str = "FAILED";
+
+static DELIVERING: AtomicBool = AtomicBool::new(false);
+
+/// A receiver that accepts the connection but never answers must not hold a delivery (and the
+/// outbox run behind it) forever.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Records a pending delivery for `memo_id`; call it inside the transaction that writes the memo.
+pub async fn enqueue<C: ConnectionTrait>(db: &C, memo_id: i32) -> Result<(), AppError> {
+    let now = Utc::now();
+    let row = webhook_outbox::ActiveModel {
+        memo_id: Set(memo_id),
+        status: Set(STATUS_PENDING.to_string()),
+        attempts: Set(0),
+        created: Set(Some(now)),
+        updated: Set(Some(now)),
+        ..Default::default()
+    };
+    row.insert(db).await.map_err(|e| {
+        error!("webhook enqueue failed: {}", e);
+        AppError::system_exception()
+    })?;
+    Ok(())
+}
+
+pub fn spawn_worker(db: DatabaseConnection, config: AppConfig) {
+    actix_web::rt::spawn(async move {
+        let mut interval =
+            actix_web::rt::time::interval(Duration::from_secs(config.webhook_poll_seconds.max(1)));
+        loop {
+            interval.tick().await;
+            deliver_pending(&db, config.webhook_max_attempts).await;
+        }
+    });
+}
+
+pub fn deliver_pending_async(db: DatabaseConnection, max_attempts: i32) {
+    actix_web::rt::spawn(async move {
+        deliver_pending(&db, max_attempts).await;
+    });
+}
+
+async fn deliver_pending(db: &DatabaseConnection, max_attempts: i32) {
+    if DELIVERING.swap(true, Ordering::AcqRel) {
+        return;
+    }
+    let rows = webhook_outbox::Entity::find()
+        .filter(webhook_outbox::Column::Status.eq(STATUS_PENDING))
+        .order_by_asc(webhook_outbox::Column::Id)
+        .limit(50)
+        .all(db)
+        .await;
+    match rows {
+        Ok(rows) => {
+            for row in rows {
+                deliver_one(db, row, max_attempts).await;
+            }
+        }
+        Err(e) => error!("webhook outbox query failed: {}", e),
+    }
+    DELIVERING.store(false, Ordering::Release);
+}
+
+async fn deliver_one(db: &DatabaseConnection, row: webhook_outbox::Model, max_attempts: i32) {
+    let attempts = row.attempts + 1;
+    let mut active = webhook_outbox::ActiveModel {
+        id: Set(row.id),
+        attempts: Set(attempts),
+        updated: Set(Some(Utc::now())),
+        ..Default::default()
+    };
+    match notify_webhook(db, row.memo_id).await {
+        Ok(()) => {
+            active.status = Set(STATUS_DELIVERED.to_string());
+            active.last_error = Set(None);
+        }
+        Err(err) => {
+            warn!("webhook delivery for memo {} failed (attempt {}): {}", row.memo_id, attempts, err);
+            if attempts >= max_attempts {
+                active.status = Set(STATUS_FAILED.to_string());
+                info!("webhook delivery for memo {} gave up after {} attempts", row.memo_id, attempts);
+            }
+            active.last_error = Set(Some(err.to_string()));
+        }
+    }
+    if let Err(e) = active.update(db).await {
+        error!("webhook outbox update failed: {}", e);
+    }
+}
+
+async fn notify_webhook(db: &DatabaseConnection, memo_id: i32) -> Result<(), AppError> {
+    let url = sys_config_store::get_string(db, "WEB_HOOK_URL")
+        .await
+        .map_err(|_| AppError::system_exception())?
+        .unwrap_or_default();
+    let token = sys_config_store::get_string(db, "WEB_HOOK_TOKEN")
+        .await
+        .map_err(|_| AppError::system_exception())?
+        .unwrap_or_default();
+
+    let memo_item = memo::Entity::find_by_id(memo_id)
+        .one(db)
+        .await
+        .map_err(|_| AppError::system_exception())?;
+    let Some(memo_item) = memo_item else {
+        return Ok(());
+    };
+    if memo_item.visibility.as_deref() != Some("PUBLIC") || url.is_empty() {
+        return Ok(());
+    }
+
+    let user_model = user::Entity::find_by_id(memo_item.user_id)
+        .one(db)
+        .await
+        .map_err(|_| AppError::system_exception())?
+        .ok_or_else(|| AppError::fail("用户不存在"))?;
+
+    let resources = resource::Entity::find()
+        .filter(resource::Column::MemoId.eq(memo_id))
+        .all(db)
+        .await
+        .map_err(|_| AppError::system_exception())?;
+
+    let content_format = sys_config_store::get_string(db, "WEB_HOOK_CONTENT_FORMAT")
+        .await
+        .map_err(|_| AppError::system_exception())?
+        .unwrap_or_default();
+
+    let backend_url = sys_config_store::get_string(db, "DOMAIN")
+        .await
+        .map_err(|_| AppError::system_exception())?
+        .unwrap_or_default();
+
+    let content = match memo_item.content.as_deref().filter(|c| !c.trim().is_empty()) {
+        Some(c) => Some(render::format_content(c, &content_format, memo_item.content_format.as_deref())),
+        None => resources
+            .first()
+            .map(|r| render::resource_placeholder(&r.file_type, &r.file_name)),
+    };
+
+    let resource_urls = resources
+        .into_iter()
+        .map(|r| format!("{}/api/resource/{}", backend_url, r.public_id))
+        .collect::<Vec<_>>();
+
+    #[derive(Serialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Payload {
+        content: Option<String>,
+        tags: Option<String>,
+        created: i64,
+        author_name: Option<String>,
+        resources: Vec<String>,
+    }
+
+    let payload = Payload {
+        content,
+        tags: memo_item.tags.clone(),
+        created: memo_item.created.map(to_millis).unwrap_or(0),
+        author_name: user_model.display_name.clone(),
+        resources: resource_urls,
+    };
+
+    post_webhook(&url, &token, &payload).await
+}
+
+/// Alerts the admin webhook that an anonymous comment is waiting for approval. Runs in the
+/// background when `NOTIFY_ON_PENDING_COMMENT` is on; failures are only logged.
+pub fn notify_pending_comment_async(db: DatabaseConnection, memo_id: i32, author_name: String, content: String) {
+    actix_web::rt::spawn(async move {
+        if let Err(err) = notify_pending_comment(&db, memo_id, author_name, content).await {
+            warn!("pending comment notification for memo {} failed: {}", memo_id, err);
+        }
+    });
+}
+
+async fn notify_pending_comment(
+    db: &DatabaseConnection,
+    memo_id: i32,
+    author_name: String,
+    content: String,
+) -> Result<(), AppError> {
+    let enabled = sys_config_store::get_boolean(db, "NOTIFY_ON_PENDING_COMMENT")
+        .await
+        .map_err(|_| AppError::system_exception())?;
+    let url = sys_config_store::get_string(db, "WEB_HOOK_URL")
+        .await
+        .map_err(|_| AppError::system_exception())?
+        .unwrap_or_default();
+    if !enabled || url.is_empty() {
+        return Ok(());
+    }
+    let token = sys_config_store::get_string(db, "WEB_HOOK_TOKEN")
+        .await
+        .map_err(|_| AppError::system_exception())?
+        .unwrap_or_default();
+    let domain = sys_config_store::get_string(db, "DOMAIN")
+        .await
+        .map_err(|_| AppError::system_exception())?
+        .unwrap_or_default();
+
+    #[derive(Serialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Payload {
+        event: &'static str,
+        memo_id: i32,
+        memo_link: String,
+        author_name: String,
+        preview: String,
+    }
+
+    let payload = Payload {
+        event: "PENDING_COMMENT",
+        memo_id,
+        memo_link: format!("{}/memo/{}", domain, memo_id),
+        author_name,
+        preview: content.chars().take(100).collect(),
+    };
+    post_webhook(&url, &token, &payload).await
+}
+
+/// Result of [`send_test`]: the HTTP status when the endpoint answered, otherwise the error.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TestOutcome {
+    pub url: String,
+    pub success: bool,
+    pub status: Option<u16>,
+    pub error: Option<String>,
+    pub duration_ms: i64,
+}
+
+/// Posts a sample memo payload marked `test: true` to `WEB_HOOK_URL` with the usual token header.
+pub async fn send_test(db: &DatabaseConnection) -> Result<TestOutcome, AppError> {
+    let url = sys_config_store::get_string(db, "WEB_HOOK_URL")
+        .await
+        .map_err(|_| AppError::system_exception())?
+        .unwrap_or_default();
+    if url.is_empty() {
+        return Err(AppError::fail("未配置WEB_HOOK_URL"));
+    }
+    let token = sys_config_store::get_string(db, "WEB_HOOK_TOKEN")
+        .await
+        .map_err(|_| AppError::system_exception())?
+        .unwrap_or_default();
+
+    #[derive(Serialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Payload {
+        test: bool,
+        content: String,
+        tags: String,
+        created: i64,
+        author_name: String,
+        resources: Vec<String>,
+    }
+
+    let started = Utc::now();
+    let payload = Payload {
+        test: true,
+        content: "这是一条测试消息".to_string(),
+        tags: "#test,".to_string(),
+        created: to_millis(started),
+        author_name: "mblog".to_string(),
+        resources: Vec::new(),
+    };
+    let (status, error) = match send(&url, &token, &payload, REQUEST_TIMEOUT).await {
+        Ok(status) if status.is_success() => (Some(status.as_u16()), None),
+        Ok(status) => (Some(status.as_u16()), Some(format!("webhook返回状态码 {}", status))),
+        Err(e) => (None, Some(format!("webhook请求失败: {}", e))),
+    };
+    Ok(TestOutcome {
+        url,
+        success: error.is_none(),
+        status,
+        error,
+        duration_ms: (Utc::now() - started).num_milliseconds(),
+    })
+}
+
+pub async fn post_webhook<T: Serialize>(url: &str, token: &str, payload: &T) -> Result<(), AppError> {
+    let status = send(url, token, payload, REQUEST_TIMEOUT)
+        .await
+        .map_err(|e| AppError::fail(format!("webhook请求失败: {}", e)))?;
+    if !status.is_success() {
+        return Err(AppError::fail(format!("webhook返回状态码 {}", status)));
+    }
+    Ok(())
+}
+
+/// Posts `payload` to `url`, giving up once `timeout` passes without a complete response.
+async fn send<T: Serialize>(
+    url: &str,
+    token: &str,
+    payload: &T,
+    timeout: Duration,
+) -> Result<reqwest::StatusCode, reqwest::Error> {
+    let client = reqwest::Client::builder()
+        .connect_timeout(CONNECT_TIMEOUT)
+        .timeout(timeout)
+        .build()?;
+    let mut req = client.post(url).json(payload);
+    if !token.is_empty() {
+        req = req.header("token", token);
+    }
+    Ok(req.send().await?.status())
+}
+
+fn to_millis(dt: DateTime<Utc>) -> i64 {
+    dt.timestamp_millis()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn send_gives_up_on_a_silent_receiver() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+
+        let started = std::time::Instant::now();
+        let err = send(&url, "", &serde_json::json!({"ping": true}), Duration::from_millis(300))
+            .await
+            .unwrap_err();
+        assert!(err.is_timeout(), "{err}");
+        assert!(started.elapsed() < Duration::from_secs(5));
+        drop(listener);
+    }
+}